@@ -0,0 +1,128 @@
+// Times the export and the imports of an auction with 100k wood pieces, none of them may
+// hold the file in memory, so the most heap each step allocates is printed too.
+// Run with `cargo bench --bench export_import`.
+use rusqlite::Connection;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use licitacija_lib::export::write_export;
+use licitacija_lib::import::{import_export_file, preview_from_json};
+use licitacija_lib::merge::{merge_from_json, ConflictPolicy};
+use licitacija_lib::restore::open_expected_database;
+use licitacija_lib::subset::{export_subset_to_json, import_subset_from_json, SubsetOwner};
+
+const PIECES: i64 = 100_000;
+
+fn fill_auction(conn: &Connection) -> Result<(), Box<dyn Error>> {
+    conn.execute_batch(
+        "INSERT INTO sellers (id, seller_name, ident) VALUES (1, 'Seller', 'S1');
+        INSERT INTO buyers (id, buyer_name, ident) VALUES (1, 'Buyer', 'B1');",
+    )?;
+    conn.execute(
+        "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < ?1)
+        INSERT INTO wood_pieces
+            (sequence_no, plate_no, length, width, seller_id, tree_species_id, min_price)
+        SELECT i, 'P' || i, 4, 50, 1, 1 + i % 20, 100 FROM n;",
+        [PIECES],
+    )?;
+    conn.execute(
+        "INSERT INTO wood_piece_offers (offered_price, wood_piece_id, buyer_id)
+        SELECT 150, id, 1 FROM wood_pieces;",
+        [],
+    )?;
+
+    Ok(())
+}
+
+// Counts the heap in use and its peak, the memory SQLite allocates itself is not included
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK_ALLOCATED.fetch_max(allocated, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn timed<T>(name: &str, f: impl FnOnce() -> Result<T, Box<dyn Error>>) -> T {
+    let allocated = ALLOCATED.load(Ordering::Relaxed);
+    PEAK_ALLOCATED.store(allocated, Ordering::Relaxed);
+    let start = Instant::now();
+    let result = f().unwrap_or_else(|e| panic!("{} failed: {}", name, e));
+    println!(
+        "{:<24} {:>8.2} s {:>8.1} MB peak heap",
+        name,
+        start.elapsed().as_secs_f64(),
+        (PEAK_ALLOCATED.load(Ordering::Relaxed) - allocated) as f64 / 1e6
+    );
+
+    result
+}
+
+fn count_pieces(conn: &Connection) -> i64 {
+    conn.query_row("SELECT COUNT(*) FROM wood_pieces;", [], |row| row.get(0))
+        .unwrap()
+}
+
+fn main() {
+    let dir = std::env::temp_dir();
+    let export_path: PathBuf = dir.join(format!("licitacija-bench-{}.json", std::process::id()));
+    let subset_path: PathBuf = dir.join(format!(
+        "licitacija-bench-{}-subset.json",
+        std::process::id()
+    ));
+    let export = export_path.to_str().unwrap();
+    let subset = subset_path.to_str().unwrap();
+
+    let conn = open_expected_database().unwrap();
+    conn.pragma_update(None, "foreign_keys", "ON").unwrap();
+    timed("fill", || fill_auction(&conn));
+
+    timed("export", || {
+        write_export(&conn, File::create(export)?, |_, _| {})
+    });
+    println!(
+        "{:<24} {:>8.1} MB",
+        "file size",
+        fs::metadata(export).unwrap().len() as f64 / 1e6
+    );
+
+    timed("import", || import_export_file(&conn, export, |_| Ok(())));
+    assert_eq!(count_pieces(&conn), PIECES);
+
+    let preview = timed("preview", || preview_from_json(&conn, export));
+    assert!(preview.problems.is_empty());
+
+    let summary = timed("merge", || {
+        merge_from_json(&conn, export, ConflictPolicy::TakeIncoming)
+    });
+    assert!(summary.tables.iter().all(|table| table.inserted == 0));
+
+    timed("subset export", || {
+        export_subset_to_json(&conn, subset, SubsetOwner::Seller(1))
+    });
+    timed("subset import", || import_subset_from_json(&conn, subset));
+    assert_eq!(count_pieces(&conn), 2 * PIECES);
+
+    let _ = fs::remove_file(export);
+    let _ = fs::remove_file(subset);
+}
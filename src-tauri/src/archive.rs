@@ -0,0 +1,479 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::export::{new_envelope, to_hex, write_export};
+use crate::import::import_export_file;
+use crate::migrations::get_schema_version;
+use crate::shared::get_connection;
+use crate::snapshot::write_snapshot;
+use crate::undo::with_group;
+
+// A .licitacija archive is a zip holding manifest.json, data.json (an export without the
+// image data) and the images as files
+pub const ARCHIVE_FORMAT_VERSION: u32 = 1;
+
+const MANIFEST_PATH: &str = "manifest.json";
+const DATA_PATH: &str = "data.json";
+const IMAGES_DIR: &str = "images";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchiveFile {
+    pub path: String,
+    pub size: u64,
+    // Hex encoded SHA-256 of the file
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchiveImage {
+    pub image_key: String,
+    pub path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    pub format_version: u32,
+    pub schema_version: Option<i64>,
+    pub app_version: String,
+    // Unix timestamp (seconds) of when the archive was made
+    pub created_at: u64,
+    // Every file of the archive except the manifest
+    pub files: Vec<ArchiveFile>,
+    // Which image file holds the data of which row of `images`
+    pub images: Vec<ArchiveImage>,
+}
+
+// Counts and hashes everything written through it
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        HashingWriter {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    fn into_file(self, path: &str) -> ArchiveFile {
+        ArchiveFile {
+            path: path.to_string(),
+            size: self.size,
+            sha256: to_hex(&self.hasher.finalize()),
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn get_extension(mime_type: Option<&str>) -> &'static str {
+    match mime_type {
+        Some("image/png") => "png",
+        Some("image/jpeg") => "jpg",
+        Some("image/webp") => "webp",
+        _ => "bin",
+    }
+}
+
+fn add_file(
+    zip: &mut ZipWriter<File>,
+    path: &str,
+    options: SimpleFileOptions,
+    write: impl FnOnce(&mut HashingWriter<&mut ZipWriter<File>>) -> Result<(), Box<dyn Error>>,
+) -> Result<ArchiveFile, Box<dyn Error>> {
+    zip.start_file(path, options)?;
+    let mut writer = HashingWriter::new(zip);
+    write(&mut writer)?;
+
+    Ok(writer.into_file(path))
+}
+
+// Everything is read in one transaction, so the images and data.json are of the same moment
+fn write_zip(conn: &Connection, zip_file: File) -> Result<ArchiveManifest, Box<dyn Error>> {
+    let tx = conn.unchecked_transaction()?;
+    let envelope = new_envelope(&tx)?;
+    let mut zip = ZipWriter::new(zip_file);
+    // Images are compressed already
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut files = vec![];
+    let mut images = vec![];
+    {
+        let mut stmt = tx.prepare(
+            "SELECT id, image_key, mime_type, data_base64 FROM images
+            WHERE data_base64 IS NOT NULL ORDER BY id;",
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let image_key: String = row.get("image_key")?;
+            let mime_type: Option<String> = row.get("mime_type")?;
+            let data = BASE64
+                .decode(row.get::<_, String>("data_base64")?.trim())
+                .map_err(|e| format!("Image {} is not valid base64: {}", image_key, e))?;
+
+            let path = format!(
+                "{}/{}.{}",
+                IMAGES_DIR,
+                row.get::<_, i64>("id")?,
+                get_extension(mime_type.as_deref())
+            );
+            files.push(add_file(&mut zip, &path, stored, |writer| {
+                Ok(writer.write_all(&data)?)
+            })?);
+            images.push(ArchiveImage { image_key, path });
+        }
+    }
+
+    // The image data is in the files above
+    files.push(add_file(&mut zip, DATA_PATH, deflated, |writer| {
+        write_export(&tx, writer, |table, row| {
+            if table == "images" {
+                row.insert("data_base64".to_string(), Value::Null);
+            }
+        })
+    })?);
+
+    let manifest = ArchiveManifest {
+        format_version: ARCHIVE_FORMAT_VERSION,
+        schema_version: envelope.schema_version,
+        app_version: envelope.app_version,
+        created_at: envelope.created_at,
+        files,
+        images,
+    };
+    zip.start_file(MANIFEST_PATH, deflated)?;
+    serde_json::to_writer_pretty(&mut zip, &manifest)?;
+    zip.finish()?.sync_all()?;
+
+    Ok(manifest)
+}
+
+// The archive is written next to `archive_path` and moved there once it is complete, so a
+// failed export never leaves a broken archive or replaces a good one
+fn write_archive(conn: &Connection, archive_path: &str) -> Result<ArchiveManifest, Box<dyn Error>> {
+    let part_path = format!("{}.part", archive_path);
+    let result = File::create(&part_path)
+        .map_err(|e| e.into())
+        .and_then(|file| write_zip(conn, file))
+        .and_then(|manifest| {
+            fs::rename(&part_path, archive_path)?;
+            Ok(manifest)
+        });
+    if result.is_err() {
+        let _ = fs::remove_file(&part_path);
+    }
+
+    result
+}
+
+// Checks every file against the manifest, copying data.json to `data_file` on the way
+fn verify_archive(
+    zip: &mut ZipArchive<File>,
+    manifest: &ArchiveManifest,
+    data_file: &File,
+) -> Result<(), Box<dyn Error>> {
+    if !manifest.files.iter().any(|file| file.path == DATA_PATH) {
+        return Err(format!("Archive has no {}", DATA_PATH).into());
+    }
+    for image in &manifest.images {
+        if !manifest.files.iter().any(|file| file.path == image.path) {
+            return Err(format!("Image file {} is not listed in the manifest", image.path).into());
+        }
+    }
+
+    for file in &manifest.files {
+        let mut entry = zip
+            .by_name(&file.path)
+            .map_err(|e| format!("Archive is missing {}: {}", file.path, e))?;
+
+        let copy: Box<dyn Write + '_> = if file.path == DATA_PATH {
+            Box::new(data_file)
+        } else {
+            Box::new(io::sink())
+        };
+        let mut writer = HashingWriter::new(copy);
+        // The zip checks its own CRC while reading
+        io::copy(&mut entry, &mut writer)
+            .map_err(|e| format!("File {} is damaged: {}", file.path, e))?;
+        writer.flush()?;
+
+        let found = writer.into_file(&file.path);
+        if found.size != file.size || found.sha256 != file.sha256 {
+            return Err(format!("File {} is damaged, checksum does not match", file.path).into());
+        }
+    }
+
+    Ok(())
+}
+
+fn read_archive(conn: &Connection, archive_path: &str) -> Result<ArchiveManifest, Box<dyn Error>> {
+    let mut zip = ZipArchive::new(File::open(archive_path)?)?;
+    let manifest: ArchiveManifest = serde_json::from_reader(zip.by_name(MANIFEST_PATH)?)?;
+
+    if manifest.format_version > ARCHIVE_FORMAT_VERSION {
+        return Err(format!(
+            "Archive format {} was made by a newer version of the app (app {})",
+            manifest.format_version, manifest.app_version
+        )
+        .into());
+    }
+    if manifest
+        .schema_version
+        .is_some_and(|version| version > get_schema_version())
+    {
+        return Err(format!(
+            "Archive was made by a newer version of the app (app {})",
+            manifest.app_version
+        )
+        .into());
+    }
+
+    // The import reads data.json more than once, so it is extracted instead of read from the
+    // zip. The file is created with a name of its own and removed when it is dropped.
+    let data_file = tempfile::Builder::new()
+        .prefix("licitacija-")
+        .suffix(".json")
+        .tempfile()?;
+    verify_archive(&mut zip, &manifest, data_file.as_file())?;
+    let data_path = data_file.path().to_str().ok_or("Invalid temporary path")?;
+
+    import_export_file(conn, data_path, |tx| {
+        for image in &manifest.images {
+            let mut data = vec![];
+            zip.by_name(&image.path)?.read_to_end(&mut data)?;
+
+            let updated = tx.execute(
+                "UPDATE images SET data_base64 = ?1 WHERE image_key = ?2;",
+                [BASE64.encode(&data), image.image_key.clone()],
+            )?;
+            if updated == 0 {
+                return Err(format!("Image {} is not in {}", image.image_key, DATA_PATH).into());
+            }
+        }
+
+        Ok(())
+    })?;
+
+    Ok(manifest)
+}
+
+#[tauri::command]
+pub fn export_archive(
+    app_handle: tauri::AppHandle,
+    file_path: String,
+) -> Result<ArchiveManifest, String> {
+    let conn: Connection =
+        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+
+    write_archive(&conn, &file_path).map_err(|e| format!("Error exporting archive: {}", e))
+}
+
+// Nothing is changed unless every file of the archive matches the manifest
+#[tauri::command]
+pub fn import_archive(
+    app_handle: tauri::AppHandle,
+    file_path: String,
+) -> Result<ArchiveManifest, String> {
+    let conn: Connection =
+        get_connection(app_handle.clone()).map_err(|e| format!("Error opening database: {}", e))?;
+
+    let manifest = with_group(&conn, "archive_import", || read_archive(&conn, &file_path))
+        .map_err(|e| format!("Error importing archive: {}", e))?;
+
+    write_snapshot(&app_handle, "archive_import")
+        .map_err(|e| format!("Error creating snapshot: {}", e))?;
+
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::open_test_database;
+
+    fn count_pieces(conn: &Connection) -> i64 {
+        conn.query_row("SELECT COUNT(*) FROM wood_pieces;", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn archive_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("auction.licitacija");
+        let archive_path = archive_path.to_str().unwrap();
+
+        let conn = open_test_database();
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name) VALUES (1, 'Seller');
+            INSERT INTO wood_pieces (sequence_no, plate_no, seller_id, tree_species_id)
+                VALUES (1, 'A1', 1, 1), (2, 'A2', 1, 1);
+            UPDATE images SET mime_type = 'image/png', data_base64 = 'aGVhZGVy'
+                WHERE image_key = 'header';",
+        )
+        .unwrap();
+        let written = write_archive(&conn, archive_path).unwrap();
+        // Only the finished archive is left
+        let names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec!["auction.licitacija"]);
+
+        let other = open_test_database();
+        let read = read_archive(&other, archive_path).unwrap();
+        assert_eq!(read.files.len(), written.files.len());
+        assert_eq!(count_pieces(&other), 2);
+        let data: String = other
+            .query_row(
+                "SELECT data_base64 FROM images WHERE image_key = 'header';",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(data, "aGVhZGVy");
+    }
+
+    #[test]
+    fn failed_export_leaves_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("auction.licitacija");
+        let archive_path = archive_path.to_str().unwrap();
+
+        let conn = open_test_database();
+        conn.execute(
+            "UPDATE images SET data_base64 = '%%' WHERE image_key = 'header';",
+            [],
+        )
+        .unwrap();
+
+        assert!(write_archive(&conn, archive_path).is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    // Copies the archive at `path` with the contents of every entry passed through `change`
+    fn rewrite_archive(path: &str, change: impl Fn(&str, Vec<u8>) -> Vec<u8>) {
+        let mut zip = ZipArchive::new(File::open(path).unwrap()).unwrap();
+        let rewritten_path = format!("{}.rewritten", path);
+        let mut rewritten = ZipWriter::new(File::create(&rewritten_path).unwrap());
+        for i in 0..zip.len() {
+            let mut entry = zip.by_index(i).unwrap();
+            let mut contents = vec![];
+            entry.read_to_end(&mut contents).unwrap();
+            rewritten
+                .start_file(entry.name(), SimpleFileOptions::default())
+                .unwrap();
+            rewritten
+                .write_all(&change(entry.name(), contents))
+                .unwrap();
+        }
+        rewritten.finish().unwrap();
+        fs::rename(&rewritten_path, path).unwrap();
+    }
+
+    #[test]
+    fn tampered_archive_is_refused_and_changes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = open_test_database();
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name) VALUES (1, 'Seller');
+            INSERT INTO wood_pieces (sequence_no, plate_no, seller_id, tree_species_id)
+                VALUES (1, 'A1', 1, 1), (2, 'A2', 1, 1);
+            UPDATE images SET mime_type = 'image/png', data_base64 = 'aGVhZGVy'
+                WHERE image_key = 'header';",
+        )
+        .unwrap();
+
+        let image_path = dir.path().join("image.licitacija");
+        let image_path = image_path.to_str().unwrap();
+        let manifest = write_archive(&conn, image_path).unwrap();
+        let header_path = manifest
+            .images
+            .iter()
+            .find(|image| image.image_key == "header")
+            .unwrap()
+            .path
+            .clone();
+        // Same size, different bytes
+        rewrite_archive(image_path, |name, contents| {
+            if name == header_path {
+                b"HEADER".to_vec()
+            } else {
+                contents
+            }
+        });
+
+        let manifest_path = dir.path().join("manifest.licitacija");
+        let manifest_path = manifest_path.to_str().unwrap();
+        write_archive(&conn, manifest_path).unwrap();
+        rewrite_archive(manifest_path, |name, contents| {
+            if name != MANIFEST_PATH {
+                return contents;
+            }
+            let mut manifest: ArchiveManifest = serde_json::from_slice(&contents).unwrap();
+            for file in manifest
+                .files
+                .iter_mut()
+                .filter(|file| file.path == DATA_PATH)
+            {
+                file.sha256 = "0".repeat(64);
+            }
+            serde_json::to_vec(&manifest).unwrap()
+        });
+
+        let other = open_test_database();
+        other
+            .execute_batch(
+                "INSERT INTO sellers (id, seller_name) VALUES (1, 'Other');
+                INSERT INTO wood_pieces (sequence_no, plate_no, seller_id, tree_species_id)
+                    VALUES (1, 'B1', 1, 1);",
+            )
+            .unwrap();
+        for (path, damaged) in [
+            (image_path, header_path.as_str()),
+            (manifest_path, DATA_PATH),
+        ] {
+            let error = read_archive(&other, path).unwrap_err().to_string();
+            assert_eq!(
+                error,
+                format!("File {} is damaged, checksum does not match", damaged)
+            );
+        }
+
+        assert_eq!(count_pieces(&other), 1);
+        let (seller_name, image_data): (String, Option<String>) = other
+            .query_row(
+                "SELECT seller_name, (SELECT data_base64 FROM images WHERE image_key = 'header')
+                FROM sellers;",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(seller_name, "Other");
+        assert_ne!(image_data.as_deref(), Some("aGVhZGVy"));
+    }
+}
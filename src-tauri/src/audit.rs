@@ -0,0 +1,157 @@
+use rusqlite::Connection;
+use serde::Serialize;
+use serde_json::Value;
+use std::error::Error;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::shared::get_connection;
+
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    // Unix timestamp (seconds)
+    pub created_at: i64,
+    pub table: String,
+    pub row_id: Option<i64>,
+    // insert, update or delete
+    pub operation: String,
+    // The whole row before and after the change, null for inserts and deletes respectively
+    pub old_row: Option<Value>,
+    pub new_row: Option<Value>,
+    // Label of the undo group the change was made in, or "undo"/"redo"
+    pub action: Option<String>,
+    // Who made the change and in which run of the app, unknown for entries logged by older
+    // versions of the app
+    pub actor: Option<String>,
+    pub session_id: Option<String>,
+}
+
+// Identifies this run of the app, the same for all of its connections
+fn get_session_id() -> &'static str {
+    static SESSION_ID: OnceLock<String> = OnceLock::new();
+
+    SESSION_ID.get_or_init(|| {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        format!("{}-{}", started.as_millis(), std::process::id())
+    })
+}
+
+// The user logged into the computer
+fn get_os_user() -> Option<String> {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+}
+
+// Written next to every change by the audit triggers, until another session starts
+pub fn start_session(conn: &Connection, actor: Option<&str>) -> Result<(), Box<dyn Error>> {
+    conn.execute(
+        "INSERT OR REPLACE INTO audit_session (id, actor, session_id) VALUES (1, ?1, ?2);",
+        (actor, get_session_id()),
+    )?;
+
+    Ok(())
+}
+
+fn parse_row(row: Option<String>) -> Option<Value> {
+    row.and_then(|row| serde_json::from_str(&row).ok())
+}
+
+// Changes of one row, oldest first
+pub fn query_audit_log(
+    conn: &Connection,
+    table: &str,
+    row_id: i64,
+) -> Result<Vec<AuditEntry>, Box<dyn Error>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, created_at, table_name, row_id, operation, old_row, new_row, action, actor,
+            session_id
+        FROM audit_log WHERE table_name = ?1 AND row_id = ?2 ORDER BY id;",
+    )?;
+    let rows = stmt.query_map((table, row_id), |row| {
+        Ok(AuditEntry {
+            id: row.get("id")?,
+            created_at: row.get("created_at")?,
+            table: row.get("table_name")?,
+            row_id: row.get("row_id")?,
+            operation: row.get("operation")?,
+            old_row: parse_row(row.get("old_row")?),
+            new_row: parse_row(row.get("new_row")?),
+            action: row.get("action")?,
+            actor: row.get("actor")?,
+            session_id: row.get("session_id")?,
+        })
+    })?;
+
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+// The whole log is exported with `export_csv` and the audit_log report
+#[tauri::command]
+pub fn get_audit_log(
+    app_handle: tauri::AppHandle,
+    table: String,
+    row_id: i64,
+) -> Result<Vec<AuditEntry>, String> {
+    let conn: Connection =
+        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+
+    query_audit_log(&conn, &table, row_id).map_err(|e| format!("Error reading audit log: {}", e))
+}
+
+// Called by the frontend once the database is upgraded, the actor defaults to the user
+// logged into the computer
+#[tauri::command]
+pub fn start_audit_session(
+    app_handle: tauri::AppHandle,
+    actor: Option<String>,
+) -> Result<(), String> {
+    let conn: Connection =
+        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+
+    let actor = actor.or_else(get_os_user);
+    start_session(&conn, actor.as_deref())
+        .map_err(|e| format!("Error starting audit session: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::open_test_database;
+
+    #[test]
+    fn large_columns_are_logged_as_their_length_with_the_session() {
+        let conn = open_test_database();
+        start_session(&conn, Some("clerk")).unwrap();
+        let image = "a".repeat(100_000);
+        conn.execute(
+            "UPDATE images SET mime_type = 'image/png', data_base64 = ?1 WHERE image_key = 'header';",
+            [&image],
+        )
+        .unwrap();
+        let id: i64 = conn
+            .query_row(
+                "SELECT id FROM images WHERE image_key = 'header';",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        let entries = query_audit_log(&conn, "images", id).unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        let new_row = entry.new_row.as_ref().unwrap();
+        assert_eq!(new_row["data_base64_length"], 100_000);
+        assert_eq!(new_row["mime_type"], "image/png");
+        assert!(new_row.get("data_base64").is_none());
+        assert_eq!(
+            entry.old_row.as_ref().unwrap()["data_base64_length"],
+            Value::Null
+        );
+        assert_eq!(entry.actor.as_deref(), Some("clerk"));
+        assert_eq!(entry.session_id.as_deref(), Some(get_session_id()));
+    }
+}
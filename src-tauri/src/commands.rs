@@ -1,66 +1,221 @@
-use rusqlite::Connection;
+use rusqlite::backup::{Backup, Progress, StepResult};
+use rusqlite::{Connection, OpenFlags};
+use serde::Serialize;
 use std::error::Error;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
 use std::time::Duration;
-use tauri::Manager;
+use tauri::{Emitter, Manager};
 use tauri_plugin_fs::FsExt;
+use crate::legacy::{copy_database, upgrade_legacy_database};
+use crate::restore::{validate_backup_file, BackupReport, RestoreError};
+use crate::shared::{get_connection, get_db_path, DB_NAME};
+use crate::snapshot::write_snapshot;
 
-fn backup_progress(progress: rusqlite::backup::Progress) {
-    // Calculate the percentage of the backup progress
-    let percentage = if progress.pagecount > 0 {
-        100 - (progress.remaining * 100 / progress.pagecount)
-    } else {
-        0
+// Pages copied per backup step, a progress event is emitted after every step
+const PAGES_PER_STEP: std::os::raw::c_int = 256;
+
+#[derive(Clone, Serialize)]
+pub struct BackupProgress {
+    // "dump" or "load"
+    pub operation: &'static str,
+    // "started", "copying", "finished", "cancelled" or "failed"
+    pub phase: &'static str,
+    pub pages_done: i32,
+    pub pages_total: i32,
+}
+
+// Lets `cancel_backup` stop a running dump or load, each has its own flag so cancelling one
+// never stops the other
+#[derive(Default)]
+pub struct BackupState {
+    cancel_dump: AtomicBool,
+    cancel_load: AtomicBool,
+}
+
+impl BackupState {
+    fn cancel_flag(&self, operation: &str) -> Result<&AtomicBool, String> {
+        match operation {
+            "dump" => Ok(&self.cancel_dump),
+            "load" => Ok(&self.cancel_load),
+            _ => Err(format!("Unknown backup operation: {}", operation)),
+        }
+    }
+}
+
+fn emit_progress(
+    app_handle: &tauri::AppHandle,
+    operation: &'static str,
+    phase: &'static str,
+    progress: Option<Progress>,
+) {
+    let (pages_done, pages_total) = progress
+        .map(|p| (p.pagecount - p.remaining, p.pagecount))
+        .unwrap_or((0, 0));
+
+    let payload = BackupProgress {
+        operation,
+        phase,
+        pages_done,
+        pages_total,
     };
+    if let Err(e) = app_handle.emit_to("main", "backup-progress", payload) {
+        println!("Failed to emit backup progress: {}", e);
+    }
+}
+
+// Copies `src` into `dst` step by step, reporting progress and checking for cancellation.
+// When the copy does not finish, dropping the backup rolls back everything written to `dst`.
+fn run_backup(
+    src: &Connection,
+    dst: &mut Connection,
+    cancel_requested: &AtomicBool,
+    on_progress: impl Fn(&'static str, Option<Progress>),
+) -> Result<(), String> {
+    // A cancel requested while nothing was running must not stop this run
+    cancel_requested.store(false, Ordering::SeqCst);
 
-    // Print the progress
-    println!("Backup progress: {}%", percentage);
+    let backup = Backup::new(src, dst).map_err(|e| e.to_string())?;
+    on_progress("started", None);
+
+    loop {
+        if cancel_requested.swap(false, Ordering::SeqCst) {
+            on_progress("cancelled", Some(backup.progress()));
+            return Err("Backup cancelled".to_string());
+        }
+
+        let step = match backup.step(PAGES_PER_STEP) {
+            Ok(step) => step,
+            Err(e) => {
+                on_progress("failed", Some(backup.progress()));
+                return Err(e.to_string());
+            }
+        };
+
+        match step {
+            StepResult::Done => break,
+            StepResult::More => {}
+            // Another connection is using the database, try again shortly
+            _ => thread::sleep(Duration::from_millis(250)),
+        }
+
+        on_progress("copying", Some(backup.progress()));
+    }
+
+    on_progress("finished", Some(backup.progress()));
+
+    Ok(())
 }
 
-pub fn get_connection(app_handle: tauri::AppHandle) -> Result<Connection, String> {
-    // Get the app data directory path
-    let app_data_dir = app_handle
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to resolve app data directory: {}", e))?;
+// `run_backup` with the cancel flag of `operation`, reporting progress as events
+fn run_app_backup(
+    app_handle: &tauri::AppHandle,
+    operation: &'static str,
+    src: &Connection,
+    dst: &mut Connection,
+) -> Result<(), String> {
+    let state = app_handle.state::<BackupState>();
+    run_backup(
+        src,
+        dst,
+        state.cancel_flag(operation)?,
+        |phase, progress| emit_progress(app_handle, operation, phase, progress),
+    )
+}
 
-    // Construct the path to the SQLite database file
-    let sqlite_file = app_data_dir.join("main_database_v12.db");
+// Writes next to the target first so a cancelled or failed dump never leaves a partial file
+fn dump_database(
+    file_path: &str,
+    backup: impl FnOnce(&mut Connection) -> Result<(), String>,
+) -> Result<(), String> {
+    let part_path = PathBuf::from(format!("{}.part", file_path));
+    let _ = fs::remove_file(&part_path);
+
+    let result = Connection::open(&part_path)
+        .map_err(|e| e.to_string())
+        .and_then(|mut dst| backup(&mut dst));
 
-    // Open the SQLite connection
-    Connection::open(sqlite_file).map_err(|e| e.to_string())
+    match result {
+        Ok(()) => fs::rename(&part_path, file_path).map_err(|e| e.to_string()),
+        Err(e) => {
+            let _ = fs::remove_file(&part_path);
+            Err(e)
+        }
+    }
 }
 
-#[tauri::command]
+#[tauri::command(async)]
 pub fn dump_sqlite_db(app_handle: tauri::AppHandle, file_path: String) -> Result<(), String> {
-    let conn = get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+    let conn = get_connection(app_handle.clone())
+        .map_err(|e| format!("Error opening database: {}", e))?;
 
-    let mut dst = Connection::open(file_path).map_err(|e| e.to_string())?;
+    dump_database(&file_path, |dst| {
+        run_app_backup(&app_handle, "dump", &conn, dst)
+    })
+}
 
-    // Backup the database to the user-selected file
-    let mut backup = rusqlite::backup::Backup::new(&conn, &mut dst).map_err(|e| e.to_string())?;
+// Validates the database at `path`, brings a copy of it up to the current schema, snapshots
+// the working database and replaces it with the copy
+pub fn restore_database(
+    app_handle: &tauri::AppHandle,
+    path: &Path,
+    snapshot_reason: &str,
+) -> Result<BackupReport, RestoreError> {
+    // Refuse anything that is not a backup of this app before touching the live database
+    let mut report = validate_backup_file(path)?;
 
-    backup
-        .run_to_completion(5, Duration::from_millis(250), Some(backup_progress))
-        .map_err(|e| e.to_string())?;
+    // The SQL plugin only runs pending migrations when the app starts, so a backup made by an
+    // older version is upgraded here. Otherwise the app would run on the old schema until then.
+    let db_path = get_db_path(app_handle).map_err(RestoreError::failed)?;
+    let upgrade_path = PathBuf::from(format!("{}.restore", db_path.display()));
+    let result = copy_database(path, &upgrade_path)
+        .and_then(|conn| upgrade_legacy_database(&conn))
+        .map_err(|e| RestoreError::failed(format!("Error upgrading backup: {}", e)))
+        .and_then(|steps| {
+            report.upgrade_steps = steps;
+            load_database(app_handle, &upgrade_path, snapshot_reason)
+        });
+    let _ = fs::remove_file(&upgrade_path);
+    result?;
 
-    Ok(())
+    Ok(report)
 }
 
-#[tauri::command]
-pub fn load_sqlite_db(app_handle: tauri::AppHandle, file_path: String) -> Result<(), String> {
-    let mut conn =
-        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+pub fn load_database(
+    app_handle: &tauri::AppHandle,
+    path: &Path,
+    snapshot_reason: &str,
+) -> Result<(), RestoreError> {
+    write_snapshot(app_handle, snapshot_reason)
+        .map_err(|e| RestoreError::failed(format!("Error creating snapshot: {}", e)))?;
 
-    let dst = Connection::open(file_path).map_err(|e| e.to_string())?;
+    let mut conn = get_connection(app_handle.clone())
+        .map_err(|e| RestoreError::failed(format!("Error opening database: {}", e)))?;
 
+    let src = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(RestoreError::failed)?;
+
+    run_app_backup(app_handle, "load", &src, &mut conn).map_err(RestoreError::failed)
+}
+
+#[tauri::command(async)]
+pub fn load_sqlite_db(
+    app_handle: tauri::AppHandle,
+    file_path: String,
+) -> Result<BackupReport, RestoreError> {
     // Restore the user-selected database backup
-    let mut backup = rusqlite::backup::Backup::new(&dst, &mut conn).map_err(|e| e.to_string())?;
+    restore_database(&app_handle, Path::new(&file_path), "before_load")
+}
 
-    backup
-        .run_to_completion(5, Duration::from_millis(250), Some(backup_progress))
-        .map_err(|e| e.to_string())?;
+// `operation` is "dump" or "load"
+#[tauri::command]
+pub fn cancel_backup(
+    state: tauri::State<'_, BackupState>,
+    operation: String,
+) -> Result<(), String> {
+    state.cancel_flag(&operation)?.store(true, Ordering::SeqCst);
 
     Ok(())
 }
@@ -74,7 +229,7 @@ pub fn clear_db(app_handle: tauri::AppHandle) -> Result<(), String> {
         .map_err(|e| format!("Failed to remove file: {}", e))?;
 
     // Construct the path to the SQLite database file
-    let sqlite_file = app_data_dir.join("main_database_v12.db");
+    let sqlite_file = app_data_dir.join(DB_NAME);
 
     // Attempt to remove the file
     if sqlite_file.exists() {
@@ -86,3 +241,86 @@ pub fn clear_db(app_handle: tauri::AppHandle) -> Result<(), String> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Large enough to take several backup steps
+    fn open_large_database(path: &Path) -> Connection {
+        let conn = Connection::open(path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE data (id INTEGER PRIMARY KEY, payload BLOB);
+            INSERT INTO data (payload) VALUES (zeroblob(4 * 1024 * 1024));",
+        )
+        .unwrap();
+        conn
+    }
+
+    // Requests a cancel as soon as the first step was copied
+    fn cancel_after_first_step(
+        cancel_requested: &AtomicBool,
+    ) -> impl Fn(&'static str, Option<Progress>) + '_ {
+        |phase, _| {
+            if phase == "copying" {
+                cancel_requested.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    #[test]
+    fn cancelled_dump_leaves_no_partial_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = open_large_database(&dir.path().join("live.db"));
+        let file_path = dir.path().join("backup.db");
+        let file_path = file_path.to_str().unwrap();
+        let cancel_requested = AtomicBool::new(false);
+
+        let result = dump_database(file_path, |dst| {
+            run_backup(
+                &conn,
+                dst,
+                &cancel_requested,
+                cancel_after_first_step(&cancel_requested),
+            )
+        });
+
+        assert_eq!(result, Err("Backup cancelled".to_string()));
+        assert!(!Path::new(file_path).exists());
+        assert!(!Path::new(&format!("{}.part", file_path)).exists());
+    }
+
+    #[test]
+    fn cancelled_load_leaves_the_live_database_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = open_large_database(&dir.path().join("backup.db"));
+        let mut live = Connection::open(dir.path().join("live.db")).unwrap();
+        live.execute_batch(
+            "CREATE TABLE sellers (id INTEGER PRIMARY KEY, seller_name TEXT);
+            INSERT INTO sellers (seller_name) VALUES ('Seller');",
+        )
+        .unwrap();
+        let cancel_requested = AtomicBool::new(false);
+
+        let result = run_backup(
+            &src,
+            &mut live,
+            &cancel_requested,
+            cancel_after_first_step(&cancel_requested),
+        );
+
+        assert_eq!(result, Err("Backup cancelled".to_string()));
+        let tables: Vec<String> = live
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table';")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(tables, ["sellers"]);
+        let seller_name: String = live
+            .query_row("SELECT seller_name FROM sellers;", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(seller_name, "Seller");
+    }
+}
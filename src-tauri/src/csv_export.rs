@@ -0,0 +1,403 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rusqlite::types::ValueRef;
+use rusqlite::Connection;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use crate::settlement::{compute_all_settlements, get_winning_offers_sql, STATEMENT_COLUMNS};
+use crate::shared::get_connection;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+// Every sold piece with its winning offer, the same offer the seller's statement counts
+fn get_sold_pieces_sql() -> String {
+    format!(
+        "SELECT
+            wood_pieces.id AS wood_piece_id,
+            sellers.seller_name,
+            sellers.ident AS seller_ident,
+            buyers.buyer_name,
+            buyers.ident AS buyer_ident,
+            wood_pieces.sequence_no,
+            wood_pieces.plate_no,
+            tree_species.tree_species_name,
+            tree_species.tree_species_name_slo,
+            tree_species.latin_name,
+            wood_pieces.length,
+            wood_pieces.width,
+            wood_pieces.volume,
+            wood_pieces.min_price,
+            offers.offered_price,
+            ROUND(offers.offered_price * wood_pieces.volume, 2) AS total_price
+        FROM wood_pieces
+        JOIN ({}) offers ON offers.wood_piece_id = wood_pieces.id
+        LEFT JOIN sellers ON sellers.id = wood_pieces.seller_id
+        LEFT JOIN buyers ON buyers.id = offers.buyer_id
+        LEFT JOIN tree_species ON tree_species.id = wood_pieces.tree_species_id",
+        get_winning_offers_sql(false)
+    )
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CsvReport {
+    Sellers,
+    Buyers,
+    TreeSpecies,
+    WoodPieces,
+    WoodPieceOffers,
+    // Sold pieces grouped by seller
+    SoldPiecesPerSeller,
+    // Sold pieces grouped by buyer
+    BoughtPiecesPerBuyer,
+    // Pieces, volume and prices per species
+    SpeciesStats,
+    // The totals of every seller's statement
+    SellerStatements,
+    // Every change ever made, for disputes after the auction
+    AuditLog,
+}
+
+// The audit log is left out, it is exported on its own
+const ALL_REPORTS: [CsvReport; 9] = [
+    CsvReport::Sellers,
+    CsvReport::Buyers,
+    CsvReport::TreeSpecies,
+    CsvReport::WoodPieces,
+    CsvReport::WoodPieceOffers,
+    CsvReport::SoldPiecesPerSeller,
+    CsvReport::BoughtPiecesPerBuyer,
+    CsvReport::SpeciesStats,
+    CsvReport::SellerStatements,
+];
+
+impl CsvReport {
+    fn file_name(&self) -> &'static str {
+        match self {
+            CsvReport::Sellers => "sellers.csv",
+            CsvReport::Buyers => "buyers.csv",
+            CsvReport::TreeSpecies => "tree_species.csv",
+            CsvReport::WoodPieces => "wood_pieces.csv",
+            CsvReport::WoodPieceOffers => "wood_piece_offers.csv",
+            CsvReport::SoldPiecesPerSeller => "sold_pieces_per_seller.csv",
+            CsvReport::BoughtPiecesPerBuyer => "bought_pieces_per_buyer.csv",
+            CsvReport::SpeciesStats => "species_stats.csv",
+            CsvReport::SellerStatements => "seller_statements.csv",
+            CsvReport::AuditLog => "audit_log.csv",
+        }
+    }
+
+    // None for reports that are not a single query
+    pub fn sql(&self) -> Option<String> {
+        let sql = match self {
+            CsvReport::Sellers => "SELECT * FROM sellers ORDER BY id;".to_string(),
+            CsvReport::Buyers => "SELECT * FROM buyers ORDER BY id;".to_string(),
+            CsvReport::TreeSpecies => "SELECT * FROM tree_species ORDER BY id;".to_string(),
+            // `volume` is a generated column and is part of SELECT *
+            CsvReport::WoodPieces => "SELECT * FROM wood_pieces ORDER BY sequence_no;".to_string(),
+            CsvReport::WoodPieceOffers => {
+                "SELECT * FROM wood_piece_offers ORDER BY id;".to_string()
+            }
+            CsvReport::SoldPiecesPerSeller => format!(
+                "SELECT * FROM ({}) ORDER BY seller_name, sequence_no;",
+                get_sold_pieces_sql()
+            ),
+            CsvReport::BoughtPiecesPerBuyer => format!(
+                "SELECT * FROM ({}) ORDER BY buyer_name, sequence_no;",
+                get_sold_pieces_sql()
+            ),
+            CsvReport::SpeciesStats => format!(
+                "SELECT
+                    tree_species.tree_species_name,
+                    tree_species.tree_species_name_slo,
+                    tree_species.latin_name,
+                    COUNT(wood_pieces.id) AS num_pieces,
+                    COUNT(sold.wood_piece_id) AS num_sold_pieces,
+                    ROUND(SUM(wood_pieces.volume), 2) AS total_volume,
+                    ROUND(SUM(sold.volume), 2) AS sold_volume,
+                    MAX(sold.offered_price) AS max_offered_price,
+                    ROUND(SUM(sold.total_price) / SUM(sold.volume), 2) AS avg_offered_price,
+                    ROUND(SUM(sold.total_price), 2) AS total_price
+                FROM tree_species
+                JOIN wood_pieces ON wood_pieces.tree_species_id = tree_species.id
+                LEFT JOIN ({}) sold ON sold.wood_piece_id = wood_pieces.id
+                GROUP BY tree_species.id
+                ORDER BY tree_species.tree_species_name;",
+                get_sold_pieces_sql()
+            ),
+            CsvReport::AuditLog => "SELECT
+                    id,
+                    datetime(created_at, 'unixepoch') AS created_at,
+                    table_name,
+                    row_id,
+                    operation,
+                    old_row,
+                    new_row,
+                    action,
+                    actor,
+                    session_id
+                FROM audit_log ORDER BY id;"
+                .to_string(),
+            CsvReport::SellerStatements => return None,
+        };
+
+        Some(sql)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CsvOptions {
+    // `;` is what Excel expects with Slovenian regional settings
+    pub delimiter: char,
+    // Write 1,5 instead of 1.5
+    pub decimal_comma: bool,
+    // Start the file with a UTF-8 byte order mark so Excel detects the encoding (č, š, ž)
+    pub bom: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: ',',
+            decimal_comma: false,
+            bom: false,
+        }
+    }
+}
+
+fn format_value(value: ValueRef, options: &CsvOptions) -> String {
+    match value {
+        ValueRef::Null => String::new(),
+        ValueRef::Integer(i) => i.to_string(),
+        ValueRef::Real(r) if options.decimal_comma => r.to_string().replace('.', ","),
+        ValueRef::Real(r) => r.to_string(),
+        ValueRef::Text(t) => String::from_utf8_lossy(t).to_string(),
+        ValueRef::Blob(b) => BASE64.encode(b),
+    }
+}
+
+fn format_amount(amount: Decimal, options: &CsvOptions) -> String {
+    if options.decimal_comma {
+        amount.to_string().replace('.', ",")
+    } else {
+        amount.to_string()
+    }
+}
+
+// One row per seller with pieces, the same amounts as on the printed statements
+fn write_statements(
+    conn: &Connection,
+    writer: &mut csv::Writer<File>,
+    options: &CsvOptions,
+) -> Result<(), Box<dyn Error>> {
+    writer.write_record(STATEMENT_COLUMNS)?;
+
+    for settlement in compute_all_settlements(conn, false)? {
+        let mut record = vec![
+            settlement.seller_name.clone().unwrap_or_default(),
+            settlement.seller_ident.clone().unwrap_or_default(),
+            settlement.num_sold_pieces().to_string(),
+        ];
+        record.extend(
+            settlement
+                .amounts()
+                .into_iter()
+                .map(|amount| format_amount(amount, options)),
+        );
+        writer.write_record(&record)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn write_csv(
+    conn: &Connection,
+    report: CsvReport,
+    path: &Path,
+    options: &CsvOptions,
+) -> Result<(), Box<dyn Error>> {
+    if !options.delimiter.is_ascii() {
+        return Err(format!("Unsupported delimiter: {}", options.delimiter).into());
+    }
+
+    let mut file = File::create(path)?;
+    if options.bom {
+        file.write_all(UTF8_BOM)?;
+    }
+
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(options.delimiter as u8)
+        .from_writer(file);
+
+    let Some(sql) = report.sql() else {
+        return write_statements(conn, &mut writer, options);
+    };
+    let mut stmt = conn.prepare(&sql)?;
+    writer.write_record(stmt.column_names())?;
+
+    let column_count = stmt.column_count();
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let mut record = Vec::with_capacity(column_count);
+        for i in 0..column_count {
+            record.push(format_value(row.get_ref(i)?, options));
+        }
+        writer.write_record(&record)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub fn export_csv(
+    app_handle: tauri::AppHandle,
+    report: CsvReport,
+    file_path: String,
+    options: Option<CsvOptions>,
+) -> Result<(), String> {
+    let conn: Connection =
+        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+
+    write_csv(
+        &conn,
+        report,
+        Path::new(&file_path),
+        &options.unwrap_or_default(),
+    )
+    .map_err(|e| format!("Error exporting CSV: {}", e))
+}
+
+// Writes every table and report into `dir_path`, one file each, and returns the file names
+#[tauri::command]
+pub fn export_all_csv(
+    app_handle: tauri::AppHandle,
+    dir_path: String,
+    options: Option<CsvOptions>,
+) -> Result<Vec<String>, String> {
+    let conn: Connection =
+        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+    let options = options.unwrap_or_default();
+
+    let dir = Path::new(&dir_path);
+    fs::create_dir_all(dir).map_err(|e| format!("Error creating {}: {}", dir_path, e))?;
+
+    let mut file_names = vec![];
+    for report in ALL_REPORTS {
+        write_csv(&conn, report, &dir.join(report.file_name()), &options)
+            .map_err(|e| format!("Error exporting {}: {}", report.file_name(), e))?;
+        file_names.push(report.file_name().to_string());
+    }
+
+    Ok(file_names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::open_test_database;
+
+    #[test]
+    fn species_stats_count_each_piece_once() {
+        let conn = open_test_database();
+        // Pieces without sequence or plate numbers used to match each other
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name) VALUES (1, 'Seller');
+            INSERT INTO buyers (id, buyer_name) VALUES (1, 'Buyer');
+            INSERT INTO wood_pieces (id, length, width, seller_id, tree_species_id, min_price)
+                VALUES (1, 4, 50, 1, 1, 100), (2, 4, 50, 1, 1, 100), (3, 4, 50, 1, 1, 100);
+            INSERT INTO wood_piece_offers (offered_price, wood_piece_id, buyer_id)
+                VALUES (100, 1, 1), (200, 2, 1);",
+        )
+        .unwrap();
+
+        let (num_pieces, num_sold_pieces, sold_volume, total_price): (i64, i64, f64, f64) = conn
+            .query_row(
+                &format!(
+                    "SELECT num_pieces, num_sold_pieces, sold_volume, total_price FROM ({})
+                    WHERE latin_name = (SELECT latin_name FROM tree_species WHERE id = 1);",
+                    CsvReport::SpeciesStats.sql().unwrap().trim_end_matches(';')
+                ),
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+
+        assert_eq!(num_pieces, 3);
+        assert_eq!(num_sold_pieces, 2);
+        assert_eq!(sold_volume, 1.58);
+        assert_eq!(total_price, 237.0);
+    }
+
+    #[test]
+    fn sold_pieces_are_the_pieces_sold_on_the_statements() {
+        let conn = open_test_database();
+        // Without a minimum price only a piece that may bypass it is sold
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name) VALUES (1, 'Seller');
+            INSERT INTO buyers (id, buyer_name) VALUES (1, 'Buyer');
+            INSERT INTO wood_pieces (id, sequence_no, length, width, seller_id, min_price, bypass_min_price)
+                VALUES (1, 1, 4, 50, 1, NULL, 0), (2, 2, 4, 50, 1, NULL, 1),
+                    (3, 3, 4, 50, 1, 300, 0), (4, 4, 4, 50, 1, 100, 0);
+            INSERT INTO wood_piece_offers (offered_price, wood_piece_id, buyer_id)
+                VALUES (100, 1, 1), (100, 2, 1), (200, 3, 1), (100, 4, 1);",
+        )
+        .unwrap();
+
+        let sql = CsvReport::SoldPiecesPerSeller.sql().unwrap();
+        let mut stmt = conn.prepare(&sql).unwrap();
+        let sold: Vec<i64> = stmt
+            .query_map([], |row| row.get("wood_piece_id"))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(sold, [2, 4]);
+
+        let settlement = crate::settlement::compute_settlement(&conn, 1, false).unwrap();
+        let sold_on_statement: Vec<i64> = settlement
+            .lines
+            .iter()
+            .filter(|line| line.offered_price.is_some())
+            .map(|line| line.wood_piece_id)
+            .collect();
+        assert_eq!(sold_on_statement, sold);
+    }
+
+    #[test]
+    fn seller_statements_are_the_settlements() {
+        let conn = open_test_database();
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name, ident, is_vat_liable) VALUES (1, 'Seller', 'S1', 1);
+            INSERT INTO buyers (id, buyer_name) VALUES (1, 'Buyer');
+            INSERT INTO wood_pieces (id, length, width, seller_id, min_price)
+                VALUES (1, 4, 50, 1, 100), (2, 4, 50, 1, 100);
+            INSERT INTO wood_piece_offers (offered_price, wood_piece_id, buyer_id)
+                VALUES (500, 1, 1);",
+        )
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("seller_statements.csv");
+        let options = CsvOptions {
+            delimiter: ';',
+            decimal_comma: true,
+            bom: false,
+        };
+        write_csv(&conn, CsvReport::SellerStatements, &path, &options).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines[0], STATEMENT_COLUMNS.join(";"));
+        let settlement = crate::settlement::compute_settlement(&conn, 1, false).unwrap();
+        let values: Vec<&str> = lines[1].split(';').collect();
+        assert_eq!(&values[..3], ["Seller", "S1", "1"]);
+        assert_eq!(values[16], settlement.payout.to_string().replace('.', ","));
+        assert_eq!(values[17], "0,79");
+    }
+}
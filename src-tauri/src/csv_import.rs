@@ -0,0 +1,502 @@
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+
+use crate::models::{Seller, TreeSpecies, WoodPiece};
+use crate::repository::{create, list};
+use crate::shared::{get_connection, UncheckedSavepoint};
+use crate::snapshot::write_snapshot;
+use crate::undo::with_group;
+
+const UTF8_BOM: char = '\u{feff}';
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LengthUnit {
+    Mm,
+    Cm,
+    M,
+}
+
+impl LengthUnit {
+    fn millimetres(&self) -> f64 {
+        match self {
+            LengthUnit::Mm => 1.0,
+            LengthUnit::Cm => 10.0,
+            LengthUnit::M => 1000.0,
+        }
+    }
+
+    // Multiplying first keeps e.g. 350 cm -> 3.5 m exact
+    fn convert(&self, value: f64, to: LengthUnit) -> f64 {
+        value * self.millimetres() / to.millimetres()
+    }
+}
+
+// Header of the file column holding each field, unmapped fields are left empty
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ColumnMapping {
+    pub plate_no: Option<String>,
+    pub sequence_no: Option<String>,
+    // Slovenian or Latin name
+    pub tree_species: Option<String>,
+    pub length: Option<String>,
+    pub width: Option<String>,
+    pub min_price: Option<String>,
+    pub seller_ident: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CsvImportOptions {
+    pub delimiter: char,
+    // Units used in the file, stored lengths are in metres and widths in centimetres
+    pub length_unit: LengthUnit,
+    pub width_unit: LengthUnit,
+    // Only check the file, nothing is written
+    pub dry_run: bool,
+}
+
+impl Default for CsvImportOptions {
+    fn default() -> Self {
+        CsvImportOptions {
+            delimiter: ',',
+            length_unit: LengthUnit::M,
+            width_unit: LengthUnit::Cm,
+            dry_run: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CsvLineError {
+    // Line in the file, the header is line 1
+    pub line: u64,
+    pub column: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CsvImportReport {
+    pub imported: usize,
+    // Lines left out because of errors
+    pub skipped: usize,
+    pub errors: Vec<CsvLineError>,
+}
+
+// Species and sellers the lines refer to, looked up once per import
+struct Lookup {
+    // Lowercased Slovenian and Latin names
+    tree_species: HashMap<String, i64>,
+    sellers: HashMap<String, i64>,
+    plate_nos: HashSet<String>,
+    next_sequence_no: i64,
+}
+
+impl Lookup {
+    fn load(conn: &Connection) -> Result<Lookup, Box<dyn Error>> {
+        let mut tree_species = HashMap::new();
+        for species in list::<TreeSpecies>(conn)? {
+            for name in [&species.tree_species_name_slo, &species.latin_name]
+                .into_iter()
+                .flatten()
+            {
+                tree_species.insert(name.trim().to_lowercase(), species.id);
+            }
+        }
+
+        let sellers = list::<Seller>(conn)?
+            .into_iter()
+            .filter_map(|seller| Some((seller.ident?.trim().to_string(), seller.id)))
+            .collect();
+
+        let wood_pieces = list::<WoodPiece>(conn)?;
+        let next_sequence_no = wood_pieces
+            .iter()
+            .filter_map(|piece| piece.sequence_no)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let plate_nos = wood_pieces
+            .into_iter()
+            .filter_map(|piece| piece.plate_no)
+            .collect();
+
+        Ok(Lookup {
+            tree_species,
+            sellers,
+            plate_nos,
+            next_sequence_no,
+        })
+    }
+}
+
+// Accepts both 1.5 and 1,5
+fn parse_number(value: &str) -> Result<f64, String> {
+    value
+        .replace(',', ".")
+        .parse()
+        .map_err(|_| format!("Not a number: {}", value))
+}
+
+// Reads one line into a wood piece, collecting every problem instead of stopping at the first
+fn read_line(
+    record: &csv::StringRecord,
+    line: u64,
+    fields: &HashMap<&str, (String, usize)>,
+    options: &CsvImportOptions,
+    lookup: &Lookup,
+    errors: &mut Vec<CsvLineError>,
+) -> WoodPiece {
+    let get = |field: &str| {
+        fields.get(field).and_then(|(header, index)| {
+            let value = record.get(*index).unwrap_or_default().trim();
+            (!value.is_empty()).then(|| (header.clone(), value))
+        })
+    };
+    let mut error = |column: &str, message: String| {
+        errors.push(CsvLineError {
+            line,
+            column: Some(column.to_string()),
+            message,
+        })
+    };
+
+    let mut number = |field: &str| match get(field) {
+        Some((header, value)) => parse_number(value).map_err(|e| error(&header, e)).ok(),
+        None => None,
+    };
+    let length = number("length").map(|v| options.length_unit.convert(v, LengthUnit::M));
+    let width = number("width").map(|v| options.width_unit.convert(v, LengthUnit::Cm));
+    let min_price = number("min_price");
+    let sequence_no = number("sequence_no");
+
+    let sequence_no = match sequence_no {
+        Some(v) if v.fract() != 0.0 => {
+            let header = &fields["sequence_no"].0;
+            error(header, format!("Not a whole number: {}", v));
+            None
+        }
+        Some(v) => Some(v as i64),
+        None => None,
+    };
+
+    let plate_no = match get("plate_no") {
+        Some((header, value)) if lookup.plate_nos.contains(value) => {
+            error(&header, format!("Plate {} already exists", value));
+            None
+        }
+        Some((_, value)) => Some(value.to_string()),
+        None => {
+            error(&fields["plate_no"].0, "Plate number is missing".to_string());
+            None
+        }
+    };
+
+    let seller_id = match get("seller_ident") {
+        Some((header, value)) => {
+            let seller_id = lookup.sellers.get(value).copied();
+            if seller_id.is_none() {
+                error(&header, format!("No seller with ident {}", value));
+            }
+            seller_id
+        }
+        None => {
+            error(&fields["seller_ident"].0, "Seller is missing".to_string());
+            None
+        }
+    };
+
+    let tree_species_id = get("tree_species").and_then(|(header, value)| {
+        let tree_species_id = lookup.tree_species.get(&value.to_lowercase()).copied();
+        if tree_species_id.is_none() {
+            error(&header, format!("Unknown tree species: {}", value));
+        }
+        tree_species_id
+    });
+
+    WoodPiece {
+        length,
+        sequence_no,
+        width,
+        plate_no,
+        seller_id,
+        tree_species_id,
+        min_price,
+        bypass_min_price: Some(0),
+        ..Default::default()
+    }
+}
+
+fn import_csv(
+    conn: &Connection,
+    csv_path: &str,
+    mapping: &ColumnMapping,
+    options: &CsvImportOptions,
+) -> Result<CsvImportReport, Box<dyn Error>> {
+    if !options.delimiter.is_ascii() {
+        return Err(format!("Unsupported delimiter: {}", options.delimiter).into());
+    }
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter as u8)
+        .flexible(true)
+        .from_reader(File::open(csv_path)?);
+
+    // Spreadsheet programs often start the file with a byte order mark
+    let headers: Vec<String> = reader
+        .headers()?
+        .iter()
+        .map(|header| header.trim_start_matches(UTF8_BOM).trim().to_string())
+        .collect();
+
+    let mapped = [
+        ("plate_no", &mapping.plate_no),
+        ("sequence_no", &mapping.sequence_no),
+        ("tree_species", &mapping.tree_species),
+        ("length", &mapping.length),
+        ("width", &mapping.width),
+        ("min_price", &mapping.min_price),
+        ("seller_ident", &mapping.seller_ident),
+    ];
+    let mut fields = HashMap::new();
+    for (field, header) in mapped {
+        let Some(header) = header else {
+            continue;
+        };
+        let index = headers
+            .iter()
+            .position(|h| h == header.trim())
+            .ok_or_else(|| format!("Column {} is not in the file", header))?;
+        fields.insert(field, (header.clone(), index));
+    }
+    for field in ["plate_no", "seller_ident"] {
+        if !fields.contains_key(field) {
+            return Err(format!("No column is mapped to {}", field).into());
+        }
+    }
+
+    let mut lookup = Lookup::load(conn)?;
+    let tx = UncheckedSavepoint::new(conn)?;
+
+    let mut report = CsvImportReport {
+        imported: 0,
+        skipped: 0,
+        errors: vec![],
+    };
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                report.skipped += 1;
+                report.errors.push(CsvLineError {
+                    line: e.position().map(|p| p.line()).unwrap_or_default(),
+                    column: None,
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        };
+        if record.iter().all(|value| value.trim().is_empty()) {
+            continue;
+        }
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+
+        let error_count = report.errors.len();
+        let mut piece = read_line(&record, line, &fields, options, &lookup, &mut report.errors);
+        if report.errors.len() > error_count {
+            report.skipped += 1;
+            continue;
+        }
+
+        if piece.sequence_no.is_none() {
+            piece.sequence_no = Some(lookup.next_sequence_no);
+        }
+        match create(&tx, &piece) {
+            Ok(piece) => {
+                report.imported += 1;
+                lookup.next_sequence_no = lookup
+                    .next_sequence_no
+                    .max(piece.sequence_no.unwrap_or(0) + 1);
+                if let Some(plate_no) = piece.plate_no {
+                    lookup.plate_nos.insert(plate_no);
+                }
+            }
+            Err(e) => {
+                report.skipped += 1;
+                report.errors.push(CsvLineError {
+                    line,
+                    column: None,
+                    message: e.to_string(),
+                });
+            }
+        }
+    }
+
+    if !options.dry_run {
+        tx.commit()?;
+    }
+
+    Ok(report)
+}
+
+#[tauri::command]
+pub fn import_wood_pieces_csv(
+    app_handle: tauri::AppHandle,
+    file_path: String,
+    mapping: ColumnMapping,
+    options: Option<CsvImportOptions>,
+) -> Result<CsvImportReport, String> {
+    let conn: Connection =
+        get_connection(app_handle.clone()).map_err(|e| format!("Error opening database: {}", e))?;
+    let options = options.unwrap_or_default();
+
+    let report = with_group(&conn, "csv_import", || {
+        import_csv(&conn, &file_path, &mapping, &options)
+    })
+    .map_err(|e| format!("Error importing CSV: {}", e))?;
+
+    if !options.dry_run && report.imported > 0 {
+        write_snapshot(&app_handle, "csv_import")
+            .map_err(|e| format!("Error creating snapshot: {}", e))?;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::{open_test_database, write_test_file};
+
+    const HEADER: &str = "Plate;Species;Length;Width;Price;Seller";
+
+    fn open_auction() -> Connection {
+        let conn = open_test_database();
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name, ident) VALUES (1, 'Seller', 'S1');
+            INSERT INTO wood_pieces (sequence_no, plate_no, seller_id, tree_species_id)
+                VALUES (1, 'A0', 1, 1);",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn import(conn: &Connection, lines: &[&str], options: CsvImportOptions) -> CsvImportReport {
+        let mapping = ColumnMapping {
+            plate_no: Some("Plate".to_string()),
+            tree_species: Some("Species".to_string()),
+            length: Some("Length".to_string()),
+            width: Some("Width".to_string()),
+            min_price: Some("Price".to_string()),
+            seller_ident: Some("Seller".to_string()),
+            ..Default::default()
+        };
+        let options = CsvImportOptions {
+            delimiter: ';',
+            ..options
+        };
+        let contents = std::iter::once(HEADER)
+            .chain(lines.iter().copied())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let path = write_test_file("pieces.csv", &contents);
+
+        let report = import_csv(conn, path.to_str().unwrap(), &mapping, &options);
+        std::fs::remove_file(&path).unwrap();
+        report.unwrap()
+    }
+
+    fn count(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {};", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn millimetres_are_converted_to_metres_and_centimetres() {
+        let conn = open_auction();
+        let options = CsvImportOptions {
+            length_unit: LengthUnit::Mm,
+            width_unit: LengthUnit::Mm,
+            ..Default::default()
+        };
+        let report = import(&conn, &["B1;hrast graden;3500;455;100,5;S1"], options);
+
+        assert_eq!((report.imported, report.skipped), (1, 0));
+        let piece: (f64, f64, f64, i64, i64) = conn
+            .query_row(
+                "SELECT length, width, min_price, sequence_no, tree_species_id
+                FROM wood_pieces WHERE plate_no = 'B1';",
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(piece, (3.5, 45.5, 100.5, 2, 1));
+    }
+
+    #[test]
+    fn lines_with_errors_are_skipped_and_reported_with_their_line() {
+        let conn = open_auction();
+        let report = import(
+            &conn,
+            &[
+                "B1;Hrast graden;4;50;100;S1",
+                "B2;Smreka X;4;50;100;S1",
+                "B1;Bukev;4;50;100;S1",
+                "A0;Bukev;4;50;100;S1",
+                "B3;Bukev;4;wide;100;S9",
+            ],
+            CsvImportOptions::default(),
+        );
+
+        assert_eq!((report.imported, report.skipped), (1, 4));
+        let errors: Vec<(u64, Option<&str>, &str)> = report
+            .errors
+            .iter()
+            .map(|e| (e.line, e.column.as_deref(), e.message.as_str()))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (3, Some("Species"), "Unknown tree species: Smreka X"),
+                // Plates earlier in the file count as well as those in the database
+                (4, Some("Plate"), "Plate B1 already exists"),
+                (5, Some("Plate"), "Plate A0 already exists"),
+                (6, Some("Width"), "Not a number: wide"),
+                (6, Some("Seller"), "No seller with ident S9"),
+            ]
+        );
+        assert_eq!(count(&conn, "wood_pieces"), 2);
+    }
+
+    #[test]
+    fn dry_run_writes_nothing() {
+        let conn = open_auction();
+        let undo_entries = count(&conn, "undolog");
+        let options = CsvImportOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let report = import(
+            &conn,
+            &["B1;Bukev;4;50;100;S1", "B2;Bukev;4;50;100;S1"],
+            options,
+        );
+
+        assert_eq!((report.imported, report.skipped), (2, 0));
+        assert_eq!(count(&conn, "wood_pieces"), 1);
+        assert_eq!(count(&conn, "undolog"), undo_entries);
+    }
+}
@@ -1,76 +1,297 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rusqlite::types::ValueRef;
 use rusqlite::{Connection, Result};
+use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tauri::Manager;
+use crate::restore::read_schema_version;
 use crate::shared::{get_connection};
+use crate::subset::SubsetOwner;
+use crate::tables::TABLES;
 
-fn export_to_json(conn: &Connection, json_path: &str) -> Result<(), Box<dyn Error>> {
-    // Define the list of tables to export
-    let table_names = vec![
-        "buyers",
-        "sellers",
-        "tree_species",
-        "wood_pieces",
-        "wood_piece_offers",
-        "settings",
-        "images",
-    ];
-    let mut json_map = Map::new();
-
-    for table in table_names {
-        let columns = get_column_names(table);
-        let query = format!("SELECT {} FROM {}", columns, table);
-        let mut table_stmt = conn.prepare(&query)?;
-
-        let column_names: Vec<String> = table_stmt
-            .column_names()
-            .iter()
-            .map(|s| s.to_string())
-            .collect();
-
-        let rows = table_stmt.query_map([], |row| {
-            let mut obj = Map::new();
-            for (i, col) in column_names.iter().enumerate() {
-                let value: Value = match row.get_ref(i)? {
-                    rusqlite::types::ValueRef::Null => Value::Null,
-                    rusqlite::types::ValueRef::Integer(i) => Value::from(i),
-                    rusqlite::types::ValueRef::Real(r) => Value::from(r),
-                    rusqlite::types::ValueRef::Text(t) => Value::from(String::from_utf8_lossy(t)),
-                    rusqlite::types::ValueRef::Blob(_) => {
-                        Value::String("BLOB data not supported".to_string())
-                    }
-                };
-                obj.insert(col.clone(), value);
+// Exports without an envelope (a bare map of tables) are format version 1
+pub const EXPORT_FORMAT_VERSION: u32 = 2;
+
+// Column values that plain JSON cannot represent are wrapped in an object with one of these keys
+const BLOB_KEY: &str = "$blob";
+const REAL_KEY: &str = "$real";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedColumn {
+    pub name: String,
+    // Declared SQLite type, e.g. "INTEGER" or "VARCHAR"
+    #[serde(rename = "type")]
+    pub column_type: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedTable {
+    pub columns: Vec<ExportedColumn>,
+    pub row_count: usize,
+    // Hex encoded SHA-256 of `rows` serialized as compact JSON
+    pub sha256: String,
+    pub rows: Vec<Map<String, Value>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportEnvelope {
+    pub format_version: u32,
+    // Highest migration applied to the exported database
+    pub schema_version: Option<i64>,
+    pub app_version: String,
+    // Unix timestamp (seconds) of when the export was made
+    pub created_at: u64,
+    // Set when the file holds only the records of one seller or buyer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subset: Option<SubsetOwner>,
+    pub tables: BTreeMap<String, ExportedTable>,
+}
+
+pub fn encode_value(value: ValueRef) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(i) => Value::from(i),
+        ValueRef::Real(r) if r.is_finite() => Value::from(r),
+        // JSON has no infinity or NaN
+        ValueRef::Real(r) => Value::Object(Map::from_iter([(
+            REAL_KEY.to_string(),
+            Value::from(r.to_string()),
+        )])),
+        ValueRef::Text(t) => Value::from(String::from_utf8_lossy(t)),
+        ValueRef::Blob(b) => Value::Object(Map::from_iter([(
+            BLOB_KEY.to_string(),
+            Value::from(BASE64.encode(b)),
+        )])),
+    }
+}
+
+// Inverse of `encode_value`
+pub fn decode_value(value: &Value) -> Result<rusqlite::types::Value, String> {
+    use rusqlite::types::Value as SqlValue;
+
+    match value {
+        Value::Null => Ok(SqlValue::Null),
+        Value::Bool(b) => Ok(SqlValue::Integer(*b as i64)),
+        Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => Ok(SqlValue::Integer(i)),
+            (None, Some(f)) => Ok(SqlValue::Real(f)),
+            _ => Err(format!("Unsupported number: {}", n)),
+        },
+        Value::String(s) => Ok(SqlValue::Text(s.clone())),
+        Value::Object(obj) => match (obj.get(BLOB_KEY), obj.get(REAL_KEY)) {
+            (Some(Value::String(data)), None) => BASE64
+                .decode(data)
+                .map(SqlValue::Blob)
+                .map_err(|e| format!("Invalid blob: {}", e)),
+            (None, Some(Value::String(real))) => real
+                .parse()
+                .map(SqlValue::Real)
+                .map_err(|e| format!("Invalid real {}: {}", real, e)),
+            _ => Err(format!("Unsupported value: {}", value)),
+        },
+        Value::Array(_) => Err(format!("Unsupported value: {}", value)),
+    }
+}
+
+pub fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn hash_rows(rows: &[Map<String, Value>]) -> Result<String, Box<dyn Error>> {
+    Ok(to_hex(&Sha256::digest(serde_json::to_vec(rows)?)))
+}
+
+// Stored columns of a table, generated ones are left out as they cannot be inserted
+fn get_columns(conn: &Connection, table: &str) -> Result<Vec<ExportedColumn>, Box<dyn Error>> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({});", table))?;
+    let rows = stmt.query_map([], |row| {
+        Ok(ExportedColumn {
+            name: row.get("name")?,
+            column_type: row.get("type")?,
+        })
+    })?;
+
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+// Calls `f` with every row of `table` matching `condition` encoded as JSON, in id order
+fn for_each_row(
+    conn: &Connection,
+    table: &str,
+    columns: &[ExportedColumn],
+    condition: Option<&str>,
+    mut f: impl FnMut(Map<String, Value>) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let column_names: Vec<&str> = columns.iter().map(|c| c.name.as_str()).collect();
+
+    let query = format!(
+        "SELECT {} FROM {} WHERE {} ORDER BY id",
+        column_names.join(", "),
+        table,
+        condition.unwrap_or("TRUE")
+    );
+    let mut table_stmt = conn.prepare(&query)?;
+
+    let mut rows = table_stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let mut obj = Map::new();
+        for (i, col) in column_names.iter().enumerate() {
+            obj.insert(col.to_string(), encode_value(row.get_ref(i)?));
+        }
+        f(obj)?;
+    }
+
+    Ok(())
+}
+
+// Rows of `table`, only those matching the SQL `condition` if given
+pub fn export_table(
+    conn: &Connection,
+    table: &str,
+    condition: Option<&str>,
+) -> Result<ExportedTable, Box<dyn Error>> {
+    let columns = get_columns(conn, table)?;
+
+    let mut rows = vec![];
+    for_each_row(conn, table, &columns, condition, |row| {
+        rows.push(row);
+        Ok(())
+    })?;
+
+    Ok(ExportedTable {
+        row_count: rows.len(),
+        sha256: hash_rows(&rows)?,
+        columns,
+        rows,
+    })
+}
+
+// Envelope without any tables
+pub fn new_envelope(conn: &Connection) -> Result<ExportEnvelope, Box<dyn Error>> {
+    Ok(ExportEnvelope {
+        format_version: EXPORT_FORMAT_VERSION,
+        schema_version: read_schema_version(conn)?,
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        subset: None,
+        tables: BTreeMap::new(),
+    })
+}
+
+pub fn export_envelope(conn: &Connection) -> Result<ExportEnvelope, Box<dyn Error>> {
+    let mut envelope = new_envelope(conn)?;
+    for table in &TABLES {
+        envelope
+            .tables
+            .insert(table.name.to_string(), export_table(conn, table.name, None)?);
+    }
+
+    Ok(envelope)
+}
+
+// Writes the same document as `export_envelope`, a row at a time, so memory use does not
+// grow with the database. Each row is on its own line. `map_row` may change a row of a
+// table before it is written.
+pub fn write_export(
+    conn: &Connection,
+    writer: impl Write,
+    map_row: impl FnMut(&str, &mut Map<String, Value>),
+) -> Result<(), Box<dyn Error>> {
+    write_export_of(conn, writer, None, map_row)
+}
+
+// Like `write_export`, but with only the tables and rows of `subset` when it is given
+pub fn write_export_of(
+    conn: &Connection,
+    writer: impl Write,
+    subset: Option<SubsetOwner>,
+    mut map_row: impl FnMut(&str, &mut Map<String, Value>),
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(writer);
+    let envelope = new_envelope(conn)?;
+    let conditions = subset.map(|owner| owner.get_conditions());
+
+    write!(
+        writer,
+        "{{\"format_version\":{},\"schema_version\":{},\"app_version\":{},\"created_at\":{},",
+        envelope.format_version,
+        serde_json::to_string(&envelope.schema_version)?,
+        serde_json::to_string(&envelope.app_version)?,
+        envelope.created_at
+    )?;
+    if let Some(owner) = subset {
+        write!(writer, "\"subset\":{},", serde_json::to_string(&owner)?)?;
+    }
+    writer.write_all(b"\"tables\":{")?;
+
+    let mut table_count = 0;
+    for table in &TABLES {
+        let condition = match &conditions {
+            None => None,
+            Some(conditions) => match conditions.iter().find(|(name, _)| *name == table.name) {
+                Some((_, condition)) => Some(condition.as_str()),
+                // The table holds nothing of the subset
+                None => continue,
+            },
+        };
+
+        let columns = get_columns(conn, table.name)?;
+        write!(
+            writer,
+            "{}\n{}:{{\"columns\":{},\"rows\":[",
+            if table_count > 0 { "," } else { "" },
+            serde_json::to_string(table.name)?,
+            serde_json::to_string(&columns)?
+        )?;
+        table_count += 1;
+
+        // Hashes the same bytes as `hash_rows`, the rows as one compact JSON array
+        let mut hasher = Sha256::new();
+        hasher.update(b"[");
+        let mut row_count = 0;
+        for_each_row(conn, table.name, &columns, condition, |mut row| {
+            map_row(table.name, &mut row);
+            let json = serde_json::to_vec(&row)?;
+            if row_count > 0 {
+                hasher.update(b",");
+                writer.write_all(b",")?;
             }
-            Ok(Value::Object(obj))
+            hasher.update(&json);
+            writer.write_all(b"\n")?;
+            writer.write_all(&json)?;
+            row_count += 1;
+            Ok(())
         })?;
+        hasher.update(b"]");
 
-        let table_data: Vec<Value> = rows.filter_map(Result::ok).collect();
-        json_map.insert(table.to_string(), Value::Array(table_data));
+        write!(
+            writer,
+            "\n],\"row_count\":{},\"sha256\":\"{}\"}}",
+            row_count,
+            to_hex(&hasher.finalize())
+        )?;
     }
 
-    // Write JSON to file
-    let mut file = File::create(json_path)?;
-    let json_string = serde_json::to_string_pretty(&Value::Object(json_map))?;
-    file.write_all(json_string.as_bytes())?;
+    writer.write_all(b"\n}}\n")?;
+    writer.flush()?;
 
     Ok(())
 }
 
-// Helper function to generate column names for tables
-fn get_column_names(table: &str) -> &str {
-    match table {
-        "buyers" => "id, buyer_name, address_line1, address_line2, additional_costs, is_vat_liable, used_bundle, used_loading, loading_costs, ident",
-        "sellers" => "id, seller_name, address_line1, address_line2, iban, ident, is_flat_rate, is_vat_liable, used_transport, used_logging, used_logging_non_woods, additional_costs, transport_costs, logging_costs",
-        "tree_species" => "id, tree_species_name, latin_name, tree_species_name_slo",
-        "wood_pieces" => "id, length, sequence_no, width, plate_no, seller_id, tree_species_id, min_price, bypass_min_price",
-        "wood_piece_offers" => "id, offered_price, wood_piece_id, buyer_id",
-        "settings" => "id, licitator_fixed_cost, licitator_percentage, bundle_cost",
-        "images" => "id, image_key, mime_type, data_base64",
-        _ => "",
-    }
+// Everything is read in one transaction, so the tables are of the same moment
+fn export_to_json(conn: &Connection, json_path: &str) -> Result<(), Box<dyn Error>> {
+    let tx = conn.unchecked_transaction()?;
+    write_export(&tx, File::create(json_path)?, |_, _| {})
 }
 
 #[tauri::command]
@@ -83,3 +304,105 @@ pub fn write_json(app_handle: tauri::AppHandle, file_path: String) -> Result<(),
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::{import_export_file, read_export_rows};
+    use crate::shared::{open_test_database, write_test_file};
+
+    fn export_test_file(conn: &Connection, name: &str) -> std::path::PathBuf {
+        let path = write_test_file(name, "");
+        export_to_json(conn, path.to_str().unwrap()).unwrap();
+        path
+    }
+
+    #[test]
+    fn non_finite_reals_survive_a_round_trip() {
+        let conn = open_test_database();
+        conn.execute_batch(
+            "UPDATE settings SET licitator_fixed_cost = 9e999, licitator_percentage = -9e999;",
+        )
+        .unwrap();
+        let path = export_test_file(&conn, "reals.json");
+        let contents = std::fs::read_to_string(&path).unwrap();
+
+        let imported = open_test_database();
+        let result = import_export_file(&imported, path.to_str().unwrap(), |_| Ok(()));
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        assert!(contents.contains(r#"{"$real":"inf"}"#));
+        assert!(contents.contains(r#"{"$real":"-inf"}"#));
+        let (fixed_cost, percentage): (f64, f64) = imported
+            .query_row(
+                "SELECT licitator_fixed_cost, licitator_percentage FROM settings;",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((fixed_cost, percentage), (f64::INFINITY, f64::NEG_INFINITY));
+    }
+
+    #[test]
+    fn blobs_are_read_back_as_the_same_bytes() {
+        let conn = open_test_database();
+        conn.execute_batch(
+            "INSERT INTO images (image_key, data_base64) VALUES ('binary', x'00ff10');",
+        )
+        .unwrap();
+        let path = export_test_file(&conn, "blobs.json");
+        let contents = std::fs::read_to_string(&path).unwrap();
+
+        let mut values = vec![];
+        let result = read_export_rows(path.to_str().unwrap(), "images", |_, row| {
+            if row["image_key"] == "binary" {
+                values.push(decode_value(&row["data_base64"])?);
+            }
+            Ok(())
+        });
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        assert!(contents.contains(r#"{"$blob":"AP8Q"}"#));
+        assert_eq!(
+            values,
+            [rusqlite::types::Value::Blob(vec![0x00, 0xff, 0x10])]
+        );
+    }
+
+    #[test]
+    fn every_table_has_the_checksum_of_its_rows() {
+        let conn = open_test_database();
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name) VALUES (1, 'Seller'), (2, 'Other');
+            INSERT INTO wood_pieces (id, sequence_no, width, length, seller_id, tree_species_id)
+                VALUES (1, 1, 50, 4, 1, 1);",
+        )
+        .unwrap();
+        let path = export_test_file(&conn, "checksums.json");
+        let contents = std::fs::read_to_string(&path).unwrap();
+
+        let envelope: ExportEnvelope = serde_json::from_str(&contents).unwrap();
+        assert_eq!(envelope.tables.len(), TABLES.len());
+        for (name, table) in &envelope.tables {
+            assert_eq!(table.row_count, table.rows.len(), "{}", name);
+            assert_eq!(table.sha256, hash_rows(&table.rows).unwrap(), "{}", name);
+            // The streamed file has the checksums of the tables exported in memory
+            let exported = export_table(&conn, name, None).unwrap();
+            assert_eq!(table.sha256, exported.sha256, "{}", name);
+        }
+
+        // A changed row no longer matches and the import is refused
+        std::fs::write(&path, contents.replace("\"Other\"", "\"Changed\"")).unwrap();
+        let imported = open_test_database();
+        let result = import_export_file(&imported, path.to_str().unwrap(), |_| Ok(()));
+        std::fs::remove_file(&path).unwrap();
+        let error = result.unwrap_err().to_string();
+        assert!(
+            error.contains("Data of table sellers is damaged"),
+            "{}",
+            error
+        );
+    }
+}
@@ -1,154 +1,985 @@
-use rusqlite::{Connection, Result, ToSql};
-use serde_json::{Map, Value};
-use std::fs;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
 use tauri::Manager;
-use crate::shared::{
-    SQL_STATEMENT_IMAGES, SQL_STATEMENT_SETTINGS, SQL_STATEMENT_TREE_SPECIES, get_connection,
-};
+use crate::export::{decode_value, to_hex, EXPORT_FORMAT_VERSION};
+use crate::shared::{get_connection, UncheckedSavepoint};
+use crate::snapshot::write_snapshot;
+use crate::subset::SubsetOwner;
+use crate::tables::{get_table, TABLES};
+use crate::undo::with_group;
 
 fn truncate_db(conn: &Connection) -> Result<(), Box<dyn std::error::Error>> {
-    // Define the specific import sequence
-    let import_sequence = vec![
-        "buyers",
-        "sellers",
-        "wood_pieces",
-        "wood_piece_offers",
-        "images",
-    ];
-
-    // Truncate all tables in the import sequence
-    for table in import_sequence.iter().rev() {
-        let truncate_query = format!("DELETE FROM {};", table);
+    // Truncate the tables holding auction data, children first
+    for table in TABLES.iter().rev().filter(|table| table.truncate) {
+        let truncate_query = format!("DELETE FROM {};", table.name);
         conn.execute(&truncate_query, [])?;
     }
 
-    // Check if we have any tree species
-    let tree_species_count: i32 = conn.query_row(
-        "SELECT COUNT(*) FROM tree_species;",
-        [],
-        |row| row.get(0),
-    )?;
-    if tree_species_count == 0 {
-        conn.execute_batch(SQL_STATEMENT_TREE_SPECIES)?;
+    // Restore the default rows of tables that ended up empty
+    for table in &TABLES {
+        let Some(seed_sql) = table.seed_sql else {
+            continue;
+        };
+
+        let count: i32 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM {};", table.name),
+            [],
+            |row| row.get(0),
+        )?;
+        if count == 0 {
+            conn.execute_batch(seed_sql)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Replacing tables with a subset would lose the records of everyone else
+const SUBSET_MESSAGE: &str =
+    "The file holds the records of one seller or buyer, add them with the partial import";
+
+// Where an import failed, so the operator can fix the file
+#[derive(Debug, Serialize)]
+pub struct ImportError {
+    pub table: String,
+    pub row_index: Option<usize>,
+    pub column: Option<String>,
+    pub message: String,
+}
+
+impl ImportError {
+    pub fn new(
+        table: &str,
+        row_index: Option<usize>,
+        column: Option<&str>,
+        message: impl ToString,
+    ) -> Self {
+        ImportError {
+            table: table.to_string(),
+            row_index,
+            column: column.map(str::to_string),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "table {}", self.table)?;
+        if let Some(row_index) = self.row_index {
+            write!(f, ", row {}", row_index)?;
+        }
+        if let Some(column) = &self.column {
+            write!(f, ", column {}", column)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+// How SQLite converts values stored in a column, derived from the declared type
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Affinity {
+    Integer,
+    Real,
+    Text,
+    Blob,
+    Numeric,
+}
+
+// https://www.sqlite.org/datatype3.html#determination_of_column_affinity
+fn get_affinity(declared_type: &str) -> Affinity {
+    let declared_type = declared_type.to_uppercase();
+    if declared_type.contains("INT") {
+        Affinity::Integer
+    } else if ["CHAR", "CLOB", "TEXT"]
+        .iter()
+        .any(|t| declared_type.contains(t))
+    {
+        Affinity::Text
+    } else if declared_type.contains("BLOB") || declared_type.is_empty() {
+        Affinity::Blob
+    } else if ["REAL", "FLOA", "DOUB"]
+        .iter()
+        .any(|t| declared_type.contains(t))
+    {
+        Affinity::Real
+    } else {
+        Affinity::Numeric
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TableColumns {
+    // Stored columns that can be written
+    pub affinities: HashMap<String, Affinity>,
+    // Generated columns, values for them are ignored
+    pub generated: HashSet<String>,
+}
+
+pub fn read_table_columns(
+    conn: &Connection,
+    table: &str,
+) -> Result<TableColumns, Box<dyn std::error::Error>> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_xinfo({});", table))?;
+    let rows = stmt.query_map([], |row| {
+        let name: String = row.get("name")?;
+        let declared_type: String = row.get("type")?;
+        let hidden: i64 = row.get("hidden")?;
+        Ok((name, declared_type, hidden))
+    })?;
+
+    let mut columns = TableColumns::default();
+    for row in rows {
+        let (name, declared_type, hidden) = row?;
+        // `hidden` is 2 or 3 for generated columns
+        if hidden != 0 {
+            columns.generated.insert(name);
+        } else {
+            columns
+                .affinities
+                .insert(name, get_affinity(&declared_type));
+        }
+    }
+
+    if columns.affinities.is_empty() {
+        return Err(format!("Table {} does not exist", table).into());
+    }
+
+    Ok(columns)
+}
+
+// Converts a value to the type of the column it goes into
+fn coerce_value(value: SqlValue, affinity: Affinity) -> Result<SqlValue, String> {
+    match (affinity, value) {
+        (_, SqlValue::Null) => Ok(SqlValue::Null),
+        (Affinity::Integer, SqlValue::Real(r)) if r.fract() == 0.0 && r.abs() < 9e15 => {
+            Ok(SqlValue::Integer(r as i64))
+        }
+        (Affinity::Integer, SqlValue::Text(t)) => match t.trim() {
+            "true" => Ok(SqlValue::Integer(1)),
+            "false" => Ok(SqlValue::Integer(0)),
+            t => t
+                .parse()
+                .map(SqlValue::Integer)
+                .map_err(|_| format!("Expected an integer, got {:?}", t)),
+        },
+        (Affinity::Integer, SqlValue::Real(r)) => Err(format!("Expected an integer, got {}", r)),
+        (Affinity::Real, SqlValue::Integer(i)) => Ok(SqlValue::Real(i as f64)),
+        (Affinity::Real, SqlValue::Text(t)) => t
+            .trim()
+            .parse()
+            .map(SqlValue::Real)
+            .map_err(|_| format!("Expected a number, got {:?}", t)),
+        (Affinity::Text, SqlValue::Integer(i)) => Ok(SqlValue::Text(i.to_string())),
+        (Affinity::Text, SqlValue::Real(r)) => Ok(SqlValue::Text(r.to_string())),
+        (Affinity::Integer | Affinity::Real | Affinity::Text, SqlValue::Blob(_)) => {
+            Err("Binary data is not allowed in this column".to_string())
+        }
+        (_, value) => Ok(value),
+    }
+}
+
+// Decodes a row of the file into values of the column types of `table`. Columns left
+// out of the row are left out of the INSERT as well, so they get the schema defaults.
+pub fn decode_row(
+    columns: &TableColumns,
+    table: &str,
+    row_index: usize,
+    row: &Value,
+) -> Result<Row, ImportError> {
+    let Value::Object(values) = row else {
+        return Err(ImportError::new(
+            table,
+            Some(row_index),
+            None,
+            "Expected a JSON object",
+        ));
+    };
+
+    let mut decoded = Row::new();
+    for (column, value) in values {
+        if columns.generated.contains(column) {
+            continue;
+        }
+
+        let error = |e| ImportError::new(table, Some(row_index), Some(column), e);
+        let affinity = columns
+            .affinities
+            .get(column)
+            .ok_or_else(|| error("Unknown column".to_string()))?;
+        let value = decode_value(value).and_then(|v| coerce_value(v, *affinity));
+        decoded.insert(column.clone(), value.map_err(error)?);
+    }
+
+    Ok(decoded)
+}
+
+// Inserts a decoded row and returns its id
+pub fn insert_row(conn: &Connection, table: &str, row: &Row) -> rusqlite::Result<i64> {
+    // Prepare column names and placeholders for the query
+    let col_names: Vec<&str> = row.keys().map(String::as_str).collect();
+    let placeholders: Vec<String> = (1..=col_names.len()).map(|i| format!("?{}", i)).collect();
+
+    let query = format!(
+        "INSERT INTO {} ({}) VALUES ({});",
+        table,
+        col_names.join(", "),
+        placeholders.join(", ")
+    );
+
+    // Execute the insertion, rows of a table mostly share their columns and so the statement
+    conn.prepare_cached(&query)?.execute(rusqlite::params_from_iter(row.values()))?;
+
+    Ok(conn.last_insert_rowid())
+}
+
+// Deletes all rows of `table` and restarts its ids
+pub fn clear_table(conn: &Connection, table: &str) -> rusqlite::Result<()> {
+    conn.execute_batch(&format!(
+        "DELETE FROM {0}; DELETE FROM sqlite_sequence WHERE name = '{0}';",
+        table
+    ))
+}
+
+// Takes the rows of one table as they are read, with their index
+type RowHandler<'a> =
+    &'a RefCell<dyn FnMut(usize, Value) -> Result<(), Box<dyn std::error::Error>> + 'a>;
+
+// What one pass over an export file does with its tables. The file is read more than
+// once, first to find the tables and then to write or visit them, a row at a time, so it
+// never has to be held in memory.
+#[derive(Clone, Copy)]
+enum Pass<'a> {
+    Scan(&'a RefCell<Vec<String>>),
+    Write(&'a Connection),
+    // Only the rows of the named table
+    Visit(&'a str, RowHandler<'a>),
+}
+
+// An envelope, or an old bare map of tables. Returns whose records the file holds if it
+// is a subset.
+struct ExportFile<'a>(Pass<'a>);
+
+impl<'de> DeserializeSeed<'de> for ExportFile<'_> {
+    type Value = Option<SubsetOwner>;
+
+    fn deserialize<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ExportFile<'_> {
+    type Value = Option<SubsetOwner>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an export file")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut subset = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "format_version" => {
+                    let format_version: u32 = map.next_value()?;
+                    if format_version > EXPORT_FORMAT_VERSION {
+                        return Err(de::Error::custom(format!(
+                            "Export format {} was made by a newer version of the app",
+                            format_version
+                        )));
+                    }
+                }
+                "subset" => subset = map.next_value()?,
+                "schema_version" | "app_version" | "created_at" => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+                "tables" => map.next_value_seed(ExportTables(self.0))?,
+                table => map.next_value_seed(ExportTable(table, self.0))?,
+            }
+        }
+
+        Ok(subset)
+    }
+}
+
+struct ExportTables<'a>(Pass<'a>);
+
+impl<'de> DeserializeSeed<'de> for ExportTables<'_> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ExportTables<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map of tables")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(table) = map.next_key::<String>()? {
+            map.next_value_seed(ExportTable(&table, self.0))?;
+        }
+
+        Ok(())
+    }
+}
+
+// A table of an envelope, with its checksum, or the bare list of rows of an old export
+struct ExportTable<'a, 'b>(&'b str, Pass<'a>);
+
+impl<'de> DeserializeSeed<'de> for ExportTable<'_, '_> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        match self.1 {
+            Pass::Scan(tables) => {
+                tables.borrow_mut().push(self.0.to_string());
+                IgnoredAny::deserialize(deserializer)?;
+                Ok(())
+            }
+            // Tables this version does not know are left out
+            Pass::Write(_) if get_table(self.0).is_none() => {
+                IgnoredAny::deserialize(deserializer)?;
+                Ok(())
+            }
+            Pass::Visit(table, _) if table != self.0 => {
+                IgnoredAny::deserialize(deserializer)?;
+                Ok(())
+            }
+            Pass::Write(_) | Pass::Visit(..) => deserializer.deserialize_any(self),
+        }
+    }
+}
+
+impl<'de> Visitor<'de> for ExportTable<'_, '_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a table")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<(), A::Error> {
+        ExportRows(self.0, self.1).visit_seq(seq)?;
+
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let mut row_count: Option<usize> = None;
+        let mut sha256: Option<String> = None;
+        let mut written = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "row_count" => row_count = Some(map.next_value()?),
+                "sha256" => sha256 = Some(map.next_value()?),
+                "rows" => written = Some(map.next_value_seed(ExportRows(self.0, self.1))?),
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        // Rows are already written or visited, a mismatch rolls the whole import back
+        let row_count = row_count.ok_or_else(|| de::Error::missing_field("row_count"))?;
+        let sha256 = sha256.ok_or_else(|| de::Error::missing_field("sha256"))?;
+        let (written_count, written_sha256) =
+            written.ok_or_else(|| de::Error::missing_field("rows"))?;
+        if written_count != row_count || written_sha256 != sha256 {
+            return Err(de::Error::custom(format!(
+                "Data of table {} is damaged, checksum does not match",
+                self.0
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+// Inserts or visits the rows of a table as they are read and returns their count and
+// checksum
+struct ExportRows<'a, 'b>(&'b str, Pass<'a>);
+
+impl<'de> DeserializeSeed<'de> for ExportRows<'_, '_> {
+    type Value = (usize, String);
+
+    fn deserialize<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ExportRows<'_, '_> {
+    type Value = (usize, String);
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of rows")
     }
 
-    // Check if we have settings
-    let settings_count: i32 = conn.query_row(
-        "SELECT COUNT(*) FROM settings;",
-        [],
-        |row| row.get(0),
-    )?;
-    if settings_count == 0 {
-        conn.execute_batch(SQL_STATEMENT_SETTINGS)?;
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let ExportRows(table, pass) = self;
+        let columns = match pass {
+            Pass::Write(conn) => read_table_columns(conn, table).map_err(de::Error::custom)?,
+            _ => TableColumns::default(),
+        };
+
+        // Same bytes `hash_rows` hashes
+        let mut hasher = Sha256::new();
+        hasher.update(b"[");
+        let mut row_index = 0;
+        while let Some(row) = seq.next_element::<Value>()? {
+            if row_index > 0 {
+                hasher.update(b",");
+            }
+            hasher.update(serde_json::to_vec(&row).map_err(de::Error::custom)?);
+
+            match pass {
+                Pass::Write(conn) => {
+                    decode_row(&columns, table, row_index, &row)
+                        .and_then(|row| {
+                            insert_row(conn, table, &row)
+                                .map_err(|e| ImportError::new(table, Some(row_index), None, e))
+                        })
+                        .map_err(de::Error::custom)?;
+                }
+                Pass::Visit(_, handle) => {
+                    (handle.borrow_mut())(row_index, row).map_err(de::Error::custom)?;
+                }
+                Pass::Scan(_) => {}
+            }
+            row_index += 1;
+        }
+        hasher.update(b"]");
+
+        Ok((row_index, to_hex(&hasher.finalize())))
     }
+}
+
+fn read_export_file(
+    json_path: &str,
+    pass: Pass,
+) -> Result<Option<SubsetOwner>, Box<dyn std::error::Error>> {
+    let file = BufReader::new(File::open(json_path)?);
+    let mut deserializer = serde_json::Deserializer::from_reader(file);
+    let subset = ExportFile(pass).deserialize(&mut deserializer)?;
+    deserializer.end()?;
 
-    // Check if we have image config rows
-    let images_count: i32 = conn.query_row("SELECT COUNT(*) FROM images;", [], |row| row.get(0))?;
-    if images_count == 0 {
-        conn.execute_batch(SQL_STATEMENT_IMAGES)?;
+    Ok(subset)
+}
+
+// The tables of an export file, and whose records it holds if it is a subset
+pub fn scan_export_file(
+    json_path: &str,
+) -> Result<(Vec<String>, Option<SubsetOwner>), Box<dyn std::error::Error>> {
+    let tables = RefCell::new(vec![]);
+    let subset = read_export_file(json_path, Pass::Scan(&tables))?;
+
+    Ok((tables.into_inner(), subset))
+}
+
+// Hands the rows of `table` to `handle` one at a time. The checksum of the table is
+// checked after its last row, so whatever `handle` did must be rolled back on error.
+pub fn read_export_rows(
+    json_path: &str,
+    table: &str,
+    handle: impl FnMut(usize, Value) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let handle = RefCell::new(handle);
+    read_export_file(json_path, Pass::Visit(table, &handle))?;
+
+    Ok(())
+}
+
+fn replace_tables(
+    conn: &Connection,
+    json_path: &str,
+    tables: &[String],
+    finish: impl FnOnce(&Connection) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Everything below either commits completely or is rolled back when `tx` is dropped
+    let tx = UncheckedSavepoint::new(conn)?;
+    for table in TABLES.iter().rev().map(|table| table.name) {
+        if tables.iter().any(|t| t == table) {
+            clear_table(&tx, table)?;
+        }
+    }
+    read_export_file(json_path, Pass::Write(&tx))?;
+    finish(&tx)?;
+
+    let broken: Option<(String, Option<i64>, String)> = tx
+        .query_row("PRAGMA foreign_key_check;", [], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })
+        .optional()?;
+    if let Some((table, row_id, parent_table)) = broken {
+        return Err(format!(
+            "Row {} of {} references a missing row of {}",
+            row_id.unwrap_or_default(),
+            table,
+            parent_table
+        )
+        .into());
     }
 
+    tx.commit()?;
+
     Ok(())
 }
 
+// Replaces the tables found in the export file in one transaction. `finish` runs last in
+// the same transaction, before references are checked.
+pub fn import_export_file(
+    conn: &Connection,
+    json_path: &str,
+    finish: impl FnOnce(&Connection) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tables, subset) = scan_export_file(json_path)?;
+    if subset.is_some() {
+        return Err(SUBSET_MESSAGE.into());
+    }
+
+    // Envelopes list tables by name, so children may come before their parents. Checking
+    // every row as it is inserted would then take quadratic time, so references are
+    // checked once, before committing. Inside a transaction, e.g. of an undo group, foreign
+    // keys cannot be switched off, they are deferred to its commit instead.
+    if !conn.is_autocommit() {
+        conn.pragma_update(None, "defer_foreign_keys", "ON")?;
+        return replace_tables(conn, json_path, &tables, finish);
+    }
+
+    conn.pragma_update(None, "foreign_keys", "OFF")?;
+    let result = replace_tables(conn, json_path, &tables, finish);
+    conn.pragma_update(None, "foreign_keys", "ON")?;
+
+    result
+}
+
 fn import_from_json(conn: &Connection, json_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    // Read JSON file
-    let json_content = fs::read_to_string(json_path)?;
-    let data: Map<String, Value> = serde_json::from_str(&json_content)?;
-
-    // Define the specific import sequence
-    let import_sequence = vec![
-        "settings",
-        "buyers",
-        "sellers",
-        "tree_species",
-        "wood_pieces",
-        "wood_piece_offers",
-        "images",
-    ];
-
-    // Truncate tables that are present in the JSON data
-    for table in import_sequence.iter().rev() {
-        if data.contains_key(*table) {
-            let truncate_query = format!("DELETE FROM {};", table);
-            conn.execute(&truncate_query, [])?;
-        }
-    }
-
-    // Iterate over tables in the JSON
-    for table in import_sequence {
-        if let Some(Value::Array(rows)) = data.get(table) {
-            for row in rows {
-                if let Value::Object(columns) = row {
-                    // Prepare column names and placeholders for the query
-                    let col_names: Vec<&str> = columns.keys().map(String::as_str).collect();
-                    let placeholders: Vec<String> =
-                        col_names.iter().map(|_| "?".to_string()).collect();
-
-                    let query = format!(
-                        "INSERT INTO {} ({}) VALUES ({});",
-                        table,
-                        col_names.join(", "),
-                        placeholders.join(", ")
-                    );
-
-                    // Collect values into a Vec<Option<_>> to extend their lifetimes
-                    let temp_values: Vec<Option<Box<dyn ToSql>>> = columns
-                        .values()
-                        .map(|v| match v {
-                            Value::String(s) => Some(Box::new(s.clone()) as Box<dyn ToSql>),
-                            Value::Number(n) => {
-                                if let Some(f) = n.as_f64() {
-                                    Some(Box::new(f) as Box<dyn ToSql>)
-                                } else if let Some(i) = n.as_i64() {
-                                    Some(Box::new(i) as Box<dyn ToSql>)
-                                } else {
-                                    None
-                                }
-                            }
-                            Value::Null => None,
-                            _ => None, // Unsupported types treated as NULL
-                        })
-                        .collect();
-
-                    // Create references to the `temp_values` for the query
-                    let values: Vec<&dyn ToSql> = temp_values
-                        .iter()
-                        .map(|opt| {
-                            opt.as_ref()
-                                .map(|b| b.as_ref())
-                                .unwrap_or(&rusqlite::types::Null)
-                        })
-                        .collect();
+    import_export_file(conn, json_path, |_| Ok(()))
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TableDiff {
+    pub table: String,
+    // Rows in the database that are not in the file
+    pub deleted: usize,
+    // Rows in the file that are not in the database
+    pub inserted: usize,
+    // Rows in both, matched by id or natural key, with different values
+    pub changed: usize,
+    pub unchanged: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportPreview {
+    pub tables: Vec<TableDiff>,
+    // Rows that would make the import fail
+    pub problems: Vec<ImportError>,
+}
+
+pub type Row = BTreeMap<String, SqlValue>;
+
+pub fn same_value(a: &SqlValue, b: &SqlValue) -> bool {
+    match (a, b) {
+        (SqlValue::Integer(i), SqlValue::Real(r)) | (SqlValue::Real(r), SqlValue::Integer(i)) => {
+            *i as f64 == *r
+        }
+        _ => a == b,
+    }
+}
+
+// Natural keys of a row, one for each alternative whose columns are all set
+pub fn get_keys(table: &str, row: &Row) -> Vec<String> {
+    let mut keys = vec![];
+    let natural_keys = get_table(table).map(|table| table.natural_keys).unwrap_or_default();
+    'alternatives: for (n, columns) in natural_keys.iter().enumerate() {
+        let mut key = vec![n.to_string()];
+        for column in *columns {
+            match row.get(*column) {
+                None | Some(SqlValue::Null) => continue 'alternatives,
+                // 5 and 5.0 are the same key
+                Some(SqlValue::Real(r)) if r.fract() == 0.0 => key.push(format!("{}", *r as i64)),
+                Some(SqlValue::Integer(i)) => key.push(i.to_string()),
+                Some(value) => key.push(format!("{:?}", value)),
+            }
+        }
+        keys.push(key.join("|"));
+    }
+
+    keys
+}
+
+pub fn read_rows(conn: &Connection, table: &str) -> Result<Vec<Row>, Box<dyn std::error::Error>> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM {} ORDER BY id;", table))?;
+    let column_names: Vec<String> = stmt.column_names().iter().map(|s| s.to_string()).collect();
+
+    let rows = stmt.query_map([], |row| {
+        let mut values = Row::new();
+        for (i, col) in column_names.iter().enumerate() {
+            values.insert(col.clone(), row.get(i)?);
+        }
+        Ok(values)
+    })?;
+
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
 
-                    // Execute the insertion
-                    conn.execute(&query, values.as_slice())?;
+// Compares the rows of a file with the rows of a table, one file row at a time
+struct TableDiffer {
+    table: String,
+    existing: Vec<Row>,
+    by_id: HashMap<i64, usize>,
+    by_key: HashMap<String, usize>,
+    matched: HashSet<usize>,
+    diff: TableDiff,
+}
+
+impl TableDiffer {
+    fn new(conn: &Connection, table: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let existing = read_rows(conn, table)?;
+
+        let mut by_id = HashMap::new();
+        let mut by_key = HashMap::new();
+        for (i, row) in existing.iter().enumerate() {
+            if let Some(SqlValue::Integer(id)) = row.get("id") {
+                by_id.insert(*id, i);
+            }
+            for key in get_keys(table, row) {
+                by_key.entry(key).or_insert(i);
+            }
+        }
+
+        Ok(TableDiffer {
+            table: table.to_string(),
+            existing,
+            by_id,
+            by_key,
+            matched: HashSet::new(),
+            diff: TableDiff {
+                table: table.to_string(),
+                ..Default::default()
+            },
+        })
+    }
+
+    fn add(&mut self, row: &Row) {
+        let found = match row.get("id") {
+            Some(SqlValue::Integer(id)) => self.by_id.get(id),
+            // Like the merge, a later key counts only when the earlier ones are empty
+            _ => get_keys(&self.table, row)
+                .first()
+                .and_then(|key| self.by_key.get(key)),
+        };
+
+        match found {
+            Some(&i) if self.matched.insert(i) => {
+                let same = row.iter().all(|(column, value)| {
+                    self.existing[i]
+                        .get(column)
+                        .is_some_and(|current| same_value(current, value))
+                });
+                if same {
+                    self.diff.unchanged += 1;
+                } else {
+                    self.diff.changed += 1;
                 }
             }
+            _ => self.diff.inserted += 1,
         }
     }
 
-    Ok(())
+    fn finish(mut self) -> TableDiff {
+        self.diff.deleted = self.existing.len() - self.matched.len();
+        self.diff
+    }
+}
+
+// Diffs the file against the database and runs the import for real to catch constraint
+// violations, then rolls it back. The file is read once per table, parents first.
+pub fn preview_from_json(
+    conn: &Connection,
+    json_path: &str,
+) -> Result<ImportPreview, Box<dyn std::error::Error>> {
+    let (file_tables, subset) = scan_export_file(json_path)?;
+    if subset.is_some() {
+        return Err(SUBSET_MESSAGE.into());
+    }
+
+    let mut problems: Vec<ImportError> = file_tables
+        .iter()
+        .filter(|table| get_table(table).is_none())
+        .map(|table| ImportError::new(table, None, None, "Unknown table, it will be skipped"))
+        .collect();
+
+    let tables: Vec<&str> = TABLES
+        .iter()
+        .map(|table| table.name)
+        .filter(|table| file_tables.iter().any(|t| t == table))
+        .collect();
+    // Local rows are read before the trial import clears the tables
+    let mut differs = tables
+        .iter()
+        .map(|table| TableDiffer::new(conn, table))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    {
+        let tx = conn.unchecked_transaction()?;
+        for table in tables.iter().rev() {
+            if let Err(e) = clear_table(&tx, table) {
+                problems.push(ImportError::new(table, None, None, e));
+            }
+        }
+
+        for (table, differ) in tables.iter().zip(&mut differs) {
+            let columns = read_table_columns(&tx, table)?;
+            read_export_rows(json_path, table, |row_index, row| {
+                // Broken rows are only reported, they count for nothing in the diff
+                let inserted = decode_row(&columns, table, row_index, &row).and_then(|row| {
+                    differ.add(&row);
+                    insert_row(&tx, table, &row)
+                        .map_err(|e| ImportError::new(table, Some(row_index), None, e))
+                });
+                if let Err(e) = inserted {
+                    problems.push(e);
+                }
+
+                Ok(())
+            })?;
+        }
+        tx.rollback()?;
+    }
+
+    Ok(ImportPreview {
+        tables: differs.into_iter().map(TableDiffer::finish).collect(),
+        problems,
+    })
 }
 
 #[tauri::command]
 pub fn read_json(app_handle: tauri::AppHandle, file_path: String) -> Result<(), String> {
-    let conn: Connection =
-        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+    let conn: Connection = get_connection(app_handle.clone())
+        .map_err(|e| format!("Error opening database: {}", e))?;
 
-    import_from_json(&conn, &file_path).map_err(|e| format!("Error importing database: {}", e))?;
+    with_group(&conn, "import", || import_from_json(&conn, &file_path))
+        .map_err(|e| format!("Error importing database: {}", e))?;
     println!("Data imported from JSON successfully!");
 
+    write_snapshot(&app_handle, "import").map_err(|e| format!("Error creating snapshot: {}", e))?;
+
     Ok(())
 }
 
 #[tauri::command]
-pub fn truncate_all_data(app_handle: tauri::AppHandle) -> Result<(), String> {
+pub fn preview_import(
+    app_handle: tauri::AppHandle,
+    file_path: String,
+) -> Result<ImportPreview, String> {
     let conn: Connection =
         get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
 
-    truncate_db(&conn).map_err(|e| format!("Error importing database: {}", e))?;
+    preview_from_json(&conn, &file_path).map_err(|e| format!("Error previewing import: {}", e))
+}
+
+#[tauri::command]
+pub fn truncate_all_data(app_handle: tauri::AppHandle) -> Result<(), String> {
+    let conn: Connection = get_connection(app_handle.clone())
+        .map_err(|e| format!("Error opening database: {}", e))?;
+
+    with_group(&conn, "truncate", || truncate_db(&conn))
+        .map_err(|e| format!("Error importing database: {}", e))?;
     println!("Data truncated successfully!");
 
+    write_snapshot(&app_handle, "truncate").map_err(|e| format!("Error creating snapshot: {}", e))?;
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::write_export;
+    use crate::shared::{open_test_database, write_test_file};
+
+    #[test]
+    fn preview_compares_the_file_with_the_database() {
+        let conn = open_test_database();
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name) VALUES (1, 'Seller');
+            INSERT INTO wood_pieces (id, sequence_no, plate_no, seller_id, tree_species_id)
+                VALUES (1, 1, 'A1', 1, 1), (2, 2, 'A2', 1, 1), (3, 3, 'A3', 1, 1);",
+        )
+        .unwrap();
+        let path = write_test_file("preview.json", "");
+        write_export(&conn, File::create(&path).unwrap(), |_, _| {}).unwrap();
+        conn.execute_batch(
+            "UPDATE wood_pieces SET min_price = 100 WHERE id = 1;
+            INSERT INTO wood_pieces (id, sequence_no, seller_id, tree_species_id)
+                VALUES (4, 4, 1, 1);",
+        )
+        .unwrap();
+
+        let preview = preview_from_json(&conn, path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let preview = preview.unwrap();
+
+        assert!(preview.problems.is_empty());
+        let pieces = preview
+            .tables
+            .iter()
+            .find(|diff| diff.table == "wood_pieces")
+            .unwrap();
+        assert_eq!(
+            (
+                pieces.unchanged,
+                pieces.changed,
+                pieces.inserted,
+                pieces.deleted
+            ),
+            (2, 1, 0, 1)
+        );
+        // The trial import was rolled back
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM wood_pieces;", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn failing_row_rolls_back_every_table_and_names_its_place() {
+        // Text that is not a number is kept as it is in a REAL column, so it can be exported
+        let source = open_test_database();
+        source
+            .execute_batch(
+                "INSERT INTO buyers (id, buyer_name) VALUES (1, 'Imported buyer');
+                INSERT INTO sellers (id, seller_name) VALUES (1, 'Imported seller');
+                INSERT INTO wood_pieces (id, sequence_no, width, length, seller_id, tree_species_id)
+                    VALUES (1, 1, 50, 4, 1, 1), (2, 2, 'wide', 4, 1, 1), (3, 3, 40, 3, 1, 1);",
+            )
+            .unwrap();
+        let path = write_test_file("failing_row.json", "");
+        write_export(&source, File::create(&path).unwrap(), |_, _| {}).unwrap();
+
+        let conn = open_test_database();
+        conn.execute_batch(
+            "INSERT INTO buyers (id, buyer_name) VALUES (1, 'Buyer');
+            INSERT INTO sellers (id, seller_name) VALUES (1, 'Seller');
+            INSERT INTO wood_pieces (id, sequence_no, width, length, seller_id, tree_species_id)
+                VALUES (1, 7, 60, 5, 1, 1);",
+        )
+        .unwrap();
+        let result = import_export_file(&conn, path.to_str().unwrap(), |_| Ok(()));
+        std::fs::remove_file(&path).unwrap();
+
+        let error = result.unwrap_err().to_string();
+        assert!(
+            error.contains("table wood_pieces, row 1, column width: Expected a number"),
+            "{}",
+            error
+        );
+        // Buyers and sellers came before the pieces in the file and were already replaced
+        let names: (String, String) = conn
+            .query_row(
+                "SELECT buyer_name, seller_name FROM buyers, sellers;",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(names, ("Buyer".to_string(), "Seller".to_string()));
+        let pieces: Vec<i64> = conn
+            .prepare("SELECT sequence_no FROM wood_pieces;")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(pieces, [7]);
+    }
+
+    #[test]
+    fn declared_types_have_the_affinity_of_sqlite() {
+        for (declared_type, affinity) in [
+            ("INTEGER", Affinity::Integer),
+            ("BIGINT", Affinity::Integer),
+            ("VARCHAR", Affinity::Text),
+            ("TEXT", Affinity::Text),
+            ("CLOB", Affinity::Text),
+            ("BLOB", Affinity::Blob),
+            ("", Affinity::Blob),
+            ("REAL", Affinity::Real),
+            ("DOUBLE", Affinity::Real),
+            ("FLOAT", Affinity::Real),
+            ("NUMERIC", Affinity::Numeric),
+            ("BOOLEAN", Affinity::Numeric),
+        ] {
+            assert_eq!(get_affinity(declared_type), affinity, "{}", declared_type);
+        }
+    }
+
+    #[test]
+    fn values_are_coerced_to_the_column_affinity() {
+        let text = |t: &str| SqlValue::Text(t.to_string());
+        let blob = SqlValue::Blob(vec![1, 2]);
+        for (affinity, value, expected) in [
+            (Affinity::Integer, text("5"), Ok(SqlValue::Integer(5))),
+            (Affinity::Integer, text(" 5 "), Ok(SqlValue::Integer(5))),
+            (Affinity::Integer, text("true"), Ok(SqlValue::Integer(1))),
+            (Affinity::Integer, SqlValue::Real(5.0), Ok(SqlValue::Integer(5))),
+            (Affinity::Integer, SqlValue::Real(5.5), Err(())),
+            (Affinity::Integer, text("five"), Err(())),
+            (Affinity::Integer, blob.clone(), Err(())),
+            (Affinity::Real, SqlValue::Integer(5), Ok(SqlValue::Real(5.0))),
+            (Affinity::Real, text("1.5"), Ok(SqlValue::Real(1.5))),
+            (Affinity::Real, text("wide"), Err(())),
+            (Affinity::Real, blob.clone(), Err(())),
+            (Affinity::Text, SqlValue::Integer(5), Ok(text("5"))),
+            (Affinity::Text, SqlValue::Real(1.5), Ok(text("1.5"))),
+            (Affinity::Text, blob.clone(), Err(())),
+            (Affinity::Blob, text("5"), Ok(text("5"))),
+            (Affinity::Blob, blob.clone(), Ok(blob.clone())),
+            (Affinity::Numeric, text("5"), Ok(text("5"))),
+            (Affinity::Integer, SqlValue::Null, Ok(SqlValue::Null)),
+        ] {
+            let coerced = coerce_value(value.clone(), affinity).map_err(|_| ());
+            assert_eq!(coerced, expected, "{:?} into {:?}", value, affinity);
+        }
+    }
+
+    #[test]
+    fn unknown_columns_are_rejected() {
+        let conn = open_test_database();
+        let columns = read_table_columns(&conn, "sellers").unwrap();
+        let row = serde_json::json!({ "seller_name": "Seller", "nickname": "S" });
+
+        let error = decode_row(&columns, "sellers", 3, &row).unwrap_err();
+        assert_eq!(
+            (error.table.as_str(), error.row_index, error.column.as_deref()),
+            ("sellers", Some(3), Some("nickname"))
+        );
+        assert_eq!(error.message, "Unknown column");
+
+        // Values of generated columns are ignored instead
+        let columns = read_table_columns(&conn, "wood_pieces").unwrap();
+        let row = serde_json::json!({ "width": "50", "volume": 1.0 });
+        let decoded = decode_row(&columns, "wood_pieces", 0, &row).unwrap();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded["width"], SqlValue::Real(50.0));
+    }
+}
@@ -0,0 +1,158 @@
+use rusqlite::types::Value;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::shared::get_connection;
+
+#[derive(Debug, Serialize)]
+pub struct BrokenRow {
+    pub row_id: Option<i64>,
+    // Column holding the dangling reference and its value
+    pub column: String,
+    pub value: serde_json::Value,
+    pub parent_table: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TableIntegrity {
+    pub table: String,
+    pub broken_rows: Vec<BrokenRow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IntegrityReport {
+    pub ok: bool,
+    // Messages reported by `PRAGMA integrity_check`, empty when the file is sound
+    pub integrity_errors: Vec<String>,
+    // Foreign key violations reported by `PRAGMA foreign_key_check`, grouped per table
+    pub tables: Vec<TableIntegrity>,
+}
+
+fn to_json(value: Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Integer(i) => serde_json::Value::from(i),
+        Value::Real(r) => serde_json::Value::from(r),
+        Value::Text(t) => serde_json::Value::from(t),
+        Value::Blob(_) => serde_json::Value::from("BLOB"),
+    }
+}
+
+// Maps the foreign key ids of a table to the column holding the reference
+fn get_foreign_key_columns(
+    conn: &Connection,
+    table: &str,
+) -> Result<BTreeMap<i64, String>, Box<dyn Error>> {
+    let mut stmt = conn.prepare(&format!("PRAGMA foreign_key_list({});", table))?;
+    let rows = stmt.query_map([], |row| {
+        let id: i64 = row.get("id")?;
+        let from: String = row.get("from")?;
+        Ok((id, from))
+    })?;
+
+    Ok(rows.collect::<Result<BTreeMap<_, _>, _>>()?)
+}
+
+pub fn check_db_integrity(conn: &Connection) -> Result<IntegrityReport, Box<dyn Error>> {
+    let integrity_errors: Vec<String> = {
+        let mut stmt = conn.prepare("PRAGMA integrity_check;")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        rows.collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|message| message != "ok")
+            .collect()
+    };
+
+    // (table, rowid, parent table, foreign key id)
+    let violations: Vec<(String, Option<i64>, String, i64)> = {
+        let mut stmt = conn.prepare("PRAGMA foreign_key_check;")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?;
+        rows.collect::<Result<Vec<_>, _>>()?
+    };
+
+    let mut tables: BTreeMap<String, Vec<BrokenRow>> = BTreeMap::new();
+    for (table, row_id, parent_table, fk_id) in violations {
+        let column = get_foreign_key_columns(conn, &table)?
+            .remove(&fk_id)
+            .unwrap_or_default();
+
+        let value = match (row_id, column.is_empty()) {
+            (Some(id), false) => conn.query_row(
+                &format!("SELECT {} FROM {} WHERE rowid = ?1;", column, table),
+                [id],
+                |row| row.get::<_, Value>(0),
+            )?,
+            _ => Value::Null,
+        };
+
+        tables.entry(table).or_default().push(BrokenRow {
+            row_id,
+            column,
+            value: to_json(value),
+            parent_table,
+        });
+    }
+
+    let tables: Vec<TableIntegrity> = tables
+        .into_iter()
+        .map(|(table, broken_rows)| TableIntegrity { table, broken_rows })
+        .collect();
+
+    Ok(IntegrityReport {
+        ok: integrity_errors.is_empty() && tables.is_empty(),
+        integrity_errors,
+        tables,
+    })
+}
+
+#[tauri::command]
+pub fn check_integrity(app_handle: tauri::AppHandle) -> Result<IntegrityReport, String> {
+    let conn: Connection =
+        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+
+    check_db_integrity(&conn).map_err(|e| format!("Error checking database integrity: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::open_test_database;
+
+    #[test]
+    fn dangling_seller_is_reported_with_its_row_column_and_parent() {
+        let conn = open_test_database();
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name) VALUES (1, 'Seller');
+            INSERT INTO wood_pieces (id, sequence_no, seller_id, tree_species_id)
+                VALUES (1, 1, 1, 1);",
+        )
+        .unwrap();
+        assert!(check_db_integrity(&conn).unwrap().ok);
+
+        // Only possible with the foreign keys switched off, e.g. in a file edited by hand
+        conn.pragma_update(None, "foreign_keys", "OFF").unwrap();
+        conn.execute(
+            "INSERT INTO wood_pieces (id, sequence_no, seller_id, tree_species_id)
+            VALUES (7, 2, 42, 1);",
+            [],
+        )
+        .unwrap();
+        conn.pragma_update(None, "foreign_keys", "ON").unwrap();
+
+        let report = check_db_integrity(&conn).unwrap();
+        assert!(!report.ok);
+        assert!(report.integrity_errors.is_empty());
+        assert_eq!(report.tables.len(), 1);
+        assert_eq!(report.tables[0].table, "wood_pieces");
+        let broken_rows = &report.tables[0].broken_rows;
+        assert_eq!(broken_rows.len(), 1);
+        assert_eq!(broken_rows[0].row_id, Some(7));
+        assert_eq!(broken_rows[0].column, "seller_id");
+        assert_eq!(broken_rows[0].value, serde_json::json!(42));
+        assert_eq!(broken_rows[0].parent_table, "sellers");
+    }
+}
@@ -0,0 +1,563 @@
+use rusqlite::{params, Connection, OpenFlags};
+use serde::Serialize;
+use sha2::{Digest, Sha384};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+use tauri::Manager;
+
+use crate::commands::load_database;
+use crate::integrity::check_db_integrity;
+use crate::migrations::{get_migrations, get_schema_version, get_triggers_sql};
+use crate::restore::{
+    open_expected_database, read_schema, read_schema_version, validate_backup_file, BackupReport,
+};
+use crate::shared::DB_NAME;
+use crate::snapshot::count_rows;
+use crate::tables::{get_table, TABLES};
+
+const LEGACY_PREFIX: &str = "main_database_v";
+const LEGACY_EXTENSION: &str = ".db";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LegacyDatabase {
+    pub file_name: String,
+    // The NN of main_database_vNN.db
+    pub file_version: u32,
+    // Highest migration the SQL plugin recorded in it
+    pub schema_version: Option<i64>,
+    pub size_bytes: u64,
+    // Unix timestamp (seconds) of the last write
+    pub modified_at: u64,
+    pub row_counts: BTreeMap<String, i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LegacyMigrationReport {
+    // Upgrade steps applied to the old database, in order
+    pub steps: Vec<String>,
+    pub report: BackupReport,
+}
+
+// Databases found next to the working one when the app started
+#[derive(Default)]
+pub struct LegacyDatabases(Mutex<Vec<LegacyDatabase>>);
+
+fn parse_file_version(file_name: &str) -> Option<u32> {
+    file_name
+        .strip_prefix(LEGACY_PREFIX)?
+        .strip_suffix(LEGACY_EXTENSION)?
+        .parse()
+        .ok()
+}
+
+fn get_app_data_dir(app_handle: &tauri::AppHandle) -> Result<PathBuf, String> {
+    app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))
+}
+
+fn inspect_legacy_database(path: &Path) -> Result<LegacyDatabase, Box<dyn Error>> {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+        .to_string();
+    let metadata = fs::metadata(path)?;
+    let modified_at = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+    Ok(LegacyDatabase {
+        file_version: parse_file_version(&file_name).unwrap_or(0),
+        file_name,
+        schema_version: read_schema_version(&conn)?,
+        size_bytes: metadata.len(),
+        modified_at,
+        row_counts: count_rows(&conn)?,
+    })
+}
+
+// Finds main_database_vNN.db files left behind by versions of the app that still
+// switched to a new file for every schema change
+pub fn scan_legacy_databases(app_data_dir: &Path) -> Result<Vec<LegacyDatabase>, Box<dyn Error>> {
+    if !app_data_dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut databases = vec![];
+    for entry in fs::read_dir(app_data_dir)?.filter_map(Result::ok) {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if file_name == DB_NAME || parse_file_version(&file_name).is_none() {
+            continue;
+        }
+
+        match inspect_legacy_database(&entry.path()) {
+            Ok(database) => databases.push(database),
+            Err(e) => println!("Failed to inspect {}: {}", file_name, e),
+        }
+    }
+    databases.sort_by_key(|d| Reverse(d.file_version));
+
+    Ok(databases)
+}
+
+// Runs the scan in the background so a slow disk does not delay startup
+pub fn start_legacy_scan(app_handle: tauri::AppHandle) {
+    std::thread::spawn(move || {
+        let databases = get_app_data_dir(&app_handle)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|dir| scan_legacy_databases(&dir));
+
+        match databases {
+            Ok(databases) => {
+                let state = app_handle.state::<LegacyDatabases>();
+                *state.0.lock().unwrap() = databases;
+            }
+            Err(e) => println!("Failed to scan for old databases: {}", e),
+        }
+    });
+}
+
+fn column_definition(
+    expected: &Connection,
+    table: &str,
+    column: &str,
+) -> Result<(String, bool), Box<dyn Error>> {
+    // `hidden` is 2 or 3 for generated columns, which ALTER TABLE cannot add
+    let (column_type, default, hidden): (String, Option<String>, i64) = expected.query_row(
+        &format!(
+            "SELECT type, dflt_value, hidden FROM pragma_table_xinfo('{}') WHERE name = ?1;",
+            table
+        ),
+        [column],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+
+    let definition = match default {
+        Some(default) => format!("{} {} DEFAULT {}", column, column_type, default),
+        None => format!("{} {}", column, column_type),
+    };
+
+    Ok((definition, hidden != 0))
+}
+
+fn get_create_sql(expected: &Connection, table: &str) -> Result<String, Box<dyn Error>> {
+    Ok(expected.query_row(
+        "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?1;",
+        [table],
+        |row| row.get(0),
+    )?)
+}
+
+// Step 1: create missing tables and add missing columns, rebuilding tables whose missing
+// columns are generated. Foreign keys are off, so tables can be swapped freely.
+fn upgrade_tables(conn: &Connection, steps: &mut Vec<String>) -> Result<(), Box<dyn Error>> {
+    let expected = open_expected_database()?;
+    let expected_schema = read_schema(&expected)?;
+    let schema = read_schema(conn)?;
+
+    for (table, columns) in &expected_schema {
+        // The undo log tables are created and extended by the migrations of step 2, which
+        // would fail on columns added here
+        if get_table(table).is_none() {
+            continue;
+        }
+
+        let Some(found) = schema.get(table) else {
+            conn.execute_batch(&get_create_sql(&expected, table)?)?;
+            steps.push(format!("Created table {}", table));
+            continue;
+        };
+
+        let mut rebuild = false;
+        for column in columns.difference(found) {
+            let (definition, generated) = column_definition(&expected, table, column)?;
+            if generated {
+                rebuild = true;
+                continue;
+            }
+
+            conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {};", table, definition))?;
+            steps.push(format!("Added column {}.{}", table, column));
+        }
+
+        if rebuild {
+            // Generated columns are computed, copy only the stored ones both versions share
+            let mut stmt = expected.prepare(&format!(
+                "SELECT name FROM pragma_table_xinfo('{}') WHERE hidden = 0;",
+                table
+            ))?;
+            let copied: Vec<String> = stmt
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<Result<BTreeSet<_>, _>>()?
+                .intersection(found)
+                .cloned()
+                .collect();
+
+            // legacy_alter_table keeps the foreign keys of other tables pointing at `table`
+            conn.execute_batch(&format!(
+                "PRAGMA legacy_alter_table = ON;
+                ALTER TABLE {0} RENAME TO {0}_old;
+                {1};
+                INSERT INTO {0} ({2}) SELECT {2} FROM {0}_old;
+                DROP TABLE {0}_old;
+                PRAGMA legacy_alter_table = OFF;",
+                table,
+                get_create_sql(&expected, table)?,
+                copied.join(", ")
+            ))?;
+            steps.push(format!("Rebuilt table {}", table));
+        }
+    }
+
+    Ok(())
+}
+
+// Step 2: run the migrations the old app did not know about, by version
+fn apply_pending_migrations(
+    conn: &Connection,
+    steps: &mut Vec<String>,
+) -> Result<(), Box<dyn Error>> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS _sqlx_migrations (
+            version BIGINT PRIMARY KEY,
+            description TEXT NOT NULL,
+            installed_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            success BOOLEAN NOT NULL,
+            checksum BLOB NOT NULL,
+            execution_time BIGINT NOT NULL
+        );",
+    )?;
+
+    let applied: BTreeSet<i64> = {
+        let mut stmt = conn.prepare("SELECT version FROM _sqlx_migrations WHERE success = 1;")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect::<Result<_, _>>()?
+    };
+
+    let mut migrations = get_migrations();
+    migrations.sort_by_key(|m| m.version);
+
+    for migration in migrations.iter().filter(|m| !applied.contains(&m.version)) {
+        conn.execute_batch(migration.sql)?;
+        steps.push(format!(
+            "Applied migration {} ({})",
+            migration.version, migration.description
+        ));
+    }
+
+    // Triggers of rebuilt tables are gone and old ones may list outdated columns
+    for table in &TABLES {
+        conn.execute_batch(&get_triggers_sql(table.name))?;
+    }
+
+    // Record the migrations exactly as the SQL plugin would, so it accepts the database.
+    // Migrations recorded by older apps may have different SQL and would be refused.
+    conn.execute("DELETE FROM _sqlx_migrations;", [])?;
+    for migration in &migrations {
+        let checksum = Sha384::digest(migration.sql.as_bytes()).to_vec();
+        conn.execute(
+            "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+            VALUES (?1, ?2, TRUE, ?3, -1);",
+            params![migration.version, migration.description, checksum],
+        )?;
+    }
+    conn.pragma_update(None, "user_version", get_schema_version())?;
+
+    Ok(())
+}
+
+// Brings a copy of an old database up to the current schema
+pub fn upgrade_legacy_database(conn: &Connection) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut steps = vec![];
+
+    conn.pragma_update(None, "foreign_keys", "OFF")?;
+    let tx = conn.unchecked_transaction()?;
+    upgrade_tables(&tx, &mut steps)?;
+    apply_pending_migrations(&tx, &mut steps)?;
+    tx.commit()?;
+    conn.pragma_update(None, "foreign_keys", "ON")?;
+
+    // Old versions did not enforce foreign keys, report what is broken instead of failing
+    let integrity = check_db_integrity(conn)?;
+    for table in integrity.tables {
+        steps.push(format!(
+            "Found {} rows with broken references in {}",
+            table.broken_rows.len(),
+            table.table
+        ));
+    }
+
+    Ok(steps)
+}
+
+// Copies the database at `src` to `dst`, replacing whatever is there
+pub fn copy_database(src: &Path, dst: &Path) -> Result<Connection, Box<dyn Error>> {
+    let _ = fs::remove_file(dst);
+
+    let src = Connection::open_with_flags(src, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut conn = Connection::open(dst)?;
+    {
+        let backup = rusqlite::backup::Backup::new(&src, &mut conn)?;
+        backup.run_to_completion(100, Duration::from_millis(50), None)?;
+    }
+
+    Ok(conn)
+}
+
+#[tauri::command]
+pub fn list_legacy_databases(
+    state: tauri::State<'_, LegacyDatabases>,
+) -> Result<Vec<LegacyDatabase>, String> {
+    Ok(state.0.lock().map_err(|e| e.to_string())?.clone())
+}
+
+#[tauri::command(async)]
+pub fn migrate_legacy_database(
+    app_handle: tauri::AppHandle,
+    file_name: String,
+) -> Result<LegacyMigrationReport, String> {
+    if file_name == DB_NAME || parse_file_version(&file_name).is_none() {
+        return Err(format!("Not an old database: {}", file_name));
+    }
+
+    let app_data_dir = get_app_data_dir(&app_handle)?;
+    let legacy_path = app_data_dir.join(&file_name);
+    if !legacy_path.exists() {
+        return Err(format!("Database does not exist: {}", file_name));
+    }
+
+    // Upgrade a copy, the old file stays untouched. The copy is upgraded here and then loaded
+    // as it is, restore_database would upgrade it a second time.
+    let upgrade_path = app_data_dir.join(format!("{}.upgrade", file_name));
+    let steps = copy_database(&legacy_path, &upgrade_path)
+        .and_then(|conn| upgrade_legacy_database(&conn))
+        .map_err(|e| format!("Error upgrading {}: {}", file_name, e));
+
+    let result = steps.and_then(|steps| {
+        let report = validate_backup_file(&upgrade_path).map_err(|e| e.to_string())?;
+        load_database(&app_handle, &upgrade_path, "before_legacy_migration")
+            .map_err(|e| e.to_string())?;
+        Ok(LegacyMigrationReport { steps, report })
+    });
+
+    let _ = fs::remove_file(&upgrade_path);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Auction tables shaped like a main_database_v9.db: no settings and no images yet, pieces
+    // without the generated volume and without bypass_min_price, buyers without an ident
+    const V9_SQL: &str = "
+        CREATE TABLE buyers (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            buyer_name VARCHAR, address_line1 VARCHAR, address_line2 VARCHAR,
+            additional_costs REAL, is_vat_liable INTEGER DEFAULT 1, used_bundle INTEGER DEFAULT 1,
+            used_loading INTEGER DEFAULT 1, loading_costs REAL DEFAULT 5.00
+        );
+        CREATE TABLE sellers (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            seller_name VARCHAR, address_line1 VARCHAR, address_line2 VARCHAR, iban VARCHAR,
+            ident VARCHAR, is_flat_rate INTEGER, is_vat_liable INTEGER, used_transport INTEGER,
+            used_logging INTEGER, used_logging_non_woods INTEGER, additional_costs REAL,
+            transport_costs REAL, logging_costs REAL
+        );
+        CREATE TABLE tree_species (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            tree_species_name VARCHAR, latin_name VARCHAR, tree_species_name_slo VARCHAR
+        );
+        CREATE TABLE wood_pieces (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            length REAL, sequence_no INTEGER, width REAL, plate_no VARCHAR,
+            seller_id INTEGER, tree_species_id INTEGER, min_price REAL,
+            FOREIGN KEY(seller_id) REFERENCES sellers(id) ON DELETE RESTRICT,
+            FOREIGN KEY(tree_species_id) REFERENCES tree_species(id)
+        );
+        CREATE TABLE wood_piece_offers (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            offered_price REAL, wood_piece_id INTEGER, buyer_id INTEGER,
+            FOREIGN KEY(buyer_id) REFERENCES buyers(id) ON DELETE RESTRICT,
+            FOREIGN KEY(wood_piece_id) REFERENCES wood_pieces(id) ON DELETE RESTRICT
+        );
+        INSERT INTO buyers (id, buyer_name) VALUES (1, 'Buyer');
+        INSERT INTO sellers (id, seller_name) VALUES (1, 'Seller');
+        INSERT INTO tree_species (id, tree_species_name) VALUES (1, 'Oak');
+        INSERT INTO wood_pieces (id, length, sequence_no, width, plate_no, seller_id, tree_species_id)
+            VALUES (1, 4, 1, 50, 'A1', 1, 1);
+        INSERT INTO wood_piece_offers (id, offered_price, wood_piece_id, buyer_id)
+            VALUES (1, 300, 1, 1);";
+
+    // A v10 database has the settings and bypass_min_price
+    const V10_SQL: &str = "
+        CREATE TABLE settings (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            licitator_fixed_cost REAL, licitator_percentage REAL, bundle_cost REAL
+        );
+        ALTER TABLE wood_pieces ADD COLUMN bypass_min_price INTEGER;";
+
+    // A v11 database has the computed volume, which takes rebuilding the pieces, and buyers
+    // with an ident
+    const V11_SQL: &str = "
+        ALTER TABLE wood_pieces RENAME TO wood_pieces_v10;
+        CREATE TABLE wood_pieces (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            length REAL, sequence_no INTEGER, width REAL,
+            volume REAL AS (round(3.14159265359 * width * 0.5 * 0.01 * width * 0.5 * 0.01 * length, 2)) STORED,
+            plate_no VARCHAR, seller_id INTEGER, tree_species_id INTEGER, min_price REAL,
+            bypass_min_price INTEGER,
+            FOREIGN KEY(seller_id) REFERENCES sellers(id) ON DELETE RESTRICT,
+            FOREIGN KEY(tree_species_id) REFERENCES tree_species(id)
+        );
+        INSERT INTO wood_pieces (id, length, sequence_no, width, plate_no, seller_id, tree_species_id)
+            SELECT id, length, sequence_no, width, plate_no, seller_id, tree_species_id
+            FROM wood_pieces_v10;
+        DROP TABLE wood_pieces_v10;
+        ALTER TABLE buyers ADD COLUMN ident VARCHAR DEFAULT \"\";";
+
+    fn open_legacy_database(version: u32) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(V9_SQL).unwrap();
+        if version >= 10 {
+            conn.execute_batch(V10_SQL).unwrap();
+        }
+        if version >= 11 {
+            // Keeps the offers pointing at wood_pieces while it is swapped
+            conn.execute_batch("PRAGMA legacy_alter_table = ON;")
+                .unwrap();
+            conn.execute_batch(V11_SQL).unwrap();
+            conn.execute_batch("PRAGMA legacy_alter_table = OFF;")
+                .unwrap();
+        }
+        conn
+    }
+
+    fn upgrade(conn: &Connection) -> Vec<String> {
+        let mut steps = vec![];
+        upgrade_tables(conn, &mut steps).unwrap();
+        steps
+    }
+
+    // The auction tables of `conn` have every column of the current schema
+    fn assert_tables_upgraded(conn: &Connection) {
+        let schema = read_schema(conn).unwrap();
+        let expected = read_schema(&open_expected_database().unwrap()).unwrap();
+        for table in &TABLES {
+            assert_eq!(
+                schema.get(table.name),
+                expected.get(table.name),
+                "{}",
+                table.name
+            );
+        }
+    }
+
+    fn assert_references_intact(conn: &Connection) {
+        let sql: String = conn
+            .query_row(
+                "SELECT sql FROM sqlite_master WHERE name = 'wood_piece_offers';",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(sql.contains("REFERENCES wood_pieces(id)"), "{}", sql);
+        assert!(!sql.contains("_old"), "{}", sql);
+
+        let broken: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_foreign_key_check;",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(broken, 0);
+    }
+
+    #[test]
+    fn missing_tables_are_created_and_pieces_rebuilt_in_a_v9_database() {
+        let conn = open_legacy_database(9);
+        let steps = upgrade(&conn);
+
+        for step in [
+            "Added column buyers.ident",
+            "Added column wood_pieces.bypass_min_price",
+            "Rebuilt table wood_pieces",
+            "Created table settings",
+            "Created table images",
+        ] {
+            assert!(steps.contains(&step.to_string()), "{} in {:?}", step, steps);
+        }
+        assert_tables_upgraded(&conn);
+        assert_references_intact(&conn);
+    }
+
+    #[test]
+    fn generated_volume_is_added_by_rebuilding_a_v10_database() {
+        let conn = open_legacy_database(10);
+        let steps = upgrade(&conn);
+
+        assert!(steps.contains(&"Rebuilt table wood_pieces".to_string()));
+        assert!(!steps
+            .iter()
+            .any(|step| step.starts_with("Created table settings")));
+        assert_tables_upgraded(&conn);
+        assert_references_intact(&conn);
+
+        let (plate_no, volume, offered_price): (String, f64, f64) = conn
+            .query_row(
+                "SELECT plate_no, volume, offered_price FROM wood_pieces
+                JOIN wood_piece_offers ON wood_piece_offers.wood_piece_id = wood_pieces.id;",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            (plate_no.as_str(), volume, offered_price),
+            ("A1", 0.79, 300.0)
+        );
+    }
+
+    #[test]
+    fn v11_database_only_gets_the_missing_table() {
+        let conn = open_legacy_database(11);
+        let steps = upgrade(&conn);
+
+        assert_eq!(steps, ["Created table images"]);
+        assert_tables_upgraded(&conn);
+
+        let ident: String = conn
+            .query_row("SELECT ident FROM buyers WHERE id = 1;", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(ident, "");
+    }
+
+    #[test]
+    fn v9_database_is_upgraded_to_the_current_schema() {
+        let conn = open_legacy_database(9);
+        upgrade_legacy_database(&conn).unwrap();
+
+        let mut schema = read_schema(&conn).unwrap();
+        schema.remove("_sqlx_migrations");
+        let expected = read_schema(&open_expected_database().unwrap()).unwrap();
+        assert_eq!(schema, expected);
+        assert_eq!(
+            read_schema_version(&conn).unwrap(),
+            Some(get_schema_version())
+        );
+    }
+}
@@ -1,10 +1,27 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
+pub mod archive;
+pub mod audit;
 pub mod commands;
+pub mod csv_export;
+pub mod csv_import;
 use std::error::Error;
 pub mod export;
 pub mod import;
+pub mod integrity;
+pub mod legacy;
+pub mod merge;
+pub mod migrations;
+pub mod models;
+pub mod restore;
+pub mod repository;
+pub mod settlement;
 pub mod shared;
+pub mod snapshot;
+pub mod subset;
+pub mod tables;
+pub mod undo;
+pub mod xlsx_export;
 
 fn main() -> Result<(), Box<dyn Error>> {
     licitacija_lib::run()
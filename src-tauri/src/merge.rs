@@ -0,0 +1,515 @@
+use rusqlite::types::Value as SqlValue;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::import::{
+    decode_row, get_keys, insert_row, read_export_rows, read_rows, read_table_columns, same_value,
+    scan_export_file, ImportError, Row, TableColumns,
+};
+use crate::shared::{get_connection, UncheckedSavepoint};
+use crate::snapshot::write_snapshot;
+use crate::tables::{get_table, TABLES};
+use crate::undo::with_group;
+
+// What to do with an incoming row that matches a local row with different values
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    KeepLocal,
+    TakeIncoming,
+    // Change nothing if there is any conflict, only list them
+    Report,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TableMergeSummary {
+    pub table: String,
+    pub inserted: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    // Conflicting rows where the local values were kept
+    pub kept_local: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MergeConflict {
+    pub table: String,
+    pub row_index: usize,
+    pub local_id: i64,
+    // Columns whose incoming value differs from the local one
+    pub columns: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MergeSummary {
+    // False when the merge was rolled back because of conflicts under the report policy
+    pub applied: bool,
+    pub tables: Vec<TableMergeSummary>,
+    pub conflicts: Vec<MergeConflict>,
+    // Plates of appended pieces that another piece has too, they are added anyway and have
+    // to be told apart by hand
+    pub plate_collisions: Vec<String>,
+}
+
+// Incoming id to local id, per table
+type IdMap = HashMap<String, HashMap<i64, i64>>;
+
+// Points references of an incoming row at the local copies of the rows they reference
+fn remap_foreign_keys(
+    table: &str,
+    row_index: usize,
+    row: &mut Row,
+    foreign_keys: &[(&str, &str)],
+    id_map: &IdMap,
+) -> Result<(), ImportError> {
+    for (column, parent_table) in foreign_keys {
+        // References to tables missing from the file are taken to be local ids
+        let Some(ids) = id_map.get(*parent_table) else {
+            continue;
+        };
+
+        if let Some(SqlValue::Integer(id)) = row.get(*column) {
+            let local_id = ids.get(id).ok_or_else(|| {
+                ImportError::new(
+                    table,
+                    Some(row_index),
+                    Some(column),
+                    format!("{} with id {} is not in the file", parent_table, id),
+                )
+            })?;
+            row.insert(column.to_string(), SqlValue::Integer(*local_id));
+        }
+    }
+
+    Ok(())
+}
+
+fn update_row(conn: &Connection, table: &str, id: i64, row: &Row) -> rusqlite::Result<()> {
+    let set_statements: Vec<String> = row
+        .keys()
+        .enumerate()
+        .map(|(i, column)| format!("{} = ?{}", column, i + 1))
+        .collect();
+
+    let query = format!(
+        "UPDATE {} SET {} WHERE id = ?{};",
+        table,
+        set_statements.join(", "),
+        row.len() + 1
+    );
+    let values = row.values().cloned().chain([SqlValue::Integer(id)]);
+    conn.execute(&query, rusqlite::params_from_iter(values))?;
+
+    Ok(())
+}
+
+// Merges the rows of one table as they are read. With `append`, every row is inserted as
+// a new one instead of being matched by its keys.
+struct TableMerge<'a> {
+    conn: &'a Connection,
+    table: &'a str,
+    columns: TableColumns,
+    foreign_keys: &'static [(&'static str, &'static str)],
+    policy: ConflictPolicy,
+    append: bool,
+    local_rows: HashMap<i64, Row>,
+    by_key: HashMap<String, i64>,
+    // Incoming id to local id
+    ids: HashMap<i64, i64>,
+    summary: TableMergeSummary,
+}
+
+impl<'a> TableMerge<'a> {
+    fn new(
+        conn: &'a Connection,
+        table: &'a str,
+        policy: ConflictPolicy,
+        append: bool,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut local_rows = HashMap::new();
+        let mut by_key = HashMap::new();
+        for row in read_rows(conn, table)? {
+            let Some(SqlValue::Integer(id)) = row.get("id").cloned() else {
+                continue;
+            };
+            for key in get_keys(table, &row) {
+                by_key.entry(key).or_insert(id);
+            }
+            local_rows.insert(id, row);
+        }
+
+        Ok(TableMerge {
+            conn,
+            table,
+            columns: read_table_columns(conn, table)?,
+            foreign_keys: get_table(table)
+                .map(|table| table.foreign_keys)
+                .unwrap_or_default(),
+            policy,
+            append,
+            local_rows,
+            by_key,
+            ids: HashMap::new(),
+            summary: TableMergeSummary {
+                table: table.to_string(),
+                ..Default::default()
+            },
+        })
+    }
+
+    fn merge_row(
+        &mut self,
+        row_index: usize,
+        row: &Value,
+        id_map: &IdMap,
+        conflicts: &mut Vec<MergeConflict>,
+    ) -> Result<(), Box<dyn Error>> {
+        let table = self.table;
+        let mut row = decode_row(&self.columns, table, row_index, row)?;
+        let incoming_id = match row.remove("id") {
+            Some(SqlValue::Integer(id)) => Some(id),
+            _ => None,
+        };
+        remap_foreign_keys(table, row_index, &mut row, self.foreign_keys, id_map)?;
+
+        // Only the first key whose columns are set is compared, a later key is tried only
+        // when the earlier ones are empty, a piece with an unknown plate is a new piece even
+        // if its sequence number is taken
+        let found = if self.append {
+            None
+        } else {
+            get_keys(table, &row)
+                .first()
+                .and_then(|key| self.by_key.get(key))
+                .copied()
+        };
+
+        let local_id = match found {
+            Some(local_id) => {
+                let local = &self.local_rows[&local_id];
+                let columns: Vec<String> = row
+                    .iter()
+                    .filter(|(column, value)| {
+                        !local.get(*column).is_some_and(|v| same_value(v, value))
+                    })
+                    .map(|(column, _)| column.clone())
+                    .collect();
+
+                if columns.is_empty() {
+                    self.summary.unchanged += 1;
+                } else if self.policy == ConflictPolicy::TakeIncoming {
+                    update_row(self.conn, table, local_id, &row)
+                        .map_err(|e| ImportError::new(table, Some(row_index), None, e))?;
+                    self.summary.updated += 1;
+                } else {
+                    self.summary.kept_local += 1;
+                    conflicts.push(MergeConflict {
+                        table: table.to_string(),
+                        row_index,
+                        local_id,
+                        columns,
+                    });
+                }
+
+                local_id
+            }
+            None => {
+                let local_id = insert_row(self.conn, table, &row)
+                    .map_err(|e| ImportError::new(table, Some(row_index), None, e))?;
+                self.summary.inserted += 1;
+
+                // Later rows of the file with the same key match this one
+                for key in get_keys(table, &row) {
+                    self.by_key.entry(key).or_insert(local_id);
+                }
+                self.local_rows.insert(local_id, row);
+
+                local_id
+            }
+        };
+
+        if let Some(incoming_id) = incoming_id {
+            self.ids.insert(incoming_id, local_id);
+        }
+
+        Ok(())
+    }
+}
+
+// Merges the tables of an export file, reading it once per table, parents first so
+// references can be pointed at the local rows. Rows of `append_tables` are always added,
+// rows of other tables only when no local row has the same keys. `prepare` can change
+// each row before it is merged.
+pub fn merge_export_file(
+    conn: &Connection,
+    json_path: &str,
+    file_tables: &[String],
+    policy: ConflictPolicy,
+    append_tables: &[&str],
+    mut prepare: impl FnMut(&str, usize, &mut Value),
+) -> Result<MergeSummary, Box<dyn Error>> {
+    let tx = UncheckedSavepoint::new(conn)?;
+
+    let mut id_map = IdMap::new();
+    let mut tables = vec![];
+    let mut conflicts = vec![];
+    for table in TABLES.iter().map(|table| table.name) {
+        if !file_tables.iter().any(|t| t == table) {
+            continue;
+        }
+
+        let mut merge = TableMerge::new(&tx, table, policy, append_tables.contains(&table))?;
+        read_export_rows(json_path, table, |row_index, mut row| {
+            prepare(table, row_index, &mut row);
+            merge.merge_row(row_index, &row, &id_map, &mut conflicts)
+        })?;
+
+        id_map.insert(table.to_string(), merge.ids);
+        tables.push(merge.summary);
+    }
+
+    let applied = policy != ConflictPolicy::Report || conflicts.is_empty();
+    if applied {
+        tx.commit()?;
+    }
+
+    Ok(MergeSummary {
+        applied,
+        tables,
+        conflicts,
+        plate_collisions: vec![],
+    })
+}
+
+pub fn merge_from_json(
+    conn: &Connection,
+    json_path: &str,
+    policy: ConflictPolicy,
+) -> Result<MergeSummary, Box<dyn Error>> {
+    let (file_tables, _) = scan_export_file(json_path)?;
+
+    merge_export_file(conn, json_path, &file_tables, policy, &[], |_, _, _| {})
+}
+
+#[tauri::command]
+pub fn merge_json(
+    app_handle: tauri::AppHandle,
+    file_path: String,
+    policy: ConflictPolicy,
+) -> Result<MergeSummary, String> {
+    let conn: Connection =
+        get_connection(app_handle.clone()).map_err(|e| format!("Error opening database: {}", e))?;
+
+    let summary = with_group(&conn, "merge", || {
+        merge_from_json(&conn, &file_path, policy)
+    })
+    .map_err(|e| format!("Error merging database: {}", e))?;
+
+    if summary.applied {
+        write_snapshot(&app_handle, "merge")
+            .map_err(|e| format!("Error creating snapshot: {}", e))?;
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::{open_test_database, write_test_file};
+    use serde_json::json;
+
+    fn read_plates(conn: &Connection) -> Vec<(i64, Option<String>)> {
+        let mut stmt = conn
+            .prepare("SELECT sequence_no, plate_no FROM wood_pieces ORDER BY id;")
+            .unwrap();
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        rows.collect::<Result<_, _>>().unwrap()
+    }
+
+    fn merge_file(conn: &Connection, file: Value, policy: ConflictPolicy) -> MergeSummary {
+        let path = write_test_file("merge.json", &file.to_string());
+        let summary = merge_from_json(conn, path.to_str().unwrap(), policy);
+        std::fs::remove_file(&path).unwrap();
+        summary.unwrap()
+    }
+
+    fn merge_pieces(conn: &Connection, pieces: Value) -> MergeSummary {
+        merge_file(
+            conn,
+            json!({ "wood_pieces": pieces }),
+            ConflictPolicy::TakeIncoming,
+        )
+    }
+
+    fn read_offers(conn: &Connection) -> Vec<(f64, String, String)> {
+        let mut stmt = conn
+            .prepare(
+                "SELECT offered_price, wood_pieces.plate_no, buyers.ident
+                FROM wood_piece_offers
+                JOIN wood_pieces ON wood_pieces.id = wood_piece_offers.wood_piece_id
+                JOIN buyers ON buyers.id = wood_piece_offers.buyer_id
+                ORDER BY wood_piece_offers.id;",
+            )
+            .unwrap();
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap();
+        rows.collect::<Result<_, _>>().unwrap()
+    }
+
+    fn read_min_price(conn: &Connection, plate_no: &str) -> Option<f64> {
+        conn.query_row(
+            "SELECT min_price FROM wood_pieces WHERE plate_no = ?1;",
+            [plate_no],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    // The local piece P1 has a minimum price of 100, the file changes it to 200 and adds P2
+    fn open_conflicting_auction() -> (Connection, Value) {
+        let conn = open_test_database();
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name, ident) VALUES (1, 'Seller', 'S1');
+            INSERT INTO wood_pieces (sequence_no, plate_no, seller_id, tree_species_id, min_price)
+                VALUES (1, 'P1', 1, 1, 100);",
+        )
+        .unwrap();
+        let file = json!({
+            "sellers": [{ "id": 7, "seller_name": "Seller", "ident": "S1" }],
+            "wood_pieces": [
+                { "id": 3, "sequence_no": 1, "plate_no": "P1", "seller_id": 7, "tree_species_id": 1, "min_price": 200 },
+                { "id": 4, "sequence_no": 2, "plate_no": "P2", "seller_id": 7, "tree_species_id": 1, "min_price": 300 },
+            ],
+        });
+
+        (conn, file)
+    }
+
+    #[test]
+    fn every_offer_of_a_buyer_is_merged_and_points_at_the_local_rows() {
+        let conn = open_test_database();
+        // Local rows take the ids the file uses
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name, ident) VALUES (1, 'Local', 'L1');
+            INSERT INTO buyers (id, buyer_name, ident) VALUES (1, 'Local', 'LB');
+            INSERT INTO wood_pieces (id, sequence_no, plate_no, seller_id, tree_species_id)
+                VALUES (1, 1, 'L-P1', 1, 1);",
+        )
+        .unwrap();
+        let file = json!({
+            "sellers": [{ "id": 1, "seller_name": "Incoming", "ident": "S1" }],
+            "buyers": [{ "id": 1, "buyer_name": "Incoming", "ident": "B1" }],
+            "wood_pieces": [
+                { "id": 1, "sequence_no": 2, "plate_no": "P1", "seller_id": 1, "tree_species_id": 1 },
+            ],
+            "wood_piece_offers": [
+                { "id": 1, "offered_price": 100, "wood_piece_id": 1, "buyer_id": 1 },
+                { "id": 2, "offered_price": 150, "wood_piece_id": 1, "buyer_id": 1 },
+            ],
+        });
+
+        let summary = merge_file(&conn, file.clone(), ConflictPolicy::TakeIncoming);
+        let offers = summary
+            .tables
+            .iter()
+            .find(|table| table.table == "wood_piece_offers")
+            .unwrap();
+        assert_eq!((offers.inserted, offers.updated), (2, 0));
+        let expected = vec![
+            (100.0, "P1".to_string(), "B1".to_string()),
+            (150.0, "P1".to_string(), "B1".to_string()),
+        ];
+        assert_eq!(read_offers(&conn), expected);
+        let seller: String = conn
+            .query_row(
+                "SELECT sellers.ident FROM wood_pieces
+                JOIN sellers ON sellers.id = wood_pieces.seller_id WHERE plate_no = 'P1';",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(seller, "S1");
+
+        // Merging the same file again finds every offer
+        let summary = merge_file(&conn, file, ConflictPolicy::TakeIncoming);
+        assert!(summary.tables.iter().all(|table| table.inserted == 0));
+        assert_eq!(read_offers(&conn), expected);
+    }
+
+    #[test]
+    fn keep_local_keeps_the_local_values_and_lists_the_conflicts() {
+        let (conn, file) = open_conflicting_auction();
+        let summary = merge_file(&conn, file, ConflictPolicy::KeepLocal);
+
+        assert!(summary.applied);
+        assert_eq!(summary.conflicts.len(), 1);
+        assert_eq!(summary.conflicts[0].table, "wood_pieces");
+        assert_eq!(summary.conflicts[0].columns, vec!["min_price".to_string()]);
+        assert_eq!(read_min_price(&conn, "P1"), Some(100.0));
+        assert_eq!(read_min_price(&conn, "P2"), Some(300.0));
+    }
+
+    #[test]
+    fn report_changes_nothing_when_there_are_conflicts() {
+        let (conn, file) = open_conflicting_auction();
+        let summary = merge_file(&conn, file, ConflictPolicy::Report);
+
+        assert!(!summary.applied);
+        assert_eq!(summary.conflicts.len(), 1);
+        assert_eq!(read_min_price(&conn, "P1"), Some(100.0));
+        let pieces: i64 = conn
+            .query_row("SELECT COUNT(*) FROM wood_pieces;", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(pieces, 1);
+    }
+
+    #[test]
+    fn take_incoming_overwrites_the_local_values() {
+        let (conn, file) = open_conflicting_auction();
+        let summary = merge_file(&conn, file, ConflictPolicy::TakeIncoming);
+
+        assert!(summary.applied);
+        assert!(summary.conflicts.is_empty());
+        assert_eq!(read_min_price(&conn, "P1"), Some(200.0));
+        assert_eq!(read_min_price(&conn, "P2"), Some(300.0));
+    }
+
+    #[test]
+    fn unmatched_plate_does_not_fall_back_to_sequence_no() {
+        let conn = open_test_database();
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name) VALUES (1, 'Seller');
+            INSERT INTO wood_pieces (sequence_no, plate_no, seller_id, tree_species_id)
+                VALUES (5, 'A1', 1, 1), (6, NULL, 1, 1);",
+        )
+        .unwrap();
+
+        let summary = merge_pieces(
+            &conn,
+            json!([
+                // Same sequence number, other plate: a new piece
+                { "id": 10, "sequence_no": 5, "plate_no": "B7", "seller_id": 1, "tree_species_id": 1 },
+                // No plate, matched by the sequence number
+                { "id": 11, "sequence_no": 6, "plate_no": null, "seller_id": 1, "tree_species_id": 2 },
+            ]),
+        );
+
+        assert_eq!(summary.tables[0].inserted, 1);
+        assert_eq!(summary.tables[0].updated, 1);
+        assert_eq!(
+            read_plates(&conn),
+            vec![
+                (5, Some("A1".to_string())),
+                (6, None),
+                (5, Some("B7".to_string())),
+            ]
+        );
+    }
+}
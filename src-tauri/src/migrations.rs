@@ -0,0 +1,854 @@
+use crate::restore::read_schema_version;
+use crate::shared::get_connection;
+use crate::tables::{get_table, AddedColumn, TABLES};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use tauri_plugin_sql::{Migration, MigrationKind};
+
+// Version of the upgrade adding the redo log, undo triggers of earlier versions only
+// write to undolog
+pub const REDOLOG_VERSION: i64 = 301;
+// Version of the upgrade adding undo groups, from which the log entries carry a group id
+pub const UNDO_GROUPS_VERSION: i64 = 302;
+// Version of the upgrade from which undoing a delete restores the row with its old id
+pub const UNDO_IDS_VERSION: i64 = 303;
+// Version of the upgrade from which the log entries describe the change they revert
+pub const UNDO_HISTORY_VERSION: i64 = 304;
+// Version of the upgrade adding the audit log, from which tables also have audit triggers
+pub const AUDIT_LOG_VERSION: i64 = 305;
+// Version of the upgrade from which the audit log records the session and the user, and
+// only the length of large columns
+pub const AUDIT_SESSION_VERSION: i64 = 306;
+
+// Migrations applied by the SQL plugin, ordered as they were added (not by version)
+pub fn get_migrations() -> Vec<Migration> {
+    // Migrations with triggers for all tables
+    let mut migrations = vec![Migration {
+        version: 0,
+        description: "create_undolog_table",
+        sql: "CREATE TABLE IF NOT EXISTS undolog (
+                    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                    sql TEXT NOT NULL
+                  );",
+        kind: MigrationKind::Up,
+    }];
+
+    // Create main tables and add triggers dynamically
+    for (i, table) in TABLES.iter().enumerate() {
+        let description = Box::leak(format!("create_table_{}", table.name).into_boxed_str());
+        // Add table creation to migrations
+        migrations.push(Migration {
+            version: (i + 1) as i64,
+            description: description,
+            sql: table.create_sql,
+            kind: MigrationKind::Up,
+        });
+
+        let description_trigger =
+            Box::leak(format!("add_triggers_for_{}", table.name).into_boxed_str());
+        let sql = Box::leak(
+            format!(
+                "
+            -- INSERT Trigger
+            CREATE TRIGGER IF NOT EXISTS {0}_insert AFTER INSERT ON {0}
+            BEGIN
+                INSERT INTO undolog (sql) VALUES (
+                    'DELETE FROM {0} WHERE id=' || quote(NEW.id)
+                );
+            END;
+
+            -- DELETE Trigger
+            CREATE TRIGGER IF NOT EXISTS {0}_delete AFTER DELETE ON {0}
+            BEGIN
+                INSERT INTO undolog (sql) VALUES (
+                    'INSERT INTO {0} ({1}) VALUES ({3});'
+                );
+            END;
+
+            -- UPDATE Trigger
+            CREATE TRIGGER IF NOT EXISTS {0}_update AFTER UPDATE ON {0}
+            BEGIN
+                INSERT INTO undolog (sql) VALUES (
+                    'UPDATE {0} SET {2} WHERE id=' || quote(OLD.id)
+                );
+            END;
+            ",
+                table.name,
+                table.columns.join(", "), // Comma-separated column names
+                get_update_set_statements(table.columns), // Comma-separated update set statements
+                get_delete_insert_statements(table.columns),
+            )
+            .into_boxed_str(),
+        );
+
+        // Add triggers for this table
+        migrations.push(Migration {
+            version: (i + 10) as i64,
+            description: description_trigger,
+            sql: sql,
+            kind: MigrationKind::Up,
+        });
+    }
+
+    // Add default data (tree species, settings, images)
+    let seeded = TABLES.iter().filter_map(|table| Some((table.name, table.seed_sql?)));
+    for (i, (table, seed_sql)) in seeded.enumerate() {
+        migrations.push(Migration {
+            version: (200 + i) as i64,
+            description: Box::leak(format!("insert_{}_data", table).into_boxed_str()),
+            sql: seed_sql,
+            kind: MigrationKind::Up,
+        });
+    }
+
+    // In-place upgrades of existing databases
+    migrations.extend(get_upgrade_migrations());
+
+    migrations
+}
+
+// Upgrades existing databases in place. Every upgrade runs once, in its own transaction,
+// and the plugin refuses to start if an upgrade that already ran was modified, so never
+// edit a shipped upgrade: add a new one with the next version instead.
+fn get_upgrade_migrations() -> Vec<Migration> {
+    let mut migrations = vec![upgrade(
+        300,
+        "regenerate_undo_triggers",
+        TABLES
+            .iter()
+            .map(|table| get_undo_triggers_sql(table.name, 300))
+            .collect::<Vec<String>>()
+            .join("\n"),
+    )];
+
+    migrations.extend(get_added_column_migrations());
+
+    // undo_state holds a row while an undo or redo runs (see `undo.rs`), every change made
+    // outside of one is a new edit and drops what could be redone
+    migrations.push(upgrade(
+        REDOLOG_VERSION,
+        "create_redolog_table",
+        format!(
+            "CREATE TABLE IF NOT EXISTS redolog (
+                seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                sql TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS undo_state (
+                mode TEXT NOT NULL
+            );
+            DROP TRIGGER IF EXISTS undolog_clear_redolog;
+            CREATE TRIGGER undolog_clear_redolog AFTER INSERT ON undolog
+            WHEN NOT EXISTS (SELECT 1 FROM undo_state)
+            BEGIN
+                DELETE FROM redolog;
+            END;
+            {}",
+            get_all_undo_triggers_sql(REDOLOG_VERSION)
+        ),
+    ));
+
+    // Entries logged while a group is open in undo_state share its id and are undone and
+    // redone together. From here on undo_state also holds the open group of an edit.
+    migrations.push(upgrade(
+        UNDO_GROUPS_VERSION,
+        "add_undo_groups",
+        format!(
+            "CREATE TABLE IF NOT EXISTS undo_groups (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                label TEXT NOT NULL,
+                created_at INTEGER NOT NULL DEFAULT (CAST(strftime('%s', 'now') AS INTEGER))
+            );
+            ALTER TABLE undolog ADD COLUMN group_id INTEGER;
+            ALTER TABLE redolog ADD COLUMN group_id INTEGER;
+            ALTER TABLE undo_state ADD COLUMN group_id INTEGER;
+            DROP TRIGGER IF EXISTS undolog_clear_redolog;
+            CREATE TRIGGER undolog_clear_redolog AFTER INSERT ON undolog
+            WHEN NOT EXISTS (SELECT 1 FROM undo_state WHERE mode IN ('undo', 'redo'))
+            BEGIN
+                DELETE FROM redolog;
+            END;
+            {}",
+            get_all_undo_triggers_sql(UNDO_GROUPS_VERSION)
+        ),
+    ));
+
+    // Rows restored by an undo keep their id, so the rows referencing them stay valid
+    migrations.push(upgrade(
+        UNDO_IDS_VERSION,
+        "preserve_ids_in_undo",
+        get_all_undo_triggers_sql(UNDO_IDS_VERSION),
+    ));
+
+    // What changed and when, for the history shown to the user (see `undo.rs`)
+    let add_history_columns = |log: &str| {
+        [
+            "table_name TEXT",
+            "row_id INTEGER",
+            "operation TEXT",
+            "old_row TEXT",
+            "new_row TEXT",
+            "created_at INTEGER",
+        ]
+        .iter()
+        .map(|column| format!("ALTER TABLE {} ADD COLUMN {};", log, column))
+        .collect::<Vec<String>>()
+        .join("\n")
+    };
+    migrations.push(upgrade(
+        UNDO_HISTORY_VERSION,
+        "add_undo_history",
+        format!(
+            "{}\n{}\n{}",
+            add_history_columns("undolog"),
+            add_history_columns("redolog"),
+            get_all_undo_triggers_sql(UNDO_HISTORY_VERSION)
+        ),
+    ));
+
+    // Unlike the undo log, nothing ever removes or changes a row of audit_log
+    migrations.push(upgrade(
+        AUDIT_LOG_VERSION,
+        "create_audit_log",
+        format!(
+            "CREATE TABLE IF NOT EXISTS audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                created_at INTEGER NOT NULL DEFAULT (CAST(strftime('%s', 'now') AS INTEGER)),
+                table_name TEXT NOT NULL,
+                row_id INTEGER,
+                operation TEXT NOT NULL,
+                old_row TEXT,
+                new_row TEXT,
+                action TEXT
+            );
+            CREATE INDEX IF NOT EXISTS audit_log_row ON audit_log (table_name, row_id);
+            DROP TRIGGER IF EXISTS audit_log_no_update;
+            CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+            BEGIN
+                SELECT RAISE(ABORT, 'The audit log cannot be changed');
+            END;
+            DROP TRIGGER IF EXISTS audit_log_no_delete;
+            CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+            BEGIN
+                SELECT RAISE(ABORT, 'The audit log cannot be changed');
+            END;
+            {}",
+            TABLES
+                .iter()
+                .map(|table| get_audit_triggers_sql(table.name, AUDIT_LOG_VERSION))
+                .collect::<Vec<String>>()
+                .join("\n")
+        ),
+    ));
+
+    // audit_session holds the one row the triggers copy to every entry, written when the
+    // app opens the database (see `audit.rs`)
+    migrations.push(upgrade(
+        AUDIT_SESSION_VERSION,
+        "add_audit_session",
+        format!(
+            "ALTER TABLE audit_log ADD COLUMN actor TEXT;
+            ALTER TABLE audit_log ADD COLUMN session_id TEXT;
+            CREATE TABLE IF NOT EXISTS audit_session (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                actor TEXT,
+                session_id TEXT
+            );
+            {}",
+            TABLES
+                .iter()
+                .map(|table| get_audit_triggers_sql(table.name, AUDIT_SESSION_VERSION))
+                .collect::<Vec<String>>()
+                .join("\n")
+        ),
+    ));
+
+    migrations
+}
+
+fn get_all_undo_triggers_sql(version: i64) -> String {
+    TABLES
+        .iter()
+        .map(|table| get_undo_triggers_sql(table.name, version))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// One upgrade per version used in `Table::added_columns`, adding the columns and
+// regenerating the triggers of the changed tables
+fn get_added_column_migrations() -> Vec<Migration> {
+    let versions: BTreeSet<i64> = TABLES
+        .iter()
+        .flat_map(|table| table.added_columns.iter().map(|column| column.version))
+        .collect();
+
+    let mut migrations = vec![];
+    for version in versions {
+        let mut names = vec![];
+        let mut sql = vec![];
+        for table in &TABLES {
+            let added: Vec<&AddedColumn> = table
+                .added_columns
+                .iter()
+                .filter(|column| column.version == version)
+                .collect();
+            if added.is_empty() {
+                continue;
+            }
+
+            for column in added {
+                names.push(format!("{}_{}", table.name, column.name));
+                sql.push(format!(
+                    "ALTER TABLE {} ADD COLUMN {} {};",
+                    table.name, column.name, column.definition
+                ));
+            }
+            sql.push(get_table_triggers_sql(table.name, version));
+        }
+
+        let description = Box::leak(format!("add_columns_{}", names.join("_")).into_boxed_str());
+        migrations.push(upgrade(version, description, sql.join("\n")));
+    }
+
+    migrations
+}
+
+// Highest migration version, stored in `PRAGMA user_version` by every upgrade
+pub fn get_schema_version() -> i64 {
+    get_migrations()
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Serialize)]
+pub struct DatabaseVersion {
+    // Highest migration applied to the working database
+    pub current: Option<i64>,
+    // Highest migration known to this build of the app
+    pub expected: i64,
+}
+
+#[tauri::command]
+pub fn get_database_version(app_handle: tauri::AppHandle) -> Result<DatabaseVersion, String> {
+    let conn = get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+
+    Ok(DatabaseVersion {
+        current: read_schema_version(&conn).map_err(|e| e.to_string())?,
+        expected: get_schema_version(),
+    })
+}
+
+fn upgrade(version: i64, description: &'static str, sql: String) -> Migration {
+    let sql = format!("{}\nPRAGMA user_version = {};", sql, version);
+
+    Migration {
+        version,
+        description,
+        sql: Box::leak(sql.into_boxed_str()),
+        kind: MigrationKind::Up,
+    }
+}
+
+// The triggers every table should currently have, used when repairing older databases
+pub fn get_triggers_sql(table: &str) -> String {
+    get_table_triggers_sql(table, i64::MAX)
+}
+
+// (Re)creates all triggers of a table as of `version`
+fn get_table_triggers_sql(table: &str, version: i64) -> String {
+    if version < AUDIT_LOG_VERSION {
+        return get_undo_triggers_sql(table, version);
+    }
+
+    format!(
+        "{}\n{}",
+        get_undo_triggers_sql(table, version),
+        get_audit_triggers_sql(table, version)
+    )
+}
+
+// (Re)creates the triggers writing every change of a table to audit_log, with its columns
+// as of `version`. The action is the label of the undo group or "undo"/"redo".
+fn get_audit_triggers_sql(table: &str, version: i64) -> String {
+    if version >= AUDIT_SESSION_VERSION {
+        return get_audit_session_triggers_sql(table, version);
+    }
+
+    let columns = get_table(table)
+        .map(|table| table.columns_at(version))
+        .unwrap_or_default();
+    let action = AUDIT_ACTION_SQL;
+
+    format!(
+        "
+        DROP TRIGGER IF EXISTS {0}_audit_insert;
+        CREATE TRIGGER {0}_audit_insert AFTER INSERT ON {0}
+        BEGIN
+            INSERT INTO audit_log (table_name, row_id, operation, old_row, new_row, action)
+            VALUES ('{0}', NEW.id, 'insert', NULL, {1}, {3});
+        END;
+
+        DROP TRIGGER IF EXISTS {0}_audit_delete;
+        CREATE TRIGGER {0}_audit_delete AFTER DELETE ON {0}
+        BEGIN
+            INSERT INTO audit_log (table_name, row_id, operation, old_row, new_row, action)
+            VALUES ('{0}', OLD.id, 'delete', {2}, NULL, {3});
+        END;
+
+        DROP TRIGGER IF EXISTS {0}_audit_update;
+        CREATE TRIGGER {0}_audit_update AFTER UPDATE ON {0}
+        BEGIN
+            INSERT INTO audit_log (table_name, row_id, operation, old_row, new_row, action)
+            VALUES ('{0}', OLD.id, 'update', {2}, {1}, {3});
+        END;
+        ",
+        table,
+        get_row_json_sql(&columns, "NEW"),
+        get_row_json_sql(&columns, "OLD"),
+        action,
+    )
+}
+
+const AUDIT_ACTION_SQL: &str = "(SELECT CASE WHEN mode = 'edit'
+                    THEN (SELECT label FROM undo_groups WHERE id = undo_state.group_id)
+                    ELSE mode END FROM undo_state)";
+
+// Audit triggers from AUDIT_SESSION_VERSION on, also writing the session and the user
+fn get_audit_session_triggers_sql(table: &str, version: i64) -> String {
+    let (columns, large_columns) = get_table(table)
+        .map(|table| (table.columns_at(version), table.large_columns))
+        .unwrap_or_default();
+
+    format!(
+        "
+        DROP TRIGGER IF EXISTS {0}_audit_insert;
+        CREATE TRIGGER {0}_audit_insert AFTER INSERT ON {0}
+        BEGIN
+            INSERT INTO audit_log (table_name, row_id, operation, old_row, new_row, action, actor, session_id)
+            VALUES ('{0}', NEW.id, 'insert', NULL, {1}, {3}, {4});
+        END;
+
+        DROP TRIGGER IF EXISTS {0}_audit_delete;
+        CREATE TRIGGER {0}_audit_delete AFTER DELETE ON {0}
+        BEGIN
+            INSERT INTO audit_log (table_name, row_id, operation, old_row, new_row, action, actor, session_id)
+            VALUES ('{0}', OLD.id, 'delete', {2}, NULL, {3}, {4});
+        END;
+
+        DROP TRIGGER IF EXISTS {0}_audit_update;
+        CREATE TRIGGER {0}_audit_update AFTER UPDATE ON {0}
+        BEGIN
+            INSERT INTO audit_log (table_name, row_id, operation, old_row, new_row, action, actor, session_id)
+            VALUES ('{0}', OLD.id, 'update', {2}, {1}, {3}, {4});
+        END;
+        ",
+        table,
+        get_audit_row_json_sql(&columns, large_columns, "NEW"),
+        get_audit_row_json_sql(&columns, large_columns, "OLD"),
+        AUDIT_ACTION_SQL,
+        "(SELECT actor FROM audit_session), (SELECT session_id FROM audit_session)",
+    )
+}
+
+// Like `get_row_json_sql`, but the audit log is never pruned, so large columns and blobs
+// are stored as their length only
+fn get_audit_row_json_sql(columns: &[&str], large_columns: &[&str], prefix: &str) -> String {
+    let pairs: Vec<String> = std::iter::once(&"id")
+        .chain(columns)
+        .map(|column| {
+            if large_columns.contains(column) {
+                format!("'{0}_length', length({1}.{0})", column, prefix)
+            } else {
+                format!(
+                    "'{0}', CASE typeof({1}.{0}) WHEN 'blob'
+                        THEN 'blob, ' || length({1}.{0}) || ' bytes' ELSE {1}.{0} END",
+                    column, prefix
+                )
+            }
+        })
+        .collect();
+
+    format!("json_object({})", pairs.join(", "))
+}
+
+// (Re)creates the undo triggers of a table with its columns as of `version`, needed
+// whenever its columns change. Upgrades pass their own version so their SQL never changes.
+pub fn get_undo_triggers_sql(table: &str, version: i64) -> String {
+    let columns = get_table(table)
+        .map(|table| table.columns_at(version))
+        .unwrap_or_default();
+
+    format!(
+        "
+        DROP TRIGGER IF EXISTS {0}_insert;
+        CREATE TRIGGER {0}_insert AFTER INSERT ON {0}
+        BEGIN
+            {1}
+        END;
+
+        DROP TRIGGER IF EXISTS {0}_delete;
+        CREATE TRIGGER {0}_delete AFTER DELETE ON {0}
+        BEGIN
+            {2}
+        END;
+
+        DROP TRIGGER IF EXISTS {0}_update;
+        CREATE TRIGGER {0}_update AFTER UPDATE ON {0}
+        BEGIN
+            {3}
+        END;
+        ",
+        table,
+        get_log_sql(
+            table,
+            Operation::Insert,
+            &format!("'DELETE FROM {} WHERE id=' || quote(NEW.id)", table),
+            &columns,
+            version
+        ),
+        get_log_sql(
+            table,
+            Operation::Delete,
+            &get_restore_statement(table, &columns, version),
+            &columns,
+            version
+        ),
+        get_log_sql(
+            table,
+            Operation::Update,
+            &format!(
+                "'UPDATE {} SET {} WHERE id=' || quote(OLD.id)",
+                table,
+                get_update_set_statements(&columns)
+            ),
+            &columns,
+            version
+        ),
+    )
+}
+
+// SQL inserting a deleted row again, quoted for a trigger
+fn get_restore_statement(table: &str, columns: &[&str], version: i64) -> String {
+    if version < UNDO_IDS_VERSION {
+        return format!(
+            "'INSERT INTO {} ({}) VALUES ({});'",
+            table,
+            columns.join(", "),
+            get_delete_insert_statements(columns)
+        );
+    }
+
+    format!(
+        "'INSERT INTO {} (id, {}) VALUES (' || quote(OLD.id) || ', {});'",
+        table,
+        columns.join(", "),
+        get_delete_insert_statements(columns)
+    )
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operation {
+    Insert,
+    Update,
+    Delete,
+}
+
+// json_object() of the row a trigger sees as `prefix`, OLD or NEW. JSON cannot hold blobs,
+// which are written as hex so that storing one does not fail.
+pub fn get_row_json_sql(columns: &[&str], prefix: &str) -> String {
+    let pairs: Vec<String> = std::iter::once(&"id")
+        .chain(columns)
+        .map(|column| {
+            format!(
+                "'{0}', CASE typeof({1}.{0}) WHEN 'blob' THEN hex({1}.{0}) ELSE {1}.{0} END",
+                column, prefix
+            )
+        })
+        .collect();
+
+    format!("json_object({})", pairs.join(", "))
+}
+
+// Trigger statements recording `statement`, the SQL reverting a change. While an undo runs
+// (see `undo.rs`) it reverts the undo instead and goes to the redo log.
+fn get_log_sql(
+    table: &str,
+    operation: Operation,
+    statement: &str,
+    columns: &[&str],
+    version: i64,
+) -> String {
+    if version < REDOLOG_VERSION {
+        return format!(
+            "INSERT INTO undolog (sql) VALUES (\n                {}\n            );",
+            statement
+        );
+    }
+
+    if version < UNDO_GROUPS_VERSION {
+        return format!(
+            "INSERT INTO undolog (sql) SELECT {0}
+                WHERE NOT EXISTS (SELECT 1 FROM undo_state WHERE mode = 'undo');
+            INSERT INTO redolog (sql) SELECT {0}
+                WHERE EXISTS (SELECT 1 FROM undo_state WHERE mode = 'undo');",
+            statement
+        );
+    }
+
+    if version < UNDO_HISTORY_VERSION {
+        return format!(
+            "INSERT INTO undolog (sql, group_id) SELECT {0}, (SELECT group_id FROM undo_state)
+                WHERE NOT EXISTS (SELECT 1 FROM undo_state WHERE mode = 'undo');
+            INSERT INTO redolog (sql, group_id) SELECT {0}, (SELECT group_id FROM undo_state)
+                WHERE EXISTS (SELECT 1 FROM undo_state WHERE mode = 'undo');",
+            statement
+        );
+    }
+
+    let (operation, row_id, old_row, new_row) = match operation {
+        Operation::Insert => (
+            "insert",
+            "NEW.id",
+            "NULL".to_string(),
+            get_row_json_sql(columns, "NEW"),
+        ),
+        Operation::Update => (
+            "update",
+            "OLD.id",
+            get_row_json_sql(columns, "OLD"),
+            get_row_json_sql(columns, "NEW"),
+        ),
+        Operation::Delete => (
+            "delete",
+            "OLD.id",
+            get_row_json_sql(columns, "OLD"),
+            "NULL".to_string(),
+        ),
+    };
+    let insert = |log: &str, condition: &str| {
+        format!(
+            "INSERT INTO {} (sql, group_id, table_name, row_id, operation, old_row, new_row, created_at)
+                SELECT {}, (SELECT group_id FROM undo_state), '{}', {}, '{}', {}, {},
+                    CAST(strftime('%s', 'now') AS INTEGER)
+                WHERE {} (SELECT 1 FROM undo_state WHERE mode = 'undo');",
+            log, statement, table, row_id, operation, old_row, new_row, condition
+        )
+    };
+
+    format!(
+        "{}\n            {}",
+        insert("undolog", "NOT EXISTS"),
+        insert("redolog", "EXISTS")
+    )
+}
+
+// Rebuilds a table from a new definition, for changes ALTER TABLE cannot do in SQLite
+// (e.g. changing the generated `volume` column of wood_pieces). `create_sql` must create
+// `<table>_new`, `columns` lists the columns copied over (including `id`) and `dependents`
+// the tables with foreign keys pointing at `table`. `version` is the version of the upgrade,
+// the columns and triggers of the tables are taken as of that version.
+//
+// Upgrades run inside a transaction with foreign keys enforced, where they cannot be
+// switched off, and dropping a table with ON DELETE RESTRICT references fails. So the rows
+// of dependent tables are parked in temporary tables while the table is swapped, with
+// their triggers removed so that nothing ends up in the undo or audit log.
+pub fn get_rebuild_table_sql(
+    table: &str,
+    create_sql: &str,
+    columns: &[&str],
+    dependents: &[&str],
+    version: i64,
+) -> String {
+    let drop_triggers = |t: &str| {
+        let mut sql = format!(
+            "DROP TRIGGER IF EXISTS {0}_insert; DROP TRIGGER IF EXISTS {0}_delete; DROP TRIGGER IF EXISTS {0}_update;",
+            t
+        );
+        if version >= AUDIT_LOG_VERSION {
+            sql.push_str(&format!(
+                " DROP TRIGGER IF EXISTS {0}_audit_insert; DROP TRIGGER IF EXISTS {0}_audit_delete; DROP TRIGGER IF EXISTS {0}_audit_update;",
+                t
+            ));
+        }
+        sql
+    };
+    let dependent_columns = |t: &str| {
+        let columns = get_table(t)
+            .map(|table| table.columns_at(version))
+            .unwrap_or_default();
+        format!("id, {}", columns.join(", "))
+    };
+
+    let mut sql = vec![];
+    for dependent in dependents {
+        sql.push(drop_triggers(dependent));
+        sql.push(format!(
+            "CREATE TEMP TABLE {0}_parked AS SELECT {1} FROM {0}; DELETE FROM {0};",
+            dependent,
+            dependent_columns(dependent)
+        ));
+    }
+
+    sql.push(drop_triggers(table));
+    sql.push(create_sql.to_string());
+    sql.push(format!(
+        "INSERT INTO {0}_new ({1}) SELECT {1} FROM {0};",
+        table,
+        columns.join(", ")
+    ));
+    sql.push(format!("DROP TABLE {0}; ALTER TABLE {0}_new RENAME TO {0};", table));
+    sql.push(get_table_triggers_sql(table, version));
+
+    for dependent in dependents {
+        sql.push(format!(
+            "INSERT INTO {0} ({1}) SELECT {1} FROM temp.{0}_parked; DROP TABLE temp.{0}_parked;",
+            dependent,
+            dependent_columns(dependent)
+        ));
+        sql.push(get_table_triggers_sql(dependent, version));
+    }
+
+    sql.join("\n")
+}
+
+// Helper function to generate update set statements with proper escaping
+fn get_update_set_statements(columns: &[&str]) -> String {
+    columns
+        .iter()
+        .filter(|col| **col != "id") // Exclude the primary key column
+        .map(|col| format!("{}=' || quote(OLD.{}) || '", col, col)) // Apply quote() to OLD values
+        .collect::<Vec<String>>() // Collect into a Vec<String>
+        .join(", ") // Join with commas
+}
+
+// Helper function to generate update set statements with proper escaping
+fn get_delete_insert_statements(columns: &[&str]) -> String {
+    columns
+        .iter()
+        .filter(|col| **col != "id") // Exclude the primary key column
+        .map(|col| format!("' || quote(OLD.{}) || '", col)) // Apply quote() to OLD values
+        .collect::<Vec<String>>() // Collect into a Vec<String>
+        .join(", ") // Join with commas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::open_test_database;
+    use rusqlite::Connection;
+
+    fn query_strings(conn: &Connection, sql: &str) -> Vec<String> {
+        let mut stmt = conn.prepare(sql).unwrap();
+        let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+        rows.collect::<Result<_, _>>().unwrap()
+    }
+
+    fn count_rows(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {};", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn rebuilt_wood_pieces_keep_their_rows_triggers_and_foreign_keys() {
+        let conn = open_test_database();
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name) VALUES (1, 'Seller');
+            INSERT INTO buyers (id, buyer_name) VALUES (1, 'Buyer');
+            INSERT INTO wood_pieces (id, sequence_no, plate_no, length, width, seller_id, tree_species_id)
+                VALUES (4, 1, 'A1', 4, 50, 1, 1), (9, 2, 'A2', 3, 30, 1, 2);
+            INSERT INTO wood_piece_offers (id, offered_price, wood_piece_id, buyer_id)
+                VALUES (5, 150, 9, 1);",
+        )
+        .unwrap();
+        let triggers_sql = "SELECT name FROM sqlite_master WHERE type = 'trigger'
+            AND tbl_name IN ('wood_pieces', 'wood_piece_offers') ORDER BY name;";
+        let triggers = query_strings(&conn, triggers_sql);
+        let undo_entries = count_rows(&conn, "undolog");
+        let audit_entries = count_rows(&conn, "audit_log");
+
+        // The volume is rounded to three decimals instead of two
+        let create_sql = "CREATE TABLE wood_pieces_new (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                length REAL,
+                sequence_no INTEGER,
+                width REAL,
+                volume REAL AS (round(3.14159265359 * width * 0.5 * 0.01 * width * 0.5 * 0.01 * length, 3)) STORED,
+                plate_no VARCHAR,
+                seller_id INTEGER,
+                tree_species_id INTEGER,
+                min_price REAL,
+                bypass_min_price INTEGER,
+                FOREIGN KEY(seller_id) REFERENCES sellers(id) ON DELETE RESTRICT,
+                FOREIGN KEY(tree_species_id) REFERENCES tree_species(id)
+            );";
+        let table = get_table("wood_pieces").unwrap();
+        let mut columns = vec!["id"];
+        columns.extend(table.current_columns());
+        let sql = get_rebuild_table_sql(
+            "wood_pieces",
+            create_sql,
+            &columns,
+            &["wood_piece_offers"],
+            get_schema_version(),
+        );
+        // As an upgrade runs, in a transaction with foreign keys enforced
+        let tx = conn.unchecked_transaction().unwrap();
+        tx.execute_batch(&sql).unwrap();
+        tx.commit().unwrap();
+
+        let mut stmt = conn
+            .prepare("SELECT id, plate_no, seller_id, volume FROM wood_pieces ORDER BY id;")
+            .unwrap();
+        let pieces: Vec<(i64, String, i64, f64)> = stmt
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            pieces,
+            vec![
+                (4, "A1".to_string(), 1, 0.785),
+                (9, "A2".to_string(), 1, 0.212)
+            ]
+        );
+        let offer: (i64, f64, i64) = conn
+            .query_row(
+                "SELECT id, offered_price, wood_piece_id FROM wood_piece_offers;",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(offer, (5, 150.0, 9));
+        assert!(query_strings(&conn, "SELECT name FROM temp.sqlite_master;").is_empty());
+
+        // Nothing of the rebuild is logged, the triggers are back and log what comes after
+        assert_eq!(count_rows(&conn, "undolog"), undo_entries);
+        assert_eq!(count_rows(&conn, "audit_log"), audit_entries);
+        assert_eq!(query_strings(&conn, triggers_sql), triggers);
+        conn.execute("UPDATE wood_pieces SET width = 60 WHERE id = 4;", [])
+            .unwrap();
+        assert_eq!(count_rows(&conn, "undolog"), undo_entries + 1);
+        assert_eq!(count_rows(&conn, "audit_log"), audit_entries + 1);
+
+        // The foreign keys of the rebuilt table and of the table referencing it still hold
+        assert!(query_strings(&conn, "PRAGMA foreign_key_check;").is_empty());
+        assert!(conn
+            .execute("DELETE FROM sellers WHERE id = 1;", [])
+            .is_err());
+        assert!(conn
+            .execute("DELETE FROM wood_pieces WHERE id = 9;", [])
+            .is_err());
+        assert!(conn
+            .execute(
+                "INSERT INTO wood_piece_offers (offered_price, wood_piece_id, buyer_id)
+                VALUES (100, 99, 1);",
+                [],
+            )
+            .is_err());
+    }
+}
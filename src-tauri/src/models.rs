@@ -0,0 +1,476 @@
+use rusqlite::{Row, ToSql};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+// A row of one of the auction tables created by the migrations
+pub trait Record: Serialize + DeserializeOwned + Sized {
+    // Name of the backing table
+    const TABLE: &'static str;
+    // Writable columns, without `id` and without generated columns
+    const COLUMNS: &'static [&'static str];
+
+    fn id(&self) -> i64;
+    fn from_row(row: &Row) -> rusqlite::Result<Self>;
+    // Values in the same order as `COLUMNS`
+    fn values(&self) -> Vec<&dyn ToSql>;
+
+    // Business rules that have to hold before the row is written
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+fn ensure_not_negative(field: &str, value: Option<f64>) -> Result<(), String> {
+    match value {
+        Some(v) if v < 0.0 => Err(format!("{} must not be negative", field)),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Seller {
+    #[serde(default)]
+    pub id: i64,
+    pub seller_name: Option<String>,
+    pub address_line1: Option<String>,
+    pub address_line2: Option<String>,
+    pub iban: Option<String>,
+    pub ident: Option<String>,
+    pub is_flat_rate: Option<i64>,
+    pub is_vat_liable: Option<i64>,
+    pub used_transport: Option<i64>,
+    pub used_logging: Option<i64>,
+    pub used_logging_non_woods: Option<i64>,
+    pub additional_costs: Option<f64>,
+    pub transport_costs: Option<f64>,
+    pub logging_costs: Option<f64>,
+}
+
+impl Record for Seller {
+    const TABLE: &'static str = "sellers";
+    const COLUMNS: &'static [&'static str] = &[
+        "seller_name",
+        "address_line1",
+        "address_line2",
+        "iban",
+        "ident",
+        "is_flat_rate",
+        "is_vat_liable",
+        "used_transport",
+        "used_logging",
+        "used_logging_non_woods",
+        "additional_costs",
+        "transport_costs",
+        "logging_costs",
+    ];
+
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Seller {
+            id: row.get("id")?,
+            seller_name: row.get("seller_name")?,
+            address_line1: row.get("address_line1")?,
+            address_line2: row.get("address_line2")?,
+            iban: row.get("iban")?,
+            ident: row.get("ident")?,
+            is_flat_rate: row.get("is_flat_rate")?,
+            is_vat_liable: row.get("is_vat_liable")?,
+            used_transport: row.get("used_transport")?,
+            used_logging: row.get("used_logging")?,
+            used_logging_non_woods: row.get("used_logging_non_woods")?,
+            additional_costs: row.get("additional_costs")?,
+            transport_costs: row.get("transport_costs")?,
+            logging_costs: row.get("logging_costs")?,
+        })
+    }
+
+    fn values(&self) -> Vec<&dyn ToSql> {
+        vec![
+            &self.seller_name,
+            &self.address_line1,
+            &self.address_line2,
+            &self.iban,
+            &self.ident,
+            &self.is_flat_rate,
+            &self.is_vat_liable,
+            &self.used_transport,
+            &self.used_logging,
+            &self.used_logging_non_woods,
+            &self.additional_costs,
+            &self.transport_costs,
+            &self.logging_costs,
+        ]
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        ensure_not_negative("additional_costs", self.additional_costs)?;
+        ensure_not_negative("transport_costs", self.transport_costs)?;
+        ensure_not_negative("logging_costs", self.logging_costs)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Buyer {
+    #[serde(default)]
+    pub id: i64,
+    pub buyer_name: Option<String>,
+    pub address_line1: Option<String>,
+    pub address_line2: Option<String>,
+    pub additional_costs: Option<f64>,
+    pub is_vat_liable: Option<i64>,
+    pub used_bundle: Option<i64>,
+    pub used_loading: Option<i64>,
+    pub loading_costs: Option<f64>,
+    pub ident: Option<String>,
+}
+
+impl Record for Buyer {
+    const TABLE: &'static str = "buyers";
+    const COLUMNS: &'static [&'static str] = &[
+        "buyer_name",
+        "address_line1",
+        "address_line2",
+        "additional_costs",
+        "is_vat_liable",
+        "used_bundle",
+        "used_loading",
+        "loading_costs",
+        "ident",
+    ];
+
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Buyer {
+            id: row.get("id")?,
+            buyer_name: row.get("buyer_name")?,
+            address_line1: row.get("address_line1")?,
+            address_line2: row.get("address_line2")?,
+            additional_costs: row.get("additional_costs")?,
+            is_vat_liable: row.get("is_vat_liable")?,
+            used_bundle: row.get("used_bundle")?,
+            used_loading: row.get("used_loading")?,
+            loading_costs: row.get("loading_costs")?,
+            ident: row.get("ident")?,
+        })
+    }
+
+    fn values(&self) -> Vec<&dyn ToSql> {
+        vec![
+            &self.buyer_name,
+            &self.address_line1,
+            &self.address_line2,
+            &self.additional_costs,
+            &self.is_vat_liable,
+            &self.used_bundle,
+            &self.used_loading,
+            &self.loading_costs,
+            &self.ident,
+        ]
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        ensure_not_negative("additional_costs", self.additional_costs)?;
+        ensure_not_negative("loading_costs", self.loading_costs)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TreeSpecies {
+    #[serde(default)]
+    pub id: i64,
+    pub tree_species_name: Option<String>,
+    pub latin_name: Option<String>,
+    pub tree_species_name_slo: Option<String>,
+}
+
+impl Record for TreeSpecies {
+    const TABLE: &'static str = "tree_species";
+    const COLUMNS: &'static [&'static str] =
+        &["tree_species_name", "latin_name", "tree_species_name_slo"];
+
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(TreeSpecies {
+            id: row.get("id")?,
+            tree_species_name: row.get("tree_species_name")?,
+            latin_name: row.get("latin_name")?,
+            tree_species_name_slo: row.get("tree_species_name_slo")?,
+        })
+    }
+
+    fn values(&self) -> Vec<&dyn ToSql> {
+        vec![
+            &self.tree_species_name,
+            &self.latin_name,
+            &self.tree_species_name_slo,
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WoodPiece {
+    #[serde(default)]
+    pub id: i64,
+    pub length: Option<f64>,
+    pub sequence_no: Option<i64>,
+    pub width: Option<f64>,
+    // Generated by the database from `length` and `width`, never written
+    #[serde(default)]
+    pub volume: Option<f64>,
+    pub plate_no: Option<String>,
+    pub seller_id: Option<i64>,
+    pub tree_species_id: Option<i64>,
+    pub min_price: Option<f64>,
+    pub bypass_min_price: Option<i64>,
+}
+
+impl Record for WoodPiece {
+    const TABLE: &'static str = "wood_pieces";
+    const COLUMNS: &'static [&'static str] = &[
+        "length",
+        "sequence_no",
+        "width",
+        "plate_no",
+        "seller_id",
+        "tree_species_id",
+        "min_price",
+        "bypass_min_price",
+    ];
+
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(WoodPiece {
+            id: row.get("id")?,
+            length: row.get("length")?,
+            sequence_no: row.get("sequence_no")?,
+            width: row.get("width")?,
+            volume: row.get("volume")?,
+            plate_no: row.get("plate_no")?,
+            seller_id: row.get("seller_id")?,
+            tree_species_id: row.get("tree_species_id")?,
+            min_price: row.get("min_price")?,
+            bypass_min_price: row.get("bypass_min_price")?,
+        })
+    }
+
+    fn values(&self) -> Vec<&dyn ToSql> {
+        vec![
+            &self.length,
+            &self.sequence_no,
+            &self.width,
+            &self.plate_no,
+            &self.seller_id,
+            &self.tree_species_id,
+            &self.min_price,
+            &self.bypass_min_price,
+        ]
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        ensure_not_negative("length", self.length)?;
+        ensure_not_negative("width", self.width)?;
+        ensure_not_negative("min_price", self.min_price)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WoodPieceOffer {
+    #[serde(default)]
+    pub id: i64,
+    pub offered_price: Option<f64>,
+    pub wood_piece_id: Option<i64>,
+    pub buyer_id: Option<i64>,
+}
+
+impl Record for WoodPieceOffer {
+    const TABLE: &'static str = "wood_piece_offers";
+    const COLUMNS: &'static [&'static str] = &["offered_price", "wood_piece_id", "buyer_id"];
+
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(WoodPieceOffer {
+            id: row.get("id")?,
+            offered_price: row.get("offered_price")?,
+            wood_piece_id: row.get("wood_piece_id")?,
+            buyer_id: row.get("buyer_id")?,
+        })
+    }
+
+    fn values(&self) -> Vec<&dyn ToSql> {
+        vec![&self.offered_price, &self.wood_piece_id, &self.buyer_id]
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        ensure_not_negative("offered_price", self.offered_price)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Settings {
+    #[serde(default)]
+    pub id: i64,
+    pub licitator_fixed_cost: Option<f64>,
+    pub licitator_percentage: Option<f64>,
+    pub bundle_cost: Option<f64>,
+}
+
+impl Record for Settings {
+    const TABLE: &'static str = "settings";
+    const COLUMNS: &'static [&'static str] = &[
+        "licitator_fixed_cost",
+        "licitator_percentage",
+        "bundle_cost",
+    ];
+
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Settings {
+            id: row.get("id")?,
+            licitator_fixed_cost: row.get("licitator_fixed_cost")?,
+            licitator_percentage: row.get("licitator_percentage")?,
+            bundle_cost: row.get("bundle_cost")?,
+        })
+    }
+
+    fn values(&self) -> Vec<&dyn ToSql> {
+        vec![
+            &self.licitator_fixed_cost,
+            &self.licitator_percentage,
+            &self.bundle_cost,
+        ]
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        ensure_not_negative("licitator_fixed_cost", self.licitator_fixed_cost)?;
+        ensure_not_negative("licitator_percentage", self.licitator_percentage)?;
+        ensure_not_negative("bundle_cost", self.bundle_cost)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Image {
+    #[serde(default)]
+    pub id: i64,
+    pub image_key: String,
+    pub mime_type: Option<String>,
+    pub data_base64: Option<String>,
+}
+
+impl Record for Image {
+    const TABLE: &'static str = "images";
+    const COLUMNS: &'static [&'static str] = &["image_key", "mime_type", "data_base64"];
+
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Image {
+            id: row.get("id")?,
+            image_key: row.get("image_key")?,
+            mime_type: row.get("mime_type")?,
+            data_base64: row.get("data_base64")?,
+        })
+    }
+
+    fn values(&self) -> Vec<&dyn ToSql> {
+        vec![&self.image_key, &self.mime_type, &self.data_base64]
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.image_key.trim().is_empty() {
+            return Err("image_key must not be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_amounts_are_rejected() {
+        let seller = Seller {
+            transport_costs: Some(-1.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            seller.validate(),
+            Err("transport_costs must not be negative".to_string())
+        );
+
+        let piece = WoodPiece {
+            min_price: Some(-0.01),
+            ..Default::default()
+        };
+        assert_eq!(
+            piece.validate(),
+            Err("min_price must not be negative".to_string())
+        );
+
+        let offer = WoodPieceOffer {
+            offered_price: Some(-5.0),
+            ..Default::default()
+        };
+        assert!(offer.validate().is_err());
+
+        let settings = Settings {
+            licitator_percentage: Some(-0.06),
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+
+        let buyer = Buyer {
+            loading_costs: Some(-5.0),
+            ..Default::default()
+        };
+        assert!(buyer.validate().is_err());
+    }
+
+    #[test]
+    fn zero_and_missing_amounts_are_valid() {
+        let seller = Seller {
+            transport_costs: Some(0.0),
+            ..Default::default()
+        };
+        assert_eq!(seller.validate(), Ok(()));
+        assert_eq!(WoodPiece::default().validate(), Ok(()));
+        assert_eq!(TreeSpecies::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn image_key_is_required() {
+        let image = Image {
+            image_key: "  ".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            image.validate(),
+            Err("image_key must not be empty".to_string())
+        );
+
+        let image = Image {
+            image_key: "logo".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(image.validate(), Ok(()));
+    }
+}
@@ -0,0 +1,330 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use std::error::Error;
+
+use crate::models::{
+    Buyer, Image, Record, Seller, Settings, TreeSpecies, WoodPiece, WoodPieceOffer,
+};
+use crate::shared::get_connection;
+use crate::undo::with_group;
+
+pub fn list<T: Record>(conn: &Connection) -> Result<Vec<T>, Box<dyn Error>> {
+    let query = format!("SELECT * FROM {} ORDER BY id", T::TABLE);
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map([], |row| T::from_row(row))?;
+
+    Ok(rows.collect::<Result<Vec<T>, _>>()?)
+}
+
+pub fn get<T: Record>(conn: &Connection, id: i64) -> Result<Option<T>, Box<dyn Error>> {
+    let query = format!("SELECT * FROM {} WHERE id = ?1", T::TABLE);
+
+    Ok(conn
+        .query_row(&query, params![id], |row| T::from_row(row))
+        .optional()?)
+}
+
+pub fn create<T: Record>(conn: &Connection, record: &T) -> Result<T, Box<dyn Error>> {
+    record.validate()?;
+
+    let placeholders: Vec<String> = (1..=T::COLUMNS.len()).map(|i| format!("?{}", i)).collect();
+    let query = format!(
+        "INSERT INTO {} ({}) VALUES ({});",
+        T::TABLE,
+        T::COLUMNS.join(", "),
+        placeholders.join(", ")
+    );
+    conn.execute(&query, record.values().as_slice())?;
+
+    // Read the row back so generated columns and defaults are filled in
+    let id = conn.last_insert_rowid();
+    get(conn, id)?.ok_or_else(|| format!("{} with id {} was not created", T::TABLE, id).into())
+}
+
+pub fn update<T: Record>(conn: &Connection, record: &T) -> Result<T, Box<dyn Error>> {
+    record.validate()?;
+
+    let set_statements: Vec<String> = T::COLUMNS
+        .iter()
+        .enumerate()
+        .map(|(i, col)| format!("{} = ?{}", col, i + 1))
+        .collect();
+    let query = format!(
+        "UPDATE {} SET {} WHERE id = ?{};",
+        T::TABLE,
+        set_statements.join(", "),
+        T::COLUMNS.len() + 1
+    );
+
+    let id = record.id();
+    let mut values = record.values();
+    values.push(&id);
+
+    if conn.execute(&query, values.as_slice())? == 0 {
+        return Err(format!("{} with id {} does not exist", T::TABLE, id).into());
+    }
+
+    get(conn, id)?.ok_or_else(|| format!("{} with id {} does not exist", T::TABLE, id).into())
+}
+
+pub fn delete<T: Record>(conn: &Connection, id: i64) -> Result<(), Box<dyn Error>> {
+    let query = format!("DELETE FROM {} WHERE id = ?1;", T::TABLE);
+
+    if conn.execute(&query, params![id])? == 0 {
+        return Err(format!("{} with id {} does not exist", T::TABLE, id).into());
+    }
+
+    Ok(())
+}
+
+// Generates the list/get/create/update/delete Tauri commands for one record type
+macro_rules! crud_commands {
+    ($record:ty, $list:ident, $get:ident, $create:ident, $update:ident, $delete:ident) => {
+        #[tauri::command]
+        pub fn $list(app_handle: tauri::AppHandle) -> Result<Vec<$record>, String> {
+            let conn =
+                get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+            list::<$record>(&conn).map_err(|e| e.to_string())
+        }
+
+        #[tauri::command]
+        pub fn $get(app_handle: tauri::AppHandle, id: i64) -> Result<Option<$record>, String> {
+            let conn =
+                get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+            get::<$record>(&conn, id).map_err(|e| e.to_string())
+        }
+
+        #[tauri::command]
+        pub fn $create(app_handle: tauri::AppHandle, record: $record) -> Result<$record, String> {
+            let conn =
+                get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+            with_group(&conn, stringify!($create), || create(&conn, &record))
+                .map_err(|e| e.to_string())
+        }
+
+        #[tauri::command]
+        pub fn $update(app_handle: tauri::AppHandle, record: $record) -> Result<$record, String> {
+            let conn =
+                get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+            with_group(&conn, stringify!($update), || update(&conn, &record))
+                .map_err(|e| e.to_string())
+        }
+
+        #[tauri::command]
+        pub fn $delete(app_handle: tauri::AppHandle, id: i64) -> Result<(), String> {
+            let conn =
+                get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+            with_group(&conn, stringify!($delete), || delete::<$record>(&conn, id))
+                .map_err(|e| e.to_string())
+        }
+    };
+}
+
+crud_commands!(
+    Seller,
+    list_sellers,
+    get_seller,
+    create_seller,
+    update_seller,
+    delete_seller
+);
+crud_commands!(
+    Buyer,
+    list_buyers,
+    get_buyer,
+    create_buyer,
+    update_buyer,
+    delete_buyer
+);
+crud_commands!(
+    TreeSpecies,
+    list_tree_species,
+    get_tree_species,
+    create_tree_species,
+    update_tree_species,
+    delete_tree_species
+);
+crud_commands!(
+    WoodPiece,
+    list_wood_pieces,
+    get_wood_piece,
+    create_wood_piece,
+    update_wood_piece,
+    delete_wood_piece
+);
+crud_commands!(
+    WoodPieceOffer,
+    list_wood_piece_offers,
+    get_wood_piece_offer,
+    create_wood_piece_offer,
+    update_wood_piece_offer,
+    delete_wood_piece_offer
+);
+crud_commands!(
+    Settings,
+    list_settings,
+    get_settings,
+    create_settings,
+    update_settings,
+    delete_settings
+);
+crud_commands!(
+    Image,
+    list_images,
+    get_image,
+    create_image,
+    update_image,
+    delete_image
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::open_test_database;
+
+    fn new_seller(conn: &Connection) -> Seller {
+        create(
+            conn,
+            &Seller {
+                seller_name: Some("Seller".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    }
+
+    fn new_piece(conn: &Connection, seller_id: i64) -> WoodPiece {
+        create(
+            conn,
+            &WoodPiece {
+                length: Some(4.0),
+                width: Some(50.0),
+                seller_id: Some(seller_id),
+                tree_species_id: Some(1),
+                plate_no: Some("P1".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn create_reads_back_generated_columns() {
+        let conn = open_test_database();
+        let seller = new_seller(&conn);
+        let piece = new_piece(&conn, seller.id);
+
+        assert!(piece.id > 0);
+        assert_eq!(piece.volume, Some(0.79));
+        assert_eq!(
+            get::<WoodPiece>(&conn, piece.id).unwrap().unwrap().volume,
+            Some(0.79)
+        );
+    }
+
+    #[test]
+    fn create_and_update_validate_first() {
+        let conn = open_test_database();
+        let error = create(
+            &conn,
+            &Seller {
+                logging_costs: Some(-2.0),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "logging_costs must not be negative");
+        assert!(list::<Seller>(&conn).unwrap().is_empty());
+
+        let mut seller = new_seller(&conn);
+        seller.logging_costs = Some(-2.0);
+        assert!(update(&conn, &seller).is_err());
+        assert_eq!(
+            get::<Seller>(&conn, seller.id)
+                .unwrap()
+                .unwrap()
+                .logging_costs,
+            None
+        );
+    }
+
+    #[test]
+    fn update_changes_the_row() {
+        let conn = open_test_database();
+        let mut seller = new_seller(&conn);
+        seller.seller_name = Some("Renamed".to_string());
+        seller.transport_costs = Some(10.5);
+
+        let updated = update(&conn, &seller).unwrap();
+        assert_eq!(updated.seller_name.as_deref(), Some("Renamed"));
+        assert_eq!(updated.transport_costs, Some(10.5));
+    }
+
+    #[test]
+    fn missing_rows_are_reported() {
+        let conn = open_test_database();
+        let seller = Seller {
+            id: 42,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            update(&conn, &seller).unwrap_err().to_string(),
+            "sellers with id 42 does not exist"
+        );
+        assert_eq!(
+            delete::<Seller>(&conn, 42).unwrap_err().to_string(),
+            "sellers with id 42 does not exist"
+        );
+        assert!(get::<Seller>(&conn, 42).unwrap().is_none());
+    }
+
+    #[test]
+    fn references_must_exist() {
+        let conn = open_test_database();
+        let error = create(
+            &conn,
+            &WoodPieceOffer {
+                offered_price: Some(100.0),
+                wood_piece_id: Some(999),
+                buyer_id: Some(999),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("FOREIGN KEY constraint failed"));
+        assert!(list::<WoodPieceOffer>(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn referenced_rows_cannot_be_deleted() {
+        let conn = open_test_database();
+        let seller = new_seller(&conn);
+        let piece = new_piece(&conn, seller.id);
+
+        let error = delete::<Seller>(&conn, seller.id).unwrap_err();
+        assert!(error.to_string().contains("FOREIGN KEY constraint failed"));
+
+        delete::<WoodPiece>(&conn, piece.id).unwrap();
+        delete::<Seller>(&conn, seller.id).unwrap();
+        assert!(list::<Seller>(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unique_constraints_are_enforced() {
+        let conn = open_test_database();
+        // "header" is one of the seeded images
+        let error = create(
+            &conn,
+            &Image {
+                image_key: "header".to_string(),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("UNIQUE constraint failed: images.image_key"));
+    }
+}
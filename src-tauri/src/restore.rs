@@ -0,0 +1,318 @@
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::migrations::{get_migrations, get_schema_version};
+use crate::snapshot::count_rows;
+use crate::tables::TABLES;
+
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+#[derive(Debug, Serialize)]
+pub struct MissingColumn {
+    pub table: String,
+    pub column: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BackupReport {
+    // Highest migration recorded by the SQL plugin in the backup, if any
+    pub schema_version: Option<i64>,
+    // Highest migration this build of the app knows about
+    pub expected_schema_version: i64,
+    pub row_counts: BTreeMap<String, i64>,
+    // What was done to bring a copy of the backup up to the current schema before it was
+    // loaded, empty when it was current already or was only inspected
+    pub upgrade_steps: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RestoreError {
+    // The file is not a SQLite database at all
+    NotSqlite { message: String },
+    // The file is a SQLite database, but not one written by this app
+    SchemaMismatch {
+        missing_tables: Vec<String>,
+        missing_columns: Vec<MissingColumn>,
+        report: BackupReport,
+    },
+    // The backup was made by a newer version of the app
+    NewerSchema { report: BackupReport },
+    Failed { message: String },
+}
+
+impl RestoreError {
+    pub fn failed(message: impl ToString) -> Self {
+        RestoreError::Failed {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestoreError::NotSqlite { message } => write!(f, "Not a SQLite database: {}", message),
+            RestoreError::SchemaMismatch {
+                missing_tables,
+                missing_columns,
+                ..
+            } => {
+                let columns: Vec<String> = missing_columns
+                    .iter()
+                    .map(|c| format!("{}.{}", c.table, c.column))
+                    .collect();
+                write!(
+                    f,
+                    "Backup does not match the database schema, missing tables: [{}], missing columns: [{}]",
+                    missing_tables.join(", "),
+                    columns.join(", ")
+                )
+            }
+            RestoreError::NewerSchema { report } => write!(
+                f,
+                "Backup was made by a newer version of the app (schema {:?}, expected {})",
+                report.schema_version, report.expected_schema_version
+            ),
+            RestoreError::Failed { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+// Table name to column names
+pub type Schema = BTreeMap<String, BTreeSet<String>>;
+
+pub fn read_schema(conn: &Connection) -> Result<Schema, Box<dyn Error>> {
+    let mut stmt = conn.prepare(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%';",
+    )?;
+    let tables = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut schema = Schema::new();
+    for table in tables {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_xinfo(\"{}\");", table))?;
+        let columns = stmt
+            .query_map([], |row| row.get::<_, String>("name"))?
+            .collect::<Result<BTreeSet<_>, _>>()?;
+        schema.insert(table, columns);
+    }
+
+    Ok(schema)
+}
+
+// An in-memory database with all migrations applied, i.e. the schema this build expects
+pub fn open_expected_database() -> Result<Connection, Box<dyn Error>> {
+    let mut migrations = get_migrations();
+    migrations.sort_by_key(|m| m.version);
+
+    let conn = Connection::open_in_memory()?;
+    for migration in &migrations {
+        conn.execute_batch(migration.sql)?;
+    }
+
+    Ok(conn)
+}
+
+// The tables and columns a backup must have to be loaded. Everything added since, like
+// the undo log or added columns, is created when a copy of the backup is upgraded.
+fn required_schema() -> Schema {
+    TABLES
+        .iter()
+        .map(|table| {
+            let columns = std::iter::once("id")
+                .chain(table.columns.iter().copied())
+                .map(String::from)
+                .collect();
+            (table.name.to_string(), columns)
+        })
+        .collect()
+}
+
+// Highest migration the SQL plugin recorded as applied to the database
+pub fn read_schema_version(conn: &Connection) -> Result<Option<i64>, Box<dyn Error>> {
+    let has_migrations_table: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations';",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_migrations_table {
+        return Ok(None);
+    }
+
+    Ok(conn
+        .query_row(
+            "SELECT MAX(version) FROM _sqlx_migrations WHERE success = 1;",
+            [],
+            |row| row.get::<_, Option<i64>>(0),
+        )
+        .optional()?
+        .flatten())
+}
+
+fn check_header(path: &Path) -> Result<(), RestoreError> {
+    let mut header = [0u8; 16];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .map_err(|e| RestoreError::NotSqlite {
+            message: e.to_string(),
+        })?;
+
+    if &header != SQLITE_HEADER {
+        return Err(RestoreError::NotSqlite {
+            message: format!("{} has no SQLite header", path.display()),
+        });
+    }
+
+    Ok(())
+}
+
+// Checks that `path` is a database this version of the app can load once a copy of it is
+// upgraded, older backups only need the auction tables
+pub fn validate_backup_file(path: &Path) -> Result<BackupReport, RestoreError> {
+    check_header(path)?;
+
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| RestoreError::NotSqlite {
+            message: e.to_string(),
+        })?;
+
+    let schema = read_schema(&conn).map_err(|e| RestoreError::NotSqlite {
+        message: e.to_string(),
+    })?;
+    let expected_schema_version = get_schema_version();
+
+    let report = BackupReport {
+        schema_version: read_schema_version(&conn).map_err(RestoreError::failed)?,
+        expected_schema_version,
+        row_counts: count_rows(&conn).map_err(RestoreError::failed)?,
+        upgrade_steps: vec![],
+    };
+
+    let mut missing_tables = vec![];
+    let mut missing_columns = vec![];
+    for (table, columns) in &required_schema() {
+        match schema.get(table) {
+            None => missing_tables.push(table.clone()),
+            Some(found) => {
+                for column in columns.difference(found) {
+                    missing_columns.push(MissingColumn {
+                        table: table.clone(),
+                        column: column.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    if !missing_tables.is_empty() || !missing_columns.is_empty() {
+        return Err(RestoreError::SchemaMismatch {
+            missing_tables,
+            missing_columns,
+            report,
+        });
+    }
+
+    if report.schema_version.unwrap_or(0) > expected_schema_version {
+        return Err(RestoreError::NewerSchema { report });
+    }
+
+    Ok(report)
+}
+
+#[tauri::command]
+pub fn inspect_backup(file_path: String) -> Result<BackupReport, RestoreError> {
+    validate_backup_file(Path::new(&file_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::legacy::{copy_database, upgrade_legacy_database};
+
+    // Schema 202 is what the released app wrote, with its bookkeeping
+    fn write_released_backup(path: &Path) {
+        let conn = Connection::open(path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE _sqlx_migrations (
+                version BIGINT PRIMARY KEY,
+                description TEXT NOT NULL,
+                installed_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                success BOOLEAN NOT NULL,
+                checksum BLOB NOT NULL,
+                execution_time BIGINT NOT NULL
+            );",
+        )
+        .unwrap();
+
+        let mut migrations = get_migrations();
+        migrations.sort_by_key(|m| m.version);
+        for migration in migrations.iter().filter(|m| m.version <= 202) {
+            conn.execute_batch(migration.sql).unwrap();
+            conn.execute(
+                "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+                VALUES (?1, ?2, TRUE, x'00', 0);",
+                rusqlite::params![migration.version, migration.description],
+            )
+            .unwrap();
+        }
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name) VALUES (1, 'Seller');
+            INSERT INTO wood_pieces (sequence_no, plate_no, seller_id, tree_species_id)
+                VALUES (1, 'A1', 1, 1);",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn released_backups_are_upgraded_instead_of_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup_path = dir.path().join("backup.db");
+        write_released_backup(&backup_path);
+
+        let report = validate_backup_file(&backup_path).unwrap();
+        assert_eq!(report.schema_version, Some(202));
+        assert_eq!(report.row_counts["wood_pieces"], 1);
+
+        let copy = copy_database(&backup_path, &dir.path().join("copy.db")).unwrap();
+        assert!(!upgrade_legacy_database(&copy).unwrap().is_empty());
+
+        let mut schema = read_schema(&copy).unwrap();
+        schema.remove("_sqlx_migrations");
+        let expected = read_schema(&open_expected_database().unwrap()).unwrap();
+        assert_eq!(schema, expected);
+        assert_eq!(
+            read_schema_version(&copy).unwrap(),
+            Some(get_schema_version())
+        );
+    }
+
+    #[test]
+    fn missing_auction_tables_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup_path = dir.path().join("backup.db");
+        write_released_backup(&backup_path);
+        Connection::open(&backup_path)
+            .unwrap()
+            .execute_batch("DROP TABLE wood_piece_offers;")
+            .unwrap();
+
+        match validate_backup_file(&backup_path) {
+            Err(RestoreError::SchemaMismatch {
+                missing_tables,
+                missing_columns,
+                ..
+            }) => {
+                assert_eq!(missing_tables, vec!["wood_piece_offers"]);
+                assert!(missing_columns.is_empty());
+            }
+            other => panic!("Expected a schema mismatch, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,627 @@
+use rusqlite::{Connection, OptionalExtension};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Serialize;
+use std::error::Error;
+
+use crate::shared::get_connection;
+
+// Up to this price per m3 the licitator takes a fixed cost per m3, above it a percentage
+// of the part of the price over it
+const FEE_THRESHOLD_PRICE: Decimal = Decimal::from_parts(350, 0, 0, false, 0);
+// 8%
+const FLAT_RATE_TAX: Decimal = Decimal::from_parts(8, 0, 0, false, 2);
+// 22%
+const VAT: Decimal = Decimal::from_parts(22, 0, 0, false, 2);
+// 9.5%, logging in woods
+const LOGGING_VAT: Decimal = Decimal::from_parts(95, 0, 0, false, 3);
+
+// Amounts are serialized as strings so no precision is lost on the way to the frontend
+#[derive(Debug, Serialize)]
+pub struct SettlementLine {
+    pub wood_piece_id: i64,
+    pub sequence_no: Option<i64>,
+    pub plate_no: Option<String>,
+    pub volume: Decimal,
+    // The winning offer, none if the piece was not sold
+    pub buyer_id: Option<i64>,
+    pub offered_price: Option<Decimal>,
+    pub offered_total_price: Option<Decimal>,
+    // Licitator fees of the piece, only the totals are rounded
+    pub costs_below_350: Decimal,
+    pub costs_above_350: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SellerSettlement {
+    // None for the pieces without a seller
+    pub seller_id: Option<i64>,
+    pub seller_name: Option<String>,
+    pub seller_ident: Option<String>,
+    pub lines: Vec<SettlementLine>,
+    // Every piece of the seller, sold or not
+    pub total_volume: Decimal,
+    pub total_price: Decimal,
+    pub costs_below_350: Decimal,
+    pub costs_above_350: Decimal,
+    pub seller_income_gross: Decimal,
+    pub seller_income_tax_flat: Decimal,
+    pub seller_income_tax_vat: Decimal,
+    pub seller_income_gross_after_tax: Decimal,
+    pub transport_costs: Decimal,
+    pub transport_vat: Decimal,
+    pub logging_costs: Decimal,
+    pub logging_costs_vat: Decimal,
+    pub logging_costs_non_woods_vat: Decimal,
+    pub payout: Decimal,
+}
+
+// Columns of the seller statements reports, the amounts are `SellerSettlement::amounts`
+pub const STATEMENT_COLUMNS: [&str; 18] = [
+    "seller_name",
+    "seller_ident",
+    "num_sold_pieces",
+    "total_volume",
+    "total_price",
+    "costs_below_350",
+    "costs_above_350",
+    "seller_income_gross",
+    "seller_income_tax_flat",
+    "seller_income_tax_vat",
+    "seller_income_gross_after_tax",
+    "transport_costs",
+    "transport_vat",
+    "logging_costs",
+    "logging_costs_vat",
+    "logging_costs_non_woods_vat",
+    "payout",
+    "unsold_volume",
+];
+
+impl SellerSettlement {
+    fn sold_lines(&self) -> impl Iterator<Item = &SettlementLine> {
+        self.lines
+            .iter()
+            .filter(|line| line.offered_price.is_some())
+    }
+
+    pub fn num_sold_pieces(&self) -> usize {
+        self.sold_lines().count()
+    }
+
+    // The amounts of STATEMENT_COLUMNS, from total_volume on
+    pub fn amounts(&self) -> [Decimal; 15] {
+        let sold_volume: Decimal = self.sold_lines().map(|line| line.volume).sum();
+
+        [
+            self.total_volume,
+            self.total_price,
+            self.costs_below_350,
+            self.costs_above_350,
+            self.seller_income_gross,
+            self.seller_income_tax_flat,
+            self.seller_income_tax_vat,
+            self.seller_income_gross_after_tax,
+            self.transport_costs,
+            self.transport_vat,
+            self.logging_costs,
+            self.logging_costs_vat,
+            self.logging_costs_non_woods_vat,
+            self.payout,
+            self.total_volume - sold_volume,
+        ]
+    }
+}
+
+// Totals of the settlements of all sellers, as on the statistics screen
+#[derive(Debug, Default, Serialize)]
+pub struct AuctionStatistics {
+    pub num_wood_pieces: usize,
+    pub num_unsold_wood_pieces: usize,
+    pub total_volume: Decimal,
+    // Highest price per m3 a piece was sold for
+    pub offered_max_price: Option<Decimal>,
+    // What the buyers pay the sellers, before fees and taxes
+    pub sellers_net: Decimal,
+    pub costs_below_350: Decimal,
+    pub costs_above_350: Decimal,
+    pub total_transport_costs: Decimal,
+    pub total_logging_costs: Decimal,
+    // Fees, transport and logging, without VAT
+    pub seller_costs: Decimal,
+}
+
+// A piece without a seller has no costs besides the licitator fees
+#[derive(Default)]
+struct SellerTerms {
+    seller_name: Option<String>,
+    seller_ident: Option<String>,
+    is_flat_rate: bool,
+    is_vat_liable: bool,
+    used_transport: bool,
+    used_logging: bool,
+    used_logging_non_woods: bool,
+    transport_costs: Decimal,
+    logging_costs: Decimal,
+}
+
+// Cents, halves away from zero like the invoices always did (Big.js round)
+fn round(value: Decimal) -> Decimal {
+    value.round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero)
+}
+
+// The values are stored as REAL, they are read as the shortest decimal that is the same
+// float, so 0.1 is 0.1 like in Big.js
+fn to_decimal(value: Option<f64>) -> Result<Decimal, Box<dyn Error>> {
+    let value = value.unwrap_or(0.0);
+    Ok(value
+        .to_string()
+        .parse()
+        .map_err(|e| format!("{} is not a valid amount: {}", value, e))?)
+}
+
+fn get_flag(row: &rusqlite::Row, column: &str) -> rusqlite::Result<bool> {
+    Ok(row.get::<_, Option<i64>>(column)?.unwrap_or(0) > 0)
+}
+
+fn get_seller_terms(conn: &Connection, seller_id: i64) -> Result<SellerTerms, Box<dyn Error>> {
+    let terms = conn
+        .query_row(
+            "SELECT seller_name, ident, is_flat_rate, is_vat_liable, used_transport,
+                used_logging, used_logging_non_woods, transport_costs, logging_costs
+            FROM sellers WHERE id = ?1;",
+            [seller_id],
+            |row| {
+                Ok((
+                    row.get::<_, Option<String>>("seller_name")?,
+                    row.get::<_, Option<String>>("ident")?,
+                    get_flag(row, "is_flat_rate")?,
+                    get_flag(row, "is_vat_liable")?,
+                    get_flag(row, "used_transport")?,
+                    get_flag(row, "used_logging")?,
+                    get_flag(row, "used_logging_non_woods")?,
+                    row.get::<_, Option<f64>>("transport_costs")?,
+                    row.get::<_, Option<f64>>("logging_costs")?,
+                ))
+            },
+        )
+        .optional()?
+        .ok_or_else(|| format!("Seller with id {} does not exist", seller_id))?;
+
+    Ok(SellerTerms {
+        seller_name: terms.0,
+        seller_ident: terms.1,
+        is_flat_rate: terms.2,
+        is_vat_liable: terms.3,
+        used_transport: terms.4,
+        used_logging: terms.5,
+        used_logging_non_woods: terms.6,
+        transport_costs: to_decimal(terms.7)?,
+        logging_costs: to_decimal(terms.8)?,
+    })
+}
+
+// The winning offer of every piece: the highest one, earlier offers win ties. Unless
+// `preview` is set the offer must reach the minimum price or the piece must bypass it.
+// The sold pieces reports use it too, so they always agree with the statements.
+pub fn get_winning_offers_sql(preview: bool) -> String {
+    format!(
+        "SELECT offers.id, offers.wood_piece_id, offers.buyer_id, offers.offered_price
+        FROM (
+            SELECT *, ROW_NUMBER() OVER (
+                PARTITION BY wood_piece_id ORDER BY offered_price DESC, id ASC
+            ) AS seq_num
+            FROM wood_piece_offers
+        ) offers
+        JOIN wood_pieces ON wood_pieces.id = offers.wood_piece_id
+        WHERE offers.seq_num = 1 {}",
+        if preview {
+            ""
+        } else {
+            "AND (offers.offered_price >= wood_pieces.min_price OR wood_pieces.bypass_min_price = 1)"
+        }
+    )
+}
+
+// The pieces of the seller with their winning offers, `None` for the pieces without a
+// seller
+fn get_lines(
+    conn: &Connection,
+    seller_id: Option<i64>,
+    preview: bool,
+    fixed_cost: Decimal,
+    percentage: Decimal,
+) -> Result<Vec<SettlementLine>, Box<dyn Error>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT wood_pieces.id, wood_pieces.sequence_no, wood_pieces.plate_no,
+            wood_pieces.volume, offers.offered_price, offers.buyer_id
+        FROM wood_pieces
+        LEFT JOIN ({}) offers ON offers.wood_piece_id = wood_pieces.id
+        WHERE wood_pieces.seller_id IS ?1
+        ORDER BY wood_pieces.sequence_no, wood_pieces.id;",
+        get_winning_offers_sql(preview)
+    ))?;
+    let mut rows = stmt.query([seller_id])?;
+
+    let mut lines = vec![];
+    while let Some(row) = rows.next()? {
+        let volume = to_decimal(row.get("volume")?)?;
+        let offered_price = match row.get::<_, Option<f64>>("offered_price")? {
+            Some(price) => Some(to_decimal(Some(price))?),
+            None => None,
+        };
+        let offered_total_price = offered_price.map(|price| round(price * volume));
+
+        let costs_above_350 = match (offered_price, offered_total_price) {
+            (Some(price), Some(total)) if price > FEE_THRESHOLD_PRICE => {
+                percentage * (total - FEE_THRESHOLD_PRICE * volume)
+            }
+            _ => Decimal::ZERO,
+        };
+
+        lines.push(SettlementLine {
+            wood_piece_id: row.get("id")?,
+            sequence_no: row.get("sequence_no")?,
+            plate_no: row.get("plate_no")?,
+            volume,
+            buyer_id: row.get("buyer_id")?,
+            offered_price,
+            offered_total_price,
+            costs_below_350: fixed_cost * volume,
+            costs_above_350,
+        });
+    }
+
+    Ok(lines)
+}
+
+// Everything on the seller's statement, every amount is rounded to cents before it is used
+// further
+pub fn compute_settlement(
+    conn: &Connection,
+    seller_id: i64,
+    preview: bool,
+) -> Result<SellerSettlement, Box<dyn Error>> {
+    let terms = get_seller_terms(conn, seller_id)?;
+    settle(conn, Some(seller_id), terms, preview)
+}
+
+fn settle(
+    conn: &Connection,
+    seller_id: Option<i64>,
+    terms: SellerTerms,
+    preview: bool,
+) -> Result<SellerSettlement, Box<dyn Error>> {
+    let (fixed_cost, percentage) = conn.query_row(
+        "SELECT licitator_fixed_cost, licitator_percentage FROM settings ORDER BY id LIMIT 1;",
+        [],
+        |row| {
+            Ok((
+                row.get::<_, Option<f64>>("licitator_fixed_cost")?,
+                row.get::<_, Option<f64>>("licitator_percentage")?,
+            ))
+        },
+    )?;
+    let lines = get_lines(
+        conn,
+        seller_id,
+        preview,
+        to_decimal(fixed_cost)?,
+        to_decimal(percentage)?,
+    )?;
+
+    let total_volume = round(lines.iter().map(|line| line.volume).sum());
+    let total_price = round(
+        lines
+            .iter()
+            .filter_map(|line| line.offered_total_price)
+            .sum(),
+    );
+    let costs_below_350 = round(lines.iter().map(|line| line.costs_below_350).sum());
+    let costs_above_350 = round(lines.iter().map(|line| line.costs_above_350).sum());
+    let seller_income_gross = round(total_price - costs_above_350 - costs_below_350);
+
+    // No taxes are added to a loss
+    let taxed = seller_income_gross > Decimal::ZERO;
+    let seller_income_tax_flat = if terms.is_flat_rate && taxed {
+        round(seller_income_gross * FLAT_RATE_TAX)
+    } else {
+        Decimal::ZERO
+    };
+    let seller_income_tax_vat = if terms.is_vat_liable && taxed {
+        round(seller_income_gross * VAT)
+    } else {
+        Decimal::ZERO
+    };
+    let seller_income_gross_after_tax =
+        round(seller_income_gross + seller_income_tax_flat + seller_income_tax_vat);
+
+    let (transport_costs, transport_vat) = if terms.used_transport {
+        let costs = round(total_volume * terms.transport_costs);
+        (costs, round(costs * VAT))
+    } else {
+        (Decimal::ZERO, Decimal::ZERO)
+    };
+
+    let logging_costs = if terms.used_logging || terms.used_logging_non_woods {
+        round(total_volume * terms.logging_costs)
+    } else {
+        Decimal::ZERO
+    };
+    let logging_costs_vat = if terms.used_logging {
+        round(logging_costs * LOGGING_VAT)
+    } else {
+        Decimal::ZERO
+    };
+    let logging_costs_non_woods_vat = if terms.used_logging_non_woods {
+        round(logging_costs * VAT)
+    } else {
+        Decimal::ZERO
+    };
+
+    let payout = round(
+        seller_income_gross_after_tax
+            - transport_costs
+            - transport_vat
+            - logging_costs
+            - logging_costs_vat
+            - logging_costs_non_woods_vat,
+    );
+
+    Ok(SellerSettlement {
+        seller_id,
+        seller_name: terms.seller_name,
+        seller_ident: terms.seller_ident,
+        lines,
+        total_volume,
+        total_price,
+        costs_below_350,
+        costs_above_350,
+        seller_income_gross,
+        seller_income_tax_flat,
+        seller_income_tax_vat,
+        seller_income_gross_after_tax,
+        transport_costs,
+        transport_vat,
+        logging_costs,
+        logging_costs_vat,
+        logging_costs_non_woods_vat,
+        payout,
+    })
+}
+
+// The settlement of every seller with pieces, ordered by name
+pub fn compute_all_settlements(
+    conn: &Connection,
+    preview: bool,
+) -> Result<Vec<SellerSettlement>, Box<dyn Error>> {
+    let mut stmt = conn.prepare(
+        "SELECT id FROM sellers
+        WHERE id IN (SELECT seller_id FROM wood_pieces)
+        ORDER BY seller_name, id;",
+    )?;
+    let seller_ids = stmt
+        .query_map([], |row| row.get::<_, i64>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    seller_ids
+        .into_iter()
+        .map(|seller_id| compute_settlement(conn, seller_id, preview))
+        .collect()
+}
+
+pub fn compute_statistics(conn: &Connection) -> Result<AuctionStatistics, Box<dyn Error>> {
+    let mut settlements = compute_all_settlements(conn, false)?;
+    settlements.push(settle(conn, None, SellerTerms::default(), false)?);
+
+    let mut statistics = AuctionStatistics::default();
+    for settlement in settlements {
+        statistics.num_wood_pieces += settlement.lines.len();
+        statistics.num_unsold_wood_pieces += settlement.lines.len() - settlement.num_sold_pieces();
+        statistics.offered_max_price = settlement
+            .sold_lines()
+            .filter_map(|line| line.offered_price)
+            .chain(statistics.offered_max_price)
+            .max();
+        statistics.total_volume += settlement.total_volume;
+        statistics.sellers_net += settlement.total_price;
+        statistics.costs_below_350 += settlement.costs_below_350;
+        statistics.costs_above_350 += settlement.costs_above_350;
+        statistics.total_transport_costs += settlement.transport_costs;
+        statistics.total_logging_costs += settlement.logging_costs;
+    }
+    statistics.seller_costs = statistics.costs_below_350
+        + statistics.costs_above_350
+        + statistics.total_transport_costs
+        + statistics.total_logging_costs;
+
+    Ok(statistics)
+}
+
+// With `preview` the highest offer counts even when it is below the minimum price
+#[tauri::command]
+pub fn compute_seller_settlement(
+    app_handle: tauri::AppHandle,
+    seller_id: i64,
+    preview: Option<bool>,
+) -> Result<SellerSettlement, String> {
+    let conn: Connection =
+        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+
+    compute_settlement(&conn, seller_id, preview.unwrap_or(false))
+        .map_err(|e| format!("Error computing settlement: {}", e))
+}
+
+#[tauri::command]
+pub fn compute_auction_statistics(
+    app_handle: tauri::AppHandle,
+) -> Result<AuctionStatistics, String> {
+    let conn: Connection =
+        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+
+    compute_statistics(&conn).map_err(|e| format!("Error computing statistics: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::open_test_database;
+
+    fn amount(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    // Fees of the test database: 22 €/m3 up to 350 €/m3, 6% of the price above it.
+    // Volumes: 50 cm x 4 m is 0.79 m3, 60 cm x 5 m is 1.41 m3, 40 cm x 3 m is 0.38 m3 and
+    // 30 cm x 2 m is 0.14 m3.
+    fn open_auction() -> Connection {
+        let conn = open_test_database();
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name, is_flat_rate, is_vat_liable, used_transport,
+                used_logging, used_logging_non_woods, transport_costs, logging_costs)
+            VALUES
+                (1, 'Flat rate', 1, 0, 1, 1, 0, 10, 5),
+                (2, 'VAT liable', 0, 1, 0, 0, 1, 10, 5),
+                (3, 'Loss', 0, 1, 0, 0, 0, NULL, NULL);
+            INSERT INTO buyers (id, buyer_name) VALUES (1, 'First'), (2, 'Second');
+            INSERT INTO wood_pieces (id, sequence_no, width, length, seller_id, min_price)
+            VALUES
+                (1, 1, 50, 4, 1, 100),
+                (2, 2, 60, 5, 1, 100),
+                (3, 3, 40, 3, 1, 200),
+                (4, 4, 50, 4, 2, 100),
+                (5, 5, 30, 2, 3, 50),
+                (6, 6, 50, 4, 3, 100);
+            INSERT INTO wood_piece_offers (offered_price, wood_piece_id, buyer_id)
+            VALUES
+                (400, 1, 1), (390, 1, 2),
+                (300, 2, 2),
+                (150, 3, 1),
+                (500, 4, 2),
+                (100, 5, 1);",
+        )
+        .unwrap();
+
+        conn
+    }
+
+    #[test]
+    fn flat_rate_seller_with_transport_and_logging() {
+        let conn = open_auction();
+        let settlement = compute_settlement(&conn, 1, false).unwrap();
+
+        // Piece 3 stays below its minimum price
+        assert_eq!(settlement.num_sold_pieces(), 2);
+        assert_eq!(settlement.lines[0].buyer_id, Some(1));
+        assert_eq!(settlement.total_volume, amount("2.58"));
+        assert_eq!(settlement.total_price, amount("739.00"));
+        // 22 * 2.58 m3, the unsold piece included
+        assert_eq!(settlement.costs_below_350, amount("56.76"));
+        // 6% of 400 - 350 €/m3 for 0.79 m3, nothing for the piece sold at 300
+        assert_eq!(settlement.costs_above_350, amount("2.37"));
+        assert_eq!(settlement.seller_income_gross, amount("679.87"));
+        assert_eq!(settlement.seller_income_tax_flat, amount("54.39"));
+        assert_eq!(settlement.seller_income_tax_vat, amount("0"));
+        assert_eq!(settlement.seller_income_gross_after_tax, amount("734.26"));
+        assert_eq!(settlement.transport_costs, amount("25.80"));
+        assert_eq!(settlement.transport_vat, amount("5.68"));
+        assert_eq!(settlement.logging_costs, amount("12.90"));
+        // 9.5% of 12.90 is 1.2255
+        assert_eq!(settlement.logging_costs_vat, amount("1.23"));
+        assert_eq!(settlement.logging_costs_non_woods_vat, amount("0"));
+        assert_eq!(settlement.payout, amount("688.65"));
+        assert_eq!(settlement.amounts()[14], amount("0.38"));
+    }
+
+    #[test]
+    fn preview_counts_offers_below_the_minimum_price() {
+        let conn = open_auction();
+        let settlement = compute_settlement(&conn, 1, true).unwrap();
+
+        assert_eq!(settlement.num_sold_pieces(), 3);
+        assert_eq!(
+            settlement.lines[2].offered_total_price,
+            Some(amount("57.00"))
+        );
+        assert_eq!(settlement.total_price, amount("796.00"));
+        assert_eq!(settlement.costs_below_350, amount("56.76"));
+        assert_eq!(settlement.costs_above_350, amount("2.37"));
+        assert_eq!(settlement.seller_income_gross, amount("736.87"));
+        assert_eq!(settlement.seller_income_tax_flat, amount("58.95"));
+        assert_eq!(settlement.seller_income_gross_after_tax, amount("795.82"));
+        assert_eq!(settlement.payout, amount("750.21"));
+    }
+
+    #[test]
+    fn vat_liable_seller_with_logging_outside_the_woods() {
+        let conn = open_auction();
+        let settlement = compute_settlement(&conn, 2, false).unwrap();
+
+        assert_eq!(settlement.total_price, amount("395.00"));
+        assert_eq!(settlement.costs_below_350, amount("17.38"));
+        // 6% of (395 - 350 * 0.79)
+        assert_eq!(settlement.costs_above_350, amount("7.11"));
+        assert_eq!(settlement.seller_income_gross, amount("370.51"));
+        assert_eq!(settlement.seller_income_tax_flat, amount("0"));
+        assert_eq!(settlement.seller_income_tax_vat, amount("81.51"));
+        assert_eq!(settlement.seller_income_gross_after_tax, amount("452.02"));
+        assert_eq!(settlement.transport_costs, amount("0"));
+        assert_eq!(settlement.transport_vat, amount("0"));
+        assert_eq!(settlement.logging_costs, amount("3.95"));
+        assert_eq!(settlement.logging_costs_vat, amount("0"));
+        assert_eq!(settlement.logging_costs_non_woods_vat, amount("0.87"));
+        assert_eq!(settlement.payout, amount("447.20"));
+    }
+
+    #[test]
+    fn no_taxes_are_added_to_a_loss() {
+        let conn = open_auction();
+        let settlement = compute_settlement(&conn, 3, false).unwrap();
+
+        assert_eq!(settlement.total_price, amount("14.00"));
+        assert_eq!(settlement.costs_below_350, amount("20.46"));
+        assert_eq!(settlement.seller_income_gross, amount("-6.46"));
+        assert_eq!(settlement.seller_income_tax_vat, amount("0"));
+        assert_eq!(settlement.seller_income_gross_after_tax, amount("-6.46"));
+        assert_eq!(settlement.payout, amount("-6.46"));
+    }
+
+    #[test]
+    fn statistics_are_the_sum_of_the_settlements() {
+        let conn = open_auction();
+        let statistics = compute_statistics(&conn).unwrap();
+
+        assert_eq!(statistics.num_wood_pieces, 6);
+        assert_eq!(statistics.num_unsold_wood_pieces, 2);
+        assert_eq!(statistics.offered_max_price, Some(amount("500")));
+        assert_eq!(statistics.total_volume, amount("4.30"));
+        assert_eq!(statistics.sellers_net, amount("1148.00"));
+        assert_eq!(statistics.costs_below_350, amount("94.60"));
+        assert_eq!(statistics.costs_above_350, amount("9.48"));
+        assert_eq!(statistics.total_transport_costs, amount("25.80"));
+        assert_eq!(statistics.total_logging_costs, amount("16.85"));
+        assert_eq!(statistics.seller_costs, amount("146.73"));
+    }
+
+    #[test]
+    fn statistics_include_pieces_without_a_seller() {
+        let conn = open_auction();
+        // 0.79 m3 sold for 200 €/m3 and 0.38 m3 that did not reach the minimum price
+        conn.execute_batch(
+            "INSERT INTO wood_pieces (id, sequence_no, width, length, seller_id, min_price)
+            VALUES (7, 7, 50, 4, NULL, 100), (8, 8, 40, 3, NULL, 300);
+            INSERT INTO wood_piece_offers (offered_price, wood_piece_id, buyer_id)
+            VALUES (200, 7, 1), (250, 8, 2);",
+        )
+        .unwrap();
+        let statistics = compute_statistics(&conn).unwrap();
+
+        assert_eq!(statistics.num_wood_pieces, 8);
+        assert_eq!(statistics.num_unsold_wood_pieces, 3);
+        assert_eq!(statistics.total_volume, amount("5.47"));
+        assert_eq!(statistics.sellers_net, amount("1306.00"));
+        assert_eq!(statistics.costs_below_350, amount("120.34"));
+        assert_eq!(statistics.costs_above_350, amount("9.48"));
+        assert_eq!(statistics.total_transport_costs, amount("25.80"));
+        assert_eq!(statistics.total_logging_costs, amount("16.85"));
+    }
+}
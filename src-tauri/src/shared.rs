@@ -1,4 +1,7 @@
 use rusqlite::{Connection, Result, ToSql};
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use tauri::Manager;
 
 pub const SQL_STATEMENT_TREE_SPECIES: &str = "
@@ -33,7 +36,11 @@ pub const SQL_STATEMENT_IMAGES: &str = "
         ('header', NULL, NULL),
         ('wood', NULL, NULL);";
 
-pub fn get_connection(app_handle: tauri::AppHandle) -> Result<Connection, String> {
+// The database file name must stay the same across releases, schema changes are applied
+// in place by the upgrade migrations in `migrations.rs`
+pub const DB_NAME: &str = "main_database_v12.db";
+
+pub fn get_db_path(app_handle: &tauri::AppHandle) -> Result<PathBuf, String> {
     // Get the app data directory path
     let app_data_dir = app_handle
         .path()
@@ -41,8 +48,99 @@ pub fn get_connection(app_handle: tauri::AppHandle) -> Result<Connection, String
         .map_err(|e| format!("Failed to resolve app data directory: {}", e))?;
 
     // Construct the path to the SQLite database file
-    let sqlite_file = app_data_dir.join("main_database_v12.db");
+    Ok(app_data_dir.join(DB_NAME))
+}
+
+pub fn get_connection(app_handle: tauri::AppHandle) -> Result<Connection, String> {
+    let sqlite_file = get_db_path(&app_handle)?;
 
     // Open the SQLite connection
-    Connection::open(sqlite_file).map_err(|e| e.to_string())
+    let conn = Connection::open(sqlite_file).map_err(|e| e.to_string())?;
+
+    // SQLite ignores the declared foreign keys unless they are enabled per connection,
+    // the SQL plugin connections (sqlx) have them enabled by default
+    conn.pragma_update(None, "foreign_keys", "ON").map_err(|e| e.to_string())?;
+
+    Ok(conn)
+}
+
+// A savepoint on a shared connection. Unlike `unchecked_transaction` it also works inside
+// a transaction, e.g. of an undo group, where it can be rolled back on its own. Rolled back
+// when dropped without `commit`.
+pub struct UncheckedSavepoint<'a> {
+    conn: &'a Connection,
+    name: String,
+    finished: bool,
+}
+
+impl<'a> UncheckedSavepoint<'a> {
+    pub fn new(conn: &'a Connection) -> Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let name = format!("sp_{}", COUNTER.fetch_add(1, Ordering::Relaxed));
+        conn.execute_batch(&format!("SAVEPOINT {};", name))?;
+
+        Ok(UncheckedSavepoint {
+            conn,
+            name,
+            finished: false,
+        })
+    }
+
+    pub fn commit(mut self) -> Result<()> {
+        self.finished = true;
+        self.conn.execute_batch(&format!("RELEASE {};", self.name))
+    }
+
+    pub fn rollback(mut self) -> Result<()> {
+        self.finished = true;
+        self.conn
+            .execute_batch(&format!("ROLLBACK TO {0}; RELEASE {0};", self.name))
+    }
+}
+
+impl Deref for UncheckedSavepoint<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+    }
+}
+
+impl Drop for UncheckedSavepoint<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self
+                .conn
+                .execute_batch(&format!("ROLLBACK TO {0}; RELEASE {0};", self.name));
+        }
+    }
+}
+
+// The schema of a freshly migrated database in memory, with foreign keys enforced like on
+// the connections of the app
+#[cfg(test)]
+pub fn open_test_database() -> Connection {
+    let conn = crate::restore::open_expected_database().expect("Error migrating test database");
+    conn.pragma_update(None, "foreign_keys", "ON")
+        .expect("Error enabling foreign keys");
+
+    conn
+}
+
+// Writes `contents` to a file of its own in the temp dir, tests running in parallel never
+// share one
+#[cfg(test)]
+pub fn write_test_file(name: &str, contents: &str) -> std::path::PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let path = std::env::temp_dir().join(format!(
+        "licitacija-test-{}-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        name
+    ));
+    std::fs::write(&path, contents).expect("Error writing test file");
+
+    path
 }
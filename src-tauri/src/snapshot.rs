@@ -0,0 +1,391 @@
+use rusqlite::{Connection, OpenFlags};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::Manager;
+
+use crate::commands::restore_database;
+use crate::restore::{BackupReport, RestoreError};
+use crate::shared::{get_connection, get_db_path};
+
+// How often the background thread snapshots the working database
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+// Keep one snapshot per hour for this long
+const KEEP_HOURLY_FOR: u64 = DAY;
+// Keep one snapshot per day for this long, older snapshots are removed
+const KEEP_DAILY_FOR: u64 = 30 * DAY;
+
+const SNAPSHOT_PREFIX: &str = "snapshot_";
+const SNAPSHOT_EXTENSION: &str = ".db";
+// Reason of the snapshots taken by the background thread
+const AUTO_REASON: &str = "auto";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotInfo {
+    pub file_name: String,
+    // Unix timestamp (seconds) of when the snapshot was taken
+    pub created_at: u64,
+    // What triggered the snapshot, e.g. "auto", "import", "manual"
+    pub reason: String,
+    pub size_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SnapshotDetails {
+    pub info: SnapshotInfo,
+    pub row_counts: BTreeMap<String, i64>,
+}
+
+fn now() -> u64 {
+    now_precise().as_secs()
+}
+
+fn now_precise() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+fn get_snapshot_dir(app_handle: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))?;
+
+    Ok(app_data_dir.join("snapshots"))
+}
+
+// Snapshot files are named `snapshot_<unix seconds>.<millis>_<reason>.db`, with `-<n>` after
+// the millis when several are taken in the same millisecond
+fn parse_snapshot_name(file_name: &str) -> Option<(u64, String)> {
+    let stem = file_name
+        .strip_prefix(SNAPSHOT_PREFIX)?
+        .strip_suffix(SNAPSHOT_EXTENSION)?;
+    let (time, reason) = stem.split_once('_').unwrap_or((stem, ""));
+    let (created_at, millis) = time.split_once('.')?;
+    if millis.is_empty() || !millis.chars().all(|c| c.is_ascii_digit() || c == '-') {
+        return None;
+    }
+
+    Some((created_at.parse().ok()?, reason.to_string()))
+}
+
+fn get_snapshot_path(app_handle: &tauri::AppHandle, file_name: &str) -> Result<PathBuf, String> {
+    // Only accept names produced by `write_snapshot`, never paths
+    if file_name.contains(['/', '\\']) || parse_snapshot_name(file_name).is_none() {
+        return Err(format!("Invalid snapshot name: {}", file_name));
+    }
+
+    let path = get_snapshot_dir(app_handle)?.join(file_name);
+    if !path.exists() {
+        return Err(format!("Snapshot does not exist: {}", file_name));
+    }
+
+    Ok(path)
+}
+
+fn snapshot_info(path: &Path) -> Option<SnapshotInfo> {
+    let file_name = path.file_name()?.to_str()?.to_string();
+    let (created_at, reason) = parse_snapshot_name(&file_name)?;
+    let size_bytes = fs::metadata(path).ok()?.len();
+
+    Some(SnapshotInfo {
+        file_name,
+        created_at,
+        reason,
+        size_bytes,
+    })
+}
+
+// Newest snapshots first
+fn read_snapshots(snapshot_dir: &Path) -> Result<Vec<SnapshotInfo>, Box<dyn Error>> {
+    if !snapshot_dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut snapshots: Vec<SnapshotInfo> = fs::read_dir(snapshot_dir)?
+        .filter_map(Result::ok)
+        .filter_map(|entry| snapshot_info(&entry.path()))
+        .collect();
+    // Snapshots of the same second are ordered by their millis
+    snapshots.sort_by(|a, b| {
+        b.created_at
+            .cmp(&a.created_at)
+            .then_with(|| b.file_name.cmp(&a.file_name))
+    });
+
+    Ok(snapshots)
+}
+
+fn modified_at(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+// Whether the database (or its write-ahead log) changed since the given time
+fn changed_since(db_path: &Path, time: u64) -> bool {
+    let wal_path = PathBuf::from(format!("{}-wal", db_path.display()));
+
+    [db_path, wal_path.as_path()]
+        .iter()
+        .filter_map(|path| modified_at(path))
+        .any(|modified| modified >= time)
+}
+
+// Snapshots to delete so that we keep hourly ones for a day and daily ones for a month.
+// Snapshots taken before an import or restore, or by hand, are never thinned out with the
+// automatic ones, they are all kept for a month.
+fn select_expired(snapshots: &[SnapshotInfo], now: u64) -> Vec<&SnapshotInfo> {
+    let mut seen_buckets = HashSet::new();
+    let mut expired = vec![];
+
+    // `snapshots` are sorted newest first, so the first one in each bucket is kept
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        let age = now.saturating_sub(snapshot.created_at);
+        let keep = if i == 0 {
+            true
+        } else if snapshot.reason != AUTO_REASON {
+            age < KEEP_DAILY_FOR
+        } else if age < KEEP_HOURLY_FOR {
+            seen_buckets.insert(("hour", snapshot.created_at / HOUR))
+        } else if age < KEEP_DAILY_FOR {
+            seen_buckets.insert(("day", snapshot.created_at / DAY))
+        } else {
+            false
+        };
+
+        if !keep {
+            expired.push(snapshot);
+        }
+    }
+
+    expired
+}
+
+fn prune_snapshots(snapshot_dir: &Path) -> Result<(), Box<dyn Error>> {
+    let snapshots = read_snapshots(snapshot_dir)?;
+
+    for snapshot in select_expired(&snapshots, now()) {
+        // A snapshot that is currently open cannot be removed on Windows, retry next time
+        if let Err(e) = fs::remove_file(snapshot_dir.join(&snapshot.file_name)) {
+            println!("Failed to remove snapshot {}: {}", snapshot.file_name, e);
+        }
+    }
+
+    Ok(())
+}
+
+fn backup_to_file(conn: &Connection, path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut dst = Connection::open(path)?;
+    let backup = rusqlite::backup::Backup::new(conn, &mut dst)?;
+    backup.run_to_completion(100, Duration::from_millis(50), None)?;
+
+    Ok(())
+}
+
+pub fn write_snapshot(
+    app_handle: &tauri::AppHandle,
+    reason: &str,
+) -> Result<SnapshotInfo, Box<dyn Error>> {
+    let snapshot_dir = get_snapshot_dir(app_handle)?;
+    fs::create_dir_all(&snapshot_dir)?;
+
+    let conn = get_connection(app_handle.clone())?;
+
+    // Two snapshots taken in the same millisecond get a counter, so neither replaces the other
+    let time = now_precise();
+    let mut file_name = String::new();
+    for attempt in 0.. {
+        file_name = format!(
+            "{}{}.{:03}{}_{}{}",
+            SNAPSHOT_PREFIX,
+            time.as_secs(),
+            time.subsec_millis(),
+            if attempt == 0 {
+                String::new()
+            } else {
+                format!("-{}", attempt)
+            },
+            reason,
+            SNAPSHOT_EXTENSION
+        );
+        if !snapshot_dir.join(&file_name).exists() {
+            break;
+        }
+    }
+
+    // Write to a temporary file first so a crash never leaves a truncated snapshot behind
+    let path = snapshot_dir.join(&file_name);
+    let part_path = snapshot_dir.join(format!("{}.part", file_name));
+
+    if let Err(e) = backup_to_file(&conn, &part_path) {
+        let _ = fs::remove_file(&part_path);
+        return Err(e);
+    }
+    fs::rename(&part_path, &path)?;
+
+    prune_snapshots(&snapshot_dir)?;
+
+    snapshot_info(&path).ok_or_else(|| format!("Failed to read snapshot {}", file_name).into())
+}
+
+fn auto_snapshot(app_handle: &tauri::AppHandle) -> Result<(), Box<dyn Error>> {
+    let db_path = get_db_path(app_handle)?;
+    if !db_path.exists() {
+        return Ok(());
+    }
+
+    // Nothing to do if nobody touched the database since the last snapshot
+    let snapshots = read_snapshots(&get_snapshot_dir(app_handle)?)?;
+    if let Some(last) = snapshots.first() {
+        if !changed_since(&db_path, last.created_at) {
+            return Ok(());
+        }
+    }
+
+    write_snapshot(app_handle, AUTO_REASON)?;
+
+    Ok(())
+}
+
+// Periodically snapshots the working database in a background thread
+pub fn start_snapshots(app_handle: tauri::AppHandle) {
+    thread::spawn(move || loop {
+        thread::sleep(SNAPSHOT_INTERVAL);
+
+        if let Err(e) = auto_snapshot(&app_handle) {
+            println!("Failed to snapshot database: {}", e);
+        }
+    });
+}
+
+#[tauri::command]
+pub fn list_snapshots(app_handle: tauri::AppHandle) -> Result<Vec<SnapshotInfo>, String> {
+    let snapshot_dir = get_snapshot_dir(&app_handle)?;
+
+    read_snapshots(&snapshot_dir).map_err(|e| format!("Error listing snapshots: {}", e))
+}
+
+#[tauri::command]
+pub fn create_snapshot(app_handle: tauri::AppHandle) -> Result<SnapshotInfo, String> {
+    write_snapshot(&app_handle, "manual").map_err(|e| format!("Error creating snapshot: {}", e))
+}
+
+#[tauri::command]
+pub fn inspect_snapshot(
+    app_handle: tauri::AppHandle,
+    file_name: String,
+) -> Result<SnapshotDetails, String> {
+    let path = get_snapshot_path(&app_handle, &file_name)?;
+    let info = snapshot_info(&path).ok_or_else(|| format!("Invalid snapshot: {}", file_name))?;
+
+    let conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("Error opening snapshot: {}", e))?;
+
+    let row_counts = count_rows(&conn).map_err(|e| format!("Error reading snapshot: {}", e))?;
+
+    Ok(SnapshotDetails { info, row_counts })
+}
+
+pub fn count_rows(conn: &Connection) -> Result<BTreeMap<String, i64>, Box<dyn Error>> {
+    let mut stmt = conn.prepare(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name;",
+    )?;
+    let tables = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut row_counts = BTreeMap::new();
+    for table in tables {
+        let count: i64 =
+            conn.query_row(&format!("SELECT COUNT(*) FROM \"{}\";", table), [], |row| {
+                row.get(0)
+            })?;
+        row_counts.insert(table, count);
+    }
+
+    Ok(row_counts)
+}
+
+// Restored like a backup, validated first and with progress events. The current state is
+// kept in a "before_restore" snapshot in case the wrong snapshot was picked.
+#[tauri::command(async)]
+pub fn restore_snapshot(
+    app_handle: tauri::AppHandle,
+    file_name: String,
+) -> Result<BackupReport, RestoreError> {
+    let path = get_snapshot_path(&app_handle, &file_name).map_err(RestoreError::failed)?;
+
+    restore_database(&app_handle, &path, "before_restore")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_names_are_parsed() {
+        assert_eq!(
+            parse_snapshot_name("snapshot_1700000000.042_before_restore.db"),
+            Some((1700000000, "before_restore".to_string()))
+        );
+        assert_eq!(
+            parse_snapshot_name("snapshot_1700000000.042-1_auto.db"),
+            Some((1700000000, "auto".to_string()))
+        );
+        assert_eq!(parse_snapshot_name("snapshot_1700000000.x_auto.db"), None);
+        assert_eq!(parse_snapshot_name("main_database_v12.db"), None);
+    }
+
+    fn snapshot(created_at: u64, reason: &str) -> SnapshotInfo {
+        SnapshotInfo {
+            file_name: format!("snapshot_{}.000_{}.db", created_at, reason),
+            created_at,
+            reason: reason.to_string(),
+            size_bytes: 0,
+        }
+    }
+
+    #[test]
+    fn only_auto_snapshots_share_buckets() {
+        let now = 100 * DAY;
+        // Newest first, like `read_snapshots` returns them
+        let snapshots = [
+            snapshot(now - 60, "auto"),
+            snapshot(now - 120, "before_restore"),
+            snapshot(now - 180, "auto"),
+            snapshot(now - 240, "import"),
+            snapshot(now - 300, "auto"),
+            snapshot(now - 2 * HOUR - 60, "auto"),
+            snapshot(now - 2 * HOUR - 120, "auto"),
+            snapshot(now - 2 * DAY, "manual"),
+            snapshot(now - 2 * DAY - 60, "auto"),
+            snapshot(now - 2 * DAY - 120, "auto"),
+            snapshot(now - 40 * DAY, "before_load"),
+            snapshot(now - 40 * DAY - 60, "auto"),
+        ];
+
+        let expired: Vec<&str> = select_expired(&snapshots, now)
+            .iter()
+            .map(|snapshot| snapshot.file_name.as_str())
+            .collect();
+
+        assert_eq!(
+            expired,
+            [
+                snapshots[4].file_name.as_str(),
+                snapshots[6].file_name.as_str(),
+                snapshots[9].file_name.as_str(),
+                snapshots[10].file_name.as_str(),
+                snapshots[11].file_name.as_str(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,279 @@
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashSet};
+use std::error::Error;
+use std::fs::File;
+
+use crate::export::write_export_of;
+use crate::import::{read_export_rows, scan_export_file};
+use crate::merge::{merge_export_file, ConflictPolicy, MergeSummary};
+use crate::shared::get_connection;
+use crate::snapshot::write_snapshot;
+use crate::undo::with_group;
+
+// Tables whose rows belong to the seller or buyer and are added as new rows on import.
+// Sellers, buyers and tree species are matched to the local ones by their keys.
+const APPENDED_TABLES: [&str; 2] = ["wood_pieces", "wood_piece_offers"];
+
+// Whose records a partial export holds
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubsetOwner {
+    Seller(i64),
+    Buyer(i64),
+}
+
+impl SubsetOwner {
+    // Rows of each table that belong to the subset, as SQL conditions. Every row a
+    // subset row references is part of the subset too.
+    pub fn get_conditions(&self) -> Vec<(&'static str, String)> {
+        match self {
+            SubsetOwner::Seller(id) => {
+                let pieces = format!("SELECT id FROM wood_pieces WHERE seller_id = {}", id);
+                vec![
+                    ("sellers", format!("id = {}", id)),
+                    (
+                        "buyers",
+                        format!(
+                            "id IN (SELECT buyer_id FROM wood_piece_offers WHERE wood_piece_id IN ({}))",
+                            pieces
+                        ),
+                    ),
+                    (
+                        "tree_species",
+                        format!(
+                            "id IN (SELECT tree_species_id FROM wood_pieces WHERE seller_id = {})",
+                            id
+                        ),
+                    ),
+                    ("wood_pieces", format!("seller_id = {}", id)),
+                    ("wood_piece_offers", format!("wood_piece_id IN ({})", pieces)),
+                ]
+            }
+            SubsetOwner::Buyer(id) => {
+                let pieces = format!(
+                    "SELECT wood_piece_id FROM wood_piece_offers WHERE buyer_id = {}",
+                    id
+                );
+                vec![
+                    (
+                        "sellers",
+                        format!(
+                            "id IN (SELECT seller_id FROM wood_pieces WHERE id IN ({}))",
+                            pieces
+                        ),
+                    ),
+                    ("buyers", format!("id = {}", id)),
+                    (
+                        "tree_species",
+                        format!(
+                            "id IN (SELECT tree_species_id FROM wood_pieces WHERE id IN ({}))",
+                            pieces
+                        ),
+                    ),
+                    ("wood_pieces", format!("id IN ({})", pieces)),
+                    ("wood_piece_offers", format!("buyer_id = {}", id)),
+                ]
+            }
+        }
+    }
+
+    fn ensure_exists(&self, conn: &Connection) -> Result<(), Box<dyn Error>> {
+        let (table, name, id) = match self {
+            SubsetOwner::Seller(id) => ("sellers", "Seller", id),
+            SubsetOwner::Buyer(id) => ("buyers", "Buyer", id),
+        };
+
+        let count: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM {} WHERE id = ?1;", table),
+            [id],
+            |row| row.get(0),
+        )?;
+        if count == 0 {
+            return Err(format!("{} with id {} does not exist", name, id).into());
+        }
+
+        Ok(())
+    }
+}
+
+// Everything is read in one transaction, so the tables are of the same moment
+pub fn export_subset_to_json(
+    conn: &Connection,
+    json_path: &str,
+    owner: SubsetOwner,
+) -> Result<(), Box<dyn Error>> {
+    let tx = conn.unchecked_transaction()?;
+    owner.ensure_exists(&tx)?;
+
+    write_export_of(&tx, File::create(json_path)?, Some(owner), |_, _| {})
+}
+
+// Appended pieces continue the catalogue after the last local piece, in their own order.
+// Returns the new sequence number of each piece, by its index in the file.
+fn renumber_pieces(
+    conn: &Connection,
+    sequence_nos: &[Option<i64>],
+) -> Result<Vec<i64>, Box<dyn Error>> {
+    let last_sequence_no: i64 = conn.query_row(
+        "SELECT COALESCE(MAX(sequence_no), 0) FROM wood_pieces;",
+        [],
+        |row| row.get(0),
+    )?;
+
+    let mut order: Vec<usize> = (0..sequence_nos.len()).collect();
+    order.sort_by_key(|&i| sequence_nos[i]);
+    let mut renumbered = vec![0; sequence_nos.len()];
+    for (n, i) in order.into_iter().enumerate() {
+        renumbered[i] = last_sequence_no + n as i64 + 1;
+    }
+
+    Ok(renumbered)
+}
+
+// Plates of the appended pieces that a local piece or another appended piece already has,
+// sorted and each once
+fn find_plate_collisions(
+    conn: &Connection,
+    plates: &[String],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT plate_no FROM wood_pieces WHERE plate_no IS NOT NULL;")?;
+    let mut taken = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<HashSet<_>, _>>()?;
+
+    let mut collisions = BTreeSet::new();
+    for plate_no in plates {
+        if !taken.insert(plate_no.clone()) {
+            collisions.insert(plate_no.clone());
+        }
+    }
+
+    Ok(collisions.into_iter().collect())
+}
+
+pub fn import_subset_from_json(
+    conn: &Connection,
+    json_path: &str,
+) -> Result<MergeSummary, Box<dyn Error>> {
+    let (file_tables, subset) = scan_export_file(json_path)?;
+    if subset.is_none() {
+        return Err("The file is not a partial export of a seller or buyer".into());
+    }
+
+    let mut sequence_nos = vec![];
+    let mut plates = vec![];
+    read_export_rows(json_path, "wood_pieces", |_, row| {
+        sequence_nos.push(row.get("sequence_no").and_then(Value::as_i64));
+        if let Some(plate_no) = row.get("plate_no").and_then(Value::as_str) {
+            plates.push(plate_no.to_string());
+        }
+        Ok(())
+    })?;
+    let renumbered = renumber_pieces(conn, &sequence_nos)?;
+    let plate_collisions = find_plate_collisions(conn, &plates)?;
+
+    let mut summary = merge_export_file(
+        conn,
+        json_path,
+        &file_tables,
+        ConflictPolicy::KeepLocal,
+        &APPENDED_TABLES,
+        |table, row_index, row| {
+            if let (Value::Object(row), "wood_pieces") = (row, table) {
+                row.insert(
+                    "sequence_no".to_string(),
+                    Value::from(renumbered[row_index]),
+                );
+            }
+        },
+    )?;
+    summary.plate_collisions = plate_collisions;
+
+    Ok(summary)
+}
+
+#[tauri::command]
+pub fn export_subset(
+    app_handle: tauri::AppHandle,
+    file_path: String,
+    owner: SubsetOwner,
+) -> Result<(), String> {
+    let conn: Connection =
+        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+
+    export_subset_to_json(&conn, &file_path, owner)
+        .map_err(|e| format!("Error exporting records: {}", e))
+}
+
+// Adds the records of a partial export to the current auction, with new ids
+#[tauri::command]
+pub fn import_subset(
+    app_handle: tauri::AppHandle,
+    file_path: String,
+) -> Result<MergeSummary, String> {
+    let conn: Connection =
+        get_connection(app_handle.clone()).map_err(|e| format!("Error opening database: {}", e))?;
+
+    let summary = with_group(&conn, "subset_import", || {
+        import_subset_from_json(&conn, &file_path)
+    })
+    .map_err(|e| format!("Error importing records: {}", e))?;
+
+    write_snapshot(&app_handle, "subset_import")
+        .map_err(|e| format!("Error creating snapshot: {}", e))?;
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::open_test_database;
+
+    #[test]
+    fn appended_pieces_are_renumbered_and_plate_collisions_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("seller.json");
+        let path = path.to_str().unwrap();
+
+        let other = open_test_database();
+        other
+            .execute_batch(
+                "INSERT INTO sellers (id, seller_name, ident) VALUES (7, 'Seller', 'S7');
+                INSERT INTO wood_pieces (sequence_no, plate_no, seller_id, tree_species_id)
+                    VALUES (3, 'B1', 7, 1), (1, 'A2', 7, 1), (2, 'B1', 7, 1);",
+            )
+            .unwrap();
+        export_subset_to_json(&other, path, SubsetOwner::Seller(7)).unwrap();
+
+        let conn = open_test_database();
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name) VALUES (1, 'Local');
+            INSERT INTO wood_pieces (sequence_no, plate_no, seller_id, tree_species_id)
+                VALUES (1, 'A1', 1, 1), (10, 'A2', 1, 1);",
+        )
+        .unwrap();
+        let summary = import_subset_from_json(&conn, path).unwrap();
+
+        assert!(summary.applied);
+        assert_eq!(summary.plate_collisions, vec!["A2", "B1"]);
+        let mut stmt = conn
+            .prepare("SELECT plate_no, sequence_no FROM wood_pieces WHERE id > 2 ORDER BY id;")
+            .unwrap();
+        let pieces: Vec<(String, i64)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            pieces,
+            vec![
+                ("B1".to_string(), 13),
+                ("A2".to_string(), 11),
+                ("B1".to_string(), 12)
+            ]
+        );
+    }
+}
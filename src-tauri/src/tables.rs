@@ -0,0 +1,302 @@
+use crate::shared::{SQL_STATEMENT_IMAGES, SQL_STATEMENT_SETTINGS, SQL_STATEMENT_TREE_SPECIES};
+
+// A column added to an existing table by an upgrade
+pub struct AddedColumn {
+    // Version of the upgrade adding the column, above every released migration
+    pub version: i64,
+    pub name: &'static str,
+    // Type and constraints as written after the name in ALTER TABLE ADD COLUMN, e.g. "REAL DEFAULT 0"
+    pub definition: &'static str,
+}
+
+// Everything the app needs to know about one of the auction tables. Migrations, undo
+// triggers, JSON export/import and truncation are all generated from these definitions.
+pub struct Table {
+    pub name: &'static str,
+    // What a row is called in descriptions shown to the user, e.g. "piece"
+    pub record_name: &'static str,
+    // SQL of the migration that created the table. Released, so it must never change.
+    pub create_sql: &'static str,
+    // Writable columns of `create_sql`, without `id` and without generated columns
+    pub columns: &'static [&'static str],
+    // Columns added later. Adding one here is all it takes, the upgrade adding the column
+    // and regenerating the triggers is generated from it.
+    pub added_columns: &'static [AddedColumn],
+    // Columns holding whole files, e.g. base64 images. The audit log only records their length.
+    pub large_columns: &'static [&'static str],
+    // (column, referenced table)
+    pub foreign_keys: &'static [(&'static str, &'static str)],
+    // Columns identifying a row across databases, where ids differ. Alternatives are tried
+    // in order, e.g. a wood piece is matched by its plate and only then by its sequence number.
+    pub natural_keys: &'static [&'static [&'static str]],
+    // Whether truncate_all_data empties the table, reference data like the species is kept
+    pub truncate: bool,
+    // Default rows, inserted by a migration and again whenever the table is empty after truncating
+    pub seed_sql: Option<&'static str>,
+}
+
+impl Table {
+    // Writable columns once all upgrades up to and including `version` ran. Released migrations
+    // use this with their own version, so adding columns later does not change their SQL.
+    pub fn columns_at(&self, version: i64) -> Vec<&'static str> {
+        let added = self
+            .added_columns
+            .iter()
+            .filter(|column| column.version <= version)
+            .map(|column| column.name);
+
+        self.columns.iter().copied().chain(added).collect()
+    }
+
+    pub fn current_columns(&self) -> Vec<&'static str> {
+        self.columns_at(i64::MAX)
+    }
+}
+
+// The position of a table determines the versions of its migrations, so only ever append.
+// Tables come after the tables they reference, which is the order to insert rows in.
+pub const TABLES: [Table; 7] = [
+    Table {
+        name: "buyers",
+        record_name: "buyer",
+        create_sql: "CREATE TABLE IF NOT EXISTS buyers (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT, 
+                    buyer_name VARCHAR, 
+                    address_line1 VARCHAR, 
+                    address_line2 VARCHAR,
+                    additional_costs REAL,
+                    is_vat_liable INTEGER DEFAULT 1,
+                    used_bundle INTEGER DEFAULT 1,
+                    used_loading INTEGER DEFAULT 1,
+                    loading_costs REAL DEFAULT 5.00,
+                    ident VARCHAR DEFAULT \"\"
+                );",
+        columns: &[
+            "buyer_name",
+            "address_line1",
+            "address_line2",
+            "additional_costs",
+            "is_vat_liable",
+            "used_bundle",
+            "used_loading",
+            "loading_costs",
+            "ident",
+        ],
+        added_columns: &[],
+        large_columns: &[],
+        foreign_keys: &[],
+        natural_keys: &[&["ident"]],
+        truncate: true,
+        seed_sql: None,
+    },
+    Table {
+        name: "sellers",
+        record_name: "seller",
+        create_sql: "CREATE TABLE IF NOT EXISTS sellers (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT, 
+                    seller_name VARCHAR, 
+                    address_line1 VARCHAR, 
+                    address_line2 VARCHAR,
+                    iban VARCHAR,
+                    ident VARCHAR,
+                    is_flat_rate INTEGER,
+                    is_vat_liable INTEGER,
+                    used_transport INTEGER,
+                    used_logging INTEGER,
+                    used_logging_non_woods INTEGER,
+                    additional_costs REAL,
+                    transport_costs REAL,
+                    logging_costs REAL
+                );",
+        columns: &[
+            "seller_name",
+            "address_line1",
+            "address_line2",
+            "iban",
+            "ident",
+            "is_flat_rate",
+            "is_vat_liable",
+            "used_transport",
+            "used_logging",
+            "used_logging_non_woods",
+            "additional_costs",
+            "transport_costs",
+            "logging_costs",
+        ],
+        added_columns: &[],
+        large_columns: &[],
+        foreign_keys: &[],
+        natural_keys: &[&["ident"]],
+        truncate: true,
+        seed_sql: None,
+    },
+    Table {
+        name: "tree_species",
+        record_name: "tree species",
+        create_sql: "CREATE TABLE IF NOT EXISTS tree_species (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT, 
+                    tree_species_name VARCHAR, 
+                    latin_name VARCHAR,
+                    tree_species_name_slo VARCHAR
+                );",
+        columns: &["tree_species_name", "latin_name", "tree_species_name_slo"],
+        added_columns: &[],
+        large_columns: &[],
+        foreign_keys: &[],
+        natural_keys: &[&["latin_name"], &["tree_species_name"]],
+        truncate: false,
+        seed_sql: Some(SQL_STATEMENT_TREE_SPECIES),
+    },
+    Table {
+        name: "wood_pieces",
+        record_name: "piece",
+        create_sql: "CREATE TABLE IF NOT EXISTS wood_pieces (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT, 
+                    length REAL,
+                    sequence_no INTEGER,
+                    width REAL, 
+                    volume REAL AS (round(3.14159265359 * width * 0.5 * 0.01 * width * 0.5 * 0.01 * length, 2)) STORED, 
+                    plate_no VARCHAR,
+                    seller_id INTEGER,
+                    tree_species_id INTEGER,
+                    min_price REAL, 
+                    bypass_min_price INTEGER,
+                    FOREIGN KEY(seller_id) REFERENCES sellers(id) ON DELETE RESTRICT,
+                    FOREIGN KEY(tree_species_id) REFERENCES tree_species(id)
+                );",
+        // "volume" is generated, so it is never written
+        columns: &[
+            "length",
+            "sequence_no",
+            "width",
+            "plate_no",
+            "seller_id",
+            "tree_species_id",
+            "min_price",
+            "bypass_min_price",
+        ],
+        added_columns: &[],
+        large_columns: &[],
+        foreign_keys: &[("seller_id", "sellers"), ("tree_species_id", "tree_species")],
+        natural_keys: &[&["plate_no"], &["sequence_no"]],
+        truncate: true,
+        seed_sql: None,
+    },
+    Table {
+        name: "wood_piece_offers",
+        record_name: "offer",
+        create_sql: "CREATE TABLE IF NOT EXISTS wood_piece_offers (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT, 
+                    offered_price REAL, 
+                    wood_piece_id INTEGER,
+                    buyer_id INTEGER,
+                    FOREIGN KEY(buyer_id) REFERENCES buyers(id) ON DELETE RESTRICT,
+                    FOREIGN KEY(wood_piece_id) REFERENCES wood_pieces(id) ON DELETE RESTRICT
+                );",
+        columns: &["offered_price", "wood_piece_id", "buyer_id"],
+        added_columns: &[],
+        large_columns: &[],
+        foreign_keys: &[("wood_piece_id", "wood_pieces"), ("buyer_id", "buyers")],
+        // A buyer may bid on a piece more than once, each price is an offer of its own
+        natural_keys: &[&["wood_piece_id", "buyer_id", "offered_price"]],
+        truncate: true,
+        seed_sql: None,
+    },
+    Table {
+        name: "settings",
+        record_name: "settings",
+        create_sql: "CREATE TABLE IF NOT EXISTS settings (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT, 
+                    licitator_fixed_cost REAL, 
+                    licitator_percentage REAL,
+                    bundle_cost REAL
+                );",
+        columns: &["licitator_fixed_cost", "licitator_percentage", "bundle_cost"],
+        added_columns: &[],
+        large_columns: &[],
+        foreign_keys: &[],
+        // There is only ever one row of settings
+        natural_keys: &[&[]],
+        truncate: false,
+        seed_sql: Some(SQL_STATEMENT_SETTINGS),
+    },
+    Table {
+        name: "images",
+        record_name: "image",
+        create_sql: "CREATE TABLE IF NOT EXISTS images (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    image_key VARCHAR NOT NULL UNIQUE,
+                    mime_type VARCHAR,
+                    data_base64 TEXT
+                );",
+        columns: &["image_key", "mime_type", "data_base64"],
+        added_columns: &[],
+        large_columns: &["data_base64"],
+        foreign_keys: &[],
+        natural_keys: &[&["image_key"]],
+        truncate: true,
+        seed_sql: Some(SQL_STATEMENT_IMAGES),
+    },
+];
+
+pub fn get_table(name: &str) -> Option<&'static Table> {
+    TABLES.iter().find(|table| table.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::open_test_database;
+    use rusqlite::Connection;
+    use std::collections::BTreeSet;
+
+    // Names of the columns `pragma` lists for `table`, e.g. table_info or table_xinfo
+    fn read_column_names(conn: &Connection, pragma: &str, table: &str) -> BTreeSet<String> {
+        let mut stmt = conn
+            .prepare(&format!("PRAGMA {}({});", pragma, table))
+            .unwrap();
+        let rows = stmt.query_map([], |row| row.get("name")).unwrap();
+        rows.collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn definitions_match_the_migrated_schema() {
+        let conn = open_test_database();
+
+        for table in &TABLES {
+            let stored = read_column_names(&conn, "table_info", table.name);
+            let expected: BTreeSet<String> = ["id"]
+                .into_iter()
+                .chain(table.current_columns())
+                .map(String::from)
+                .collect();
+            assert_eq!(stored, expected, "columns of {}", table.name);
+
+            // Generated columns are only listed by table_xinfo
+            let generated: Vec<String> = read_column_names(&conn, "table_xinfo", table.name)
+                .difference(&stored)
+                .cloned()
+                .collect();
+            let expected: Vec<&str> = match table.name {
+                "wood_pieces" => vec!["volume"],
+                _ => vec![],
+            };
+            assert_eq!(generated, expected, "generated columns of {}", table.name);
+
+            let mut stmt = conn
+                .prepare(&format!("PRAGMA foreign_key_list({});", table.name))
+                .unwrap();
+            let foreign_keys: BTreeSet<(String, String)> = stmt
+                .query_map([], |row| Ok((row.get("from")?, row.get("table")?)))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            let expected: BTreeSet<(String, String)> = table
+                .foreign_keys
+                .iter()
+                .map(|(column, parent)| (column.to_string(), parent.to_string()))
+                .collect();
+            assert_eq!(foreign_keys, expected, "foreign keys of {}", table.name);
+        }
+    }
+}
@@ -0,0 +1,706 @@
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::error::Error;
+
+use crate::migrations::{UNDO_GROUPS_VERSION, UNDO_HISTORY_VERSION};
+use crate::shared::{get_connection, get_db_path, UncheckedSavepoint};
+use crate::tables::get_table;
+
+// Entries kept in each log, older ones are pruned
+pub const MAX_HISTORY_ENTRIES: i64 = 10_000;
+// Age (seconds) after which entries are pruned
+pub const MAX_HISTORY_AGE: i64 = 30 * 24 * 60 * 60;
+
+#[derive(Debug, Serialize)]
+pub struct UndoStep {
+    // Set when the step undid or redid a whole group
+    pub group_id: Option<i64>,
+    pub label: Option<String>,
+    // The statements that were run, in order
+    pub statements: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryEntry {
+    pub seq: i64,
+    pub group_id: Option<i64>,
+    pub label: Option<String>,
+    // Table, row and operation are unknown for entries logged by older versions of the app
+    pub table: Option<String>,
+    pub row_id: Option<i64>,
+    pub operation: Option<String>,
+    // E.g. "Changed min price of piece 123 from 200 to 250"
+    pub description: String,
+    // Unix timestamp (seconds)
+    pub created_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Direction {
+    Undo,
+    Redo,
+}
+
+impl Direction {
+    // Log the step is taken from
+    fn log(&self) -> &'static str {
+        match self {
+            Direction::Undo => "undolog",
+            Direction::Redo => "redolog",
+        }
+    }
+
+    // Written to undo_state for the triggers, which send the statements reverting an undo to
+    // the redo log and the ones reverting a redo back to the undo log
+    fn mode(&self) -> &'static str {
+        match self {
+            Direction::Undo => "undo",
+            Direction::Redo => "redo",
+        }
+    }
+}
+
+// Opens a group, every change until `end_group` is undone and redone as one step
+pub fn begin_group(conn: &Connection, label: &str) -> Result<i64, Box<dyn Error>> {
+    // A group left open by a crash is closed by starting a new one
+    conn.execute("DELETE FROM undo_state;", [])?;
+    conn.execute("INSERT INTO undo_groups (label) VALUES (?1);", [label])?;
+    let group_id = conn.last_insert_rowid();
+    conn.execute(
+        "INSERT INTO undo_state (mode, group_id) VALUES ('edit', ?1);",
+        [group_id],
+    )?;
+
+    Ok(group_id)
+}
+
+// Groups that changed nothing or whose entries were pruned
+fn delete_unused_groups(conn: &Connection) -> rusqlite::Result<usize> {
+    conn.execute(
+        "DELETE FROM undo_groups WHERE
+            id NOT IN (SELECT group_id FROM undolog WHERE group_id IS NOT NULL)
+            AND id NOT IN (SELECT group_id FROM redolog WHERE group_id IS NOT NULL);",
+        [],
+    )
+}
+
+// Closes the group and prunes the history, the group just closed is always kept
+pub fn end_group(conn: &Connection) -> Result<(), Box<dyn Error>> {
+    conn.execute("DELETE FROM undo_state WHERE mode = 'edit';", [])?;
+    delete_unused_groups(conn)?;
+    prune_history(conn, MAX_HISTORY_ENTRIES, MAX_HISTORY_AGE)?;
+
+    Ok(())
+}
+
+// Runs `f` as one undo step in a transaction. When it fails everything it did is rolled
+// back, the group included. Transactions inside `f` must be savepoints.
+pub fn with_group<T>(
+    conn: &Connection,
+    label: &str,
+    f: impl FnOnce() -> Result<T, Box<dyn Error>>,
+) -> Result<T, Box<dyn Error>> {
+    let tx = conn.unchecked_transaction()?;
+    begin_group(&tx, label)?;
+    let value = f()?;
+    end_group(&tx)?;
+    tx.commit()?;
+
+    Ok(value)
+}
+
+// Runs the last entry of the log, or all entries of its group, and removes them. Must run
+// inside a transaction.
+fn take_step(tx: &Connection, direction: Direction) -> Result<UndoStep, Box<dyn Error>> {
+    let last = tx
+        .query_row(
+            &format!(
+                "SELECT seq, group_id FROM {} ORDER BY seq DESC LIMIT 1;",
+                direction.log()
+            ),
+            [],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<i64>>(1)?)),
+        )
+        .optional()?;
+    let Some((seq, group_id)) = last else {
+        return Err(format!("No actions to {}", direction.mode()).into());
+    };
+
+    // Entries outside of a group are a step of their own
+    let condition = match group_id {
+        Some(group_id) => format!("group_id = {}", group_id),
+        None => format!("seq = {}", seq),
+    };
+    let entries: Vec<(i64, String)> = {
+        let mut stmt = tx.prepare(&format!(
+            "SELECT seq, sql FROM {} WHERE {} ORDER BY seq DESC;",
+            direction.log(),
+            condition
+        ))?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<Result<_, _>>()?
+    };
+    let label: Option<String> = match group_id {
+        Some(group_id) => tx
+            .query_row(
+                "SELECT label FROM undo_groups WHERE id = ?1;",
+                [group_id],
+                |row| row.get(0),
+            )
+            .optional()?,
+        None => None,
+    };
+
+    tx.execute(
+        &format!("DELETE FROM {} WHERE {};", direction.log(), condition),
+        [],
+    )?;
+    // Rows of a group may reference each other in any order, references are checked once
+    // the whole step ran
+    tx.pragma_update(None, "defer_foreign_keys", "ON")?;
+    tx.execute("DELETE FROM undo_state;", [])?;
+    tx.execute(
+        "INSERT INTO undo_state (mode, group_id) VALUES (?1, ?2);",
+        (direction.mode(), group_id),
+    )?;
+    for (seq, sql) in &entries {
+        tx.execute_batch(sql)
+            .map_err(|e| format!("Step {} failed: {}", seq, e))?;
+    }
+    tx.execute("DELETE FROM undo_state;", [])?;
+
+    Ok(UndoStep {
+        group_id,
+        label,
+        statements: entries.into_iter().map(|(_, sql)| sql).collect(),
+    })
+}
+
+// One step, all or nothing
+fn run_step(conn: &Connection, direction: Direction) -> Result<UndoStep, Box<dyn Error>> {
+    let tx = conn.unchecked_transaction()?;
+    let step = take_step(&tx, direction)?;
+    tx.commit()?;
+
+    Ok(step)
+}
+
+pub fn undo_last(conn: &Connection) -> Result<UndoStep, Box<dyn Error>> {
+    run_step(conn, Direction::Undo)
+}
+
+pub fn redo_last(conn: &Connection) -> Result<UndoStep, Box<dyn Error>> {
+    run_step(conn, Direction::Redo)
+}
+
+// Undoes the history entry `seq` and everything after it, all or nothing. A group reaching
+// past `seq` is undone as a whole.
+pub fn undo_to(conn: &Connection, seq: i64) -> Result<Vec<UndoStep>, Box<dyn Error>> {
+    let tx = conn.unchecked_transaction()?;
+
+    let exists: bool = tx.query_row(
+        "SELECT EXISTS (SELECT 1 FROM undolog WHERE seq = ?1);",
+        [seq],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Err(format!("History entry {} does not exist", seq).into());
+    }
+
+    let mut steps = vec![];
+    loop {
+        let last: Option<i64> =
+            tx.query_row("SELECT MAX(seq) FROM undolog;", [], |row| row.get(0))?;
+        if last.is_none_or(|last| last < seq) {
+            break;
+        }
+        steps.push(take_step(&tx, Direction::Undo)?);
+    }
+    tx.commit()?;
+
+    Ok(steps)
+}
+
+fn format_value(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => "empty".to_string(),
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Number(number)) => match number.as_f64() {
+            Some(number) => number.to_string(),
+            None => number.to_string(),
+        },
+        Some(value) => value.to_string(),
+    }
+}
+
+fn parse_row(row: Option<&str>) -> Map<String, Value> {
+    row.and_then(|row| serde_json::from_str(row).ok())
+        .unwrap_or_default()
+}
+
+fn describe(
+    table: Option<&str>,
+    row_id: Option<i64>,
+    operation: Option<&str>,
+    old_row: Option<&str>,
+    new_row: Option<&str>,
+) -> String {
+    let (Some(table), Some(row_id), Some(operation)) = (table, row_id, operation) else {
+        return "Change made by an older version of the app".to_string();
+    };
+    let definition = get_table(table);
+    let record = format!(
+        "{} {}",
+        definition.map_or(table, |definition| definition.record_name),
+        row_id
+    );
+
+    match operation {
+        "insert" => format!("Added {}", record),
+        "delete" => format!("Deleted {}", record),
+        _ => {
+            let old_row = parse_row(old_row);
+            let new_row = parse_row(new_row);
+            let columns = definition
+                .map(|definition| definition.current_columns())
+                .unwrap_or_default();
+
+            let changes: Vec<String> = columns
+                .iter()
+                .filter(|column| old_row.get(**column) != new_row.get(**column))
+                .map(|column| {
+                    format!(
+                        "{} of {} from {} to {}",
+                        column.replace('_', " "),
+                        record,
+                        format_value(old_row.get(*column)),
+                        format_value(new_row.get(*column))
+                    )
+                })
+                .collect();
+            if changes.is_empty() {
+                format!("Saved {} without changes", record)
+            } else {
+                format!("Changed {}", changes.join(", "))
+            }
+        }
+    }
+}
+
+// The most recent entries of the undo log, newest first
+pub fn list_history(conn: &Connection, limit: i64) -> Result<Vec<HistoryEntry>, Box<dyn Error>> {
+    let mut stmt = conn.prepare(
+        "SELECT undolog.seq, undolog.group_id, undo_groups.label, undolog.table_name,
+            undolog.row_id, undolog.operation, undolog.old_row, undolog.new_row,
+            undolog.created_at
+        FROM undolog LEFT JOIN undo_groups ON undo_groups.id = undolog.group_id
+        ORDER BY undolog.seq DESC LIMIT ?1;",
+    )?;
+    let rows = stmt.query_map([limit], |row| {
+        let table: Option<String> = row.get("table_name")?;
+        let row_id: Option<i64> = row.get("row_id")?;
+        let operation: Option<String> = row.get("operation")?;
+        let old_row: Option<String> = row.get("old_row")?;
+        let new_row: Option<String> = row.get("new_row")?;
+
+        Ok(HistoryEntry {
+            seq: row.get("seq")?,
+            group_id: row.get("group_id")?,
+            label: row.get("label")?,
+            description: describe(
+                table.as_deref(),
+                row_id,
+                operation.as_deref(),
+                old_row.as_deref(),
+                new_row.as_deref(),
+            ),
+            table,
+            row_id,
+            operation,
+            created_at: row.get("created_at")?,
+        })
+    })?;
+
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+// Removes all but the last `max_entries` entries of each log and the entries older than
+// `max_age` seconds, always whole groups. The newest step is kept even when it is a group
+// larger than `max_entries`. Returns how many entries were removed.
+pub fn prune_history(
+    conn: &Connection,
+    max_entries: i64,
+    max_age: i64,
+) -> Result<usize, Box<dyn Error>> {
+    let tx = UncheckedSavepoint::new(conn)?;
+
+    let mut removed = 0;
+    for log in ["undolog", "redolog"] {
+        // Below the first entry of the newest group, or the newest entry outside of a group
+        let cutoff: Option<i64> = tx.query_row(
+            &format!(
+                "SELECT MAX(seq) FROM {0} WHERE
+                    (seq <= (SELECT MAX(seq) FROM {0}) - ?1
+                        OR created_at < CAST(strftime('%s', 'now') AS INTEGER) - ?2)
+                    AND seq < (
+                        SELECT COALESCE(MIN(seq), (SELECT MAX(seq) FROM {0})) FROM {0}
+                        WHERE group_id = (SELECT group_id FROM {0} ORDER BY seq DESC LIMIT 1)
+                    );",
+                log
+            ),
+            [max_entries, max_age],
+            |row| row.get(0),
+        )?;
+        let Some(cutoff) = cutoff else {
+            continue;
+        };
+
+        removed += tx.execute(
+            &format!(
+                "DELETE FROM {0} WHERE seq <= ?1 OR group_id IN (
+                    SELECT group_id FROM {0} WHERE seq <= ?1 AND group_id IS NOT NULL
+                );",
+                log
+            ),
+            [cutoff],
+        )?;
+    }
+    delete_unused_groups(&tx)?;
+    tx.commit()?;
+
+    Ok(removed)
+}
+
+// A group the frontend opened but never ended, because the app crashed or the webview
+// reloaded in between, would take in every later edit
+pub fn close_open_group(conn: &Connection) -> Result<(), Box<dyn Error>> {
+    conn.execute("DELETE FROM undo_state WHERE mode = 'edit';", [])?;
+    delete_unused_groups(conn)?;
+
+    Ok(())
+}
+
+// Closes the group left open and prunes the history of the last session. Databases the
+// frontend has not upgraded yet are pruned at the end of the next group.
+pub fn clean_up_at_start(app_handle: &tauri::AppHandle) -> Result<(), Box<dyn Error>> {
+    if !get_db_path(app_handle)?.exists() {
+        return Ok(());
+    }
+
+    let conn = get_connection(app_handle.clone())?;
+    let version: i64 = conn.query_row("PRAGMA user_version;", [], |row| row.get(0))?;
+    if version >= UNDO_GROUPS_VERSION {
+        close_open_group(&conn)?;
+    }
+    if version >= UNDO_HISTORY_VERSION {
+        prune_history(&conn, MAX_HISTORY_ENTRIES, MAX_HISTORY_AGE)?;
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub fn undo(app_handle: tauri::AppHandle) -> Result<UndoStep, String> {
+    let conn: Connection =
+        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+
+    undo_last(&conn).map_err(|e| format!("Error undoing: {}", e))
+}
+
+#[tauri::command]
+pub fn redo(app_handle: tauri::AppHandle) -> Result<UndoStep, String> {
+    let conn: Connection =
+        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+
+    redo_last(&conn).map_err(|e| format!("Error redoing: {}", e))
+}
+
+#[tauri::command]
+pub fn list_undo_history(
+    app_handle: tauri::AppHandle,
+    limit: Option<i64>,
+) -> Result<Vec<HistoryEntry>, String> {
+    let conn: Connection =
+        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+
+    list_history(&conn, limit.unwrap_or(100))
+        .map_err(|e| format!("Error reading undo history: {}", e))
+}
+
+#[tauri::command]
+pub fn undo_to_entry(app_handle: tauri::AppHandle, seq: i64) -> Result<Vec<UndoStep>, String> {
+    let conn: Connection =
+        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+
+    undo_to(&conn, seq).map_err(|e| format!("Error undoing: {}", e))
+}
+
+// Limits default to MAX_HISTORY_ENTRIES and MAX_HISTORY_AGE
+#[tauri::command]
+pub fn prune_undo_history(
+    app_handle: tauri::AppHandle,
+    max_entries: Option<i64>,
+    max_age_days: Option<i64>,
+) -> Result<usize, String> {
+    let conn: Connection =
+        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+
+    let max_age = max_age_days.map_or(MAX_HISTORY_AGE, |days| days * 24 * 60 * 60);
+    prune_history(&conn, max_entries.unwrap_or(MAX_HISTORY_ENTRIES), max_age)
+        .map_err(|e| format!("Error pruning undo history: {}", e))
+}
+
+// For actions of the frontend made of several statements, e.g. pasting offers. The label is
+// shown to the user.
+#[tauri::command]
+pub fn begin_undo_group(app_handle: tauri::AppHandle, label: String) -> Result<i64, String> {
+    let conn: Connection =
+        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+
+    begin_group(&conn, &label).map_err(|e| format!("Error starting undo group: {}", e))
+}
+
+#[tauri::command]
+pub fn end_undo_group(app_handle: tauri::AppHandle) -> Result<(), String> {
+    let conn: Connection =
+        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+
+    end_group(&conn).map_err(|e| format!("Error ending undo group: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::open_test_database;
+    use crate::tables::TABLES;
+    use rusqlite::types::Value as SqlValue;
+
+    fn count(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {};", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn failed_group_is_rolled_back() {
+        let conn = open_test_database();
+        let logged = count(&conn, "undolog");
+        let result: Result<(), _> = with_group(&conn, "failing", || {
+            conn.execute("INSERT INTO sellers (seller_name) VALUES ('Seller');", [])?;
+            Err("Something went wrong".into())
+        });
+
+        assert!(result.is_err());
+        assert!(conn.is_autocommit());
+        assert_eq!(count(&conn, "sellers"), 0);
+        assert_eq!(count(&conn, "undo_groups"), 0);
+        assert_eq!(count(&conn, "undo_state"), 0);
+        assert_eq!(count(&conn, "undolog"), logged);
+    }
+
+    #[test]
+    fn savepoints_inside_a_group_are_kept_or_rolled_back() {
+        let conn = open_test_database();
+        with_group(&conn, "import", || {
+            let kept = UncheckedSavepoint::new(&conn)?;
+            kept.execute("INSERT INTO sellers (seller_name) VALUES ('Kept');", [])?;
+            kept.commit()?;
+
+            let dropped = UncheckedSavepoint::new(&conn)?;
+            dropped.execute("INSERT INTO sellers (seller_name) VALUES ('Dropped');", [])?;
+            dropped.rollback()?;
+
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(count(&conn, "sellers"), 1);
+        assert_eq!(undo_last(&conn).unwrap().label.as_deref(), Some("import"));
+        assert_eq!(count(&conn, "sellers"), 0);
+    }
+
+    // Every row of every auction table, ids and references included
+    fn snapshot(conn: &Connection) -> Vec<Vec<Vec<SqlValue>>> {
+        TABLES
+            .iter()
+            .map(|table| {
+                let mut stmt = conn
+                    .prepare(&format!("SELECT * FROM {} ORDER BY id;", table.name))
+                    .unwrap();
+                let columns = stmt.column_count();
+                stmt.query_map([], |row| {
+                    (0..columns).map(|i| row.get::<_, SqlValue>(i)).collect()
+                })
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+            })
+            .collect()
+    }
+
+    fn assert_foreign_keys_valid(conn: &Connection) {
+        let violations = conn
+            .prepare("PRAGMA foreign_key_check;")
+            .unwrap()
+            .query_map([], |row| row.get::<_, String>(0))
+            .unwrap()
+            .count();
+        assert_eq!(violations, 0);
+    }
+
+    #[test]
+    fn deleted_rows_and_their_dependants_are_restored_with_their_ids() {
+        let conn = open_test_database();
+        // Ids far from 1 so a row restored under a new id does not go unnoticed
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name, ident) VALUES (5, 'First', 'S5'), (6, 'Second', 'S6');
+            INSERT INTO buyers (id, buyer_name, ident) VALUES (9, 'First', 'B9'), (10, 'Second', 'B10');
+            INSERT INTO wood_pieces (id, sequence_no, plate_no, length, width, seller_id, tree_species_id, min_price)
+                VALUES (11, 1, 'P1', 4, 50, 5, 3, 100), (12, 2, 'P2', 5, 60, 5, 4, 120), (13, 3, 'P3', 3, 40, 6, 3, 90);
+            INSERT INTO wood_piece_offers (id, offered_price, wood_piece_id, buyer_id)
+                VALUES (21, 150, 11, 9), (22, 160, 12, 9), (23, 170, 13, 10);
+            UPDATE images SET mime_type = 'image/png', data_base64 = 'aGVhZGVy' WHERE image_key = 'header';",
+        )
+        .unwrap();
+        let before = snapshot(&conn);
+
+        // (table, deleted row, dependants deleted first in the same action)
+        let cases: [(&str, i64, &[&str]); 7] = [
+            (
+                "sellers",
+                5,
+                &[
+                    "DELETE FROM wood_piece_offers WHERE wood_piece_id IN (SELECT id FROM wood_pieces WHERE seller_id = 5);",
+                    "DELETE FROM wood_pieces WHERE seller_id = 5;",
+                ],
+            ),
+            (
+                "buyers",
+                9,
+                &["DELETE FROM wood_piece_offers WHERE buyer_id = 9;"],
+            ),
+            (
+                "tree_species",
+                3,
+                &[
+                    "DELETE FROM wood_piece_offers WHERE wood_piece_id IN (SELECT id FROM wood_pieces WHERE tree_species_id = 3);",
+                    "DELETE FROM wood_pieces WHERE tree_species_id = 3;",
+                ],
+            ),
+            (
+                "wood_pieces",
+                12,
+                &["DELETE FROM wood_piece_offers WHERE wood_piece_id = 12;"],
+            ),
+            ("wood_piece_offers", 23, &[]),
+            ("settings", 1, &[]),
+            ("images", 1, &[]),
+        ];
+        assert!(TABLES
+            .iter()
+            .all(|table| cases.iter().any(|(name, _, _)| *name == table.name)));
+
+        for (table, id, dependants) in cases {
+            let delete = || {
+                with_group(&conn, "delete", || {
+                    for sql in dependants {
+                        conn.execute(sql, [])?;
+                    }
+                    conn.execute(&format!("DELETE FROM {} WHERE id = ?1;", table), [id])?;
+                    Ok(())
+                })
+                .unwrap();
+                assert_ne!(snapshot(&conn), before, "{} {} was not deleted", table, id);
+            };
+
+            delete();
+            undo_last(&conn).unwrap();
+            assert_eq!(
+                snapshot(&conn),
+                before,
+                "undoing the delete of {} {}",
+                table,
+                id
+            );
+            assert_foreign_keys_valid(&conn);
+
+            // Redoing deletes the same rows again, which the next undo brings back
+            redo_last(&conn).unwrap();
+            assert_ne!(
+                snapshot(&conn),
+                before,
+                "redoing the delete of {} {}",
+                table,
+                id
+            );
+            assert_foreign_keys_valid(&conn);
+            undo_last(&conn).unwrap();
+            assert_eq!(
+                snapshot(&conn),
+                before,
+                "undoing the redo of {} {}",
+                table,
+                id
+            );
+
+            // A fresh delete after the redo log was used
+            delete();
+            undo_last(&conn).unwrap();
+            assert_eq!(snapshot(&conn), before);
+            assert_foreign_keys_valid(&conn);
+        }
+    }
+
+    #[test]
+    fn newest_group_is_never_pruned() {
+        let conn = open_test_database();
+        conn.execute("INSERT INTO sellers (seller_name) VALUES ('Older');", [])
+            .unwrap();
+        with_group(&conn, "import", || {
+            for i in 0..5 {
+                conn.execute("INSERT INTO buyers (buyer_name) VALUES (?1);", [i])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        // Smaller than the group, only the entries before it go
+        prune_history(&conn, 2, MAX_HISTORY_AGE).unwrap();
+        assert_eq!(count(&conn, "undolog"), 5);
+        undo_last(&conn).unwrap();
+        assert_eq!(count(&conn, "buyers"), 0);
+        assert_eq!(count(&conn, "sellers"), 1);
+
+        // The newest entry outside of a group is kept as well
+        conn.execute("INSERT INTO sellers (seller_name) VALUES ('Newer');", [])
+            .unwrap();
+        prune_history(&conn, 0, 0).unwrap();
+        assert_eq!(count(&conn, "undolog"), 1);
+        undo_last(&conn).unwrap();
+        assert_eq!(count(&conn, "sellers"), 1);
+    }
+
+    #[test]
+    fn group_left_open_is_closed_at_start() {
+        let conn = open_test_database();
+        // The frontend began a group and never ended it
+        begin_group(&conn, "paste").unwrap();
+        conn.execute("INSERT INTO sellers (seller_name) VALUES ('In group');", [])
+            .unwrap();
+
+        close_open_group(&conn).unwrap();
+        assert_eq!(count(&conn, "undo_state"), 0);
+        conn.execute("INSERT INTO buyers (buyer_name) VALUES ('Later');", [])
+            .unwrap();
+
+        // The later edit is a step of its own
+        let step = undo_last(&conn).unwrap();
+        assert_eq!(step.group_id, None);
+        assert_eq!(count(&conn, "buyers"), 0);
+        assert_eq!(count(&conn, "sellers"), 1);
+        assert_eq!(undo_last(&conn).unwrap().label.as_deref(), Some("paste"));
+        assert_eq!(count(&conn, "sellers"), 0);
+    }
+}
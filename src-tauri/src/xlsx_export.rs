@@ -0,0 +1,391 @@
+use rusqlite::types::ValueRef;
+use rusqlite::Connection;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use rust_xlsxwriter::{Format, Workbook, Worksheet};
+use serde::Deserialize;
+use std::error::Error;
+
+use crate::csv_export::CsvReport;
+use crate::settlement::{compute_all_settlements, compute_statistics, STATEMENT_COLUMNS};
+use crate::shared::get_connection;
+
+// What buyers get in the printed catalogue, without the seller
+const CATALOGUE_SQL: &str = "
+    SELECT
+        wood_pieces.sequence_no,
+        wood_pieces.plate_no,
+        tree_species.tree_species_name_slo,
+        tree_species.latin_name,
+        wood_pieces.length,
+        wood_pieces.width,
+        wood_pieces.volume,
+        wood_pieces.min_price
+    FROM wood_pieces
+    LEFT JOIN tree_species ON tree_species.id = wood_pieces.tree_species_id
+    ORDER BY wood_pieces.sequence_no;
+";
+
+const EUR_FORMAT: &str = "#,##0.00 \"€\"";
+const VOLUME_FORMAT: &str = "0.00 \"m³\"";
+const LENGTH_FORMAT: &str = "0.00";
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum XlsxSheet {
+    Catalogue,
+    SoldPiecesPerSeller,
+    BoughtPiecesPerBuyer,
+    // Pieces, volume and prices per species
+    Statistics,
+    // The totals of the whole auction, as on the statistics screen
+    GeneralStatistics,
+    // The totals of every seller's statement
+    SellerStatements,
+}
+
+const ALL_SHEETS: [XlsxSheet; 6] = [
+    XlsxSheet::Catalogue,
+    XlsxSheet::SoldPiecesPerSeller,
+    XlsxSheet::BoughtPiecesPerBuyer,
+    XlsxSheet::Statistics,
+    XlsxSheet::GeneralStatistics,
+    XlsxSheet::SellerStatements,
+];
+
+impl XlsxSheet {
+    fn name(&self) -> &'static str {
+        match self {
+            XlsxSheet::Catalogue => "Catalogue",
+            XlsxSheet::SoldPiecesPerSeller => "Sold per seller",
+            XlsxSheet::BoughtPiecesPerBuyer => "Bought per buyer",
+            XlsxSheet::Statistics => "Statistics",
+            XlsxSheet::GeneralStatistics => "General statistics",
+            XlsxSheet::SellerStatements => "Seller statements",
+        }
+    }
+
+    // None for sheets that are not a single query
+    fn sql(&self) -> Option<String> {
+        match self {
+            XlsxSheet::Catalogue => Some(CATALOGUE_SQL.to_string()),
+            XlsxSheet::SoldPiecesPerSeller => CsvReport::SoldPiecesPerSeller.sql(),
+            XlsxSheet::BoughtPiecesPerBuyer => CsvReport::BoughtPiecesPerBuyer.sql(),
+            XlsxSheet::Statistics => CsvReport::SpeciesStats.sql(),
+            XlsxSheet::GeneralStatistics | XlsxSheet::SellerStatements => None,
+        }
+    }
+}
+
+// Number format of a column, picked by its name
+fn get_column_format(column: &str) -> Format {
+    let num_format = match column {
+        "min_price"
+        | "offered_price"
+        | "total_price"
+        | "max_offered_price"
+        | "avg_offered_price"
+        | "costs_below_350"
+        | "costs_above_350"
+        | "seller_income_gross"
+        | "seller_income_tax_flat"
+        | "seller_income_tax_vat"
+        | "seller_income_gross_after_tax"
+        | "transport_costs"
+        | "transport_vat"
+        | "logging_costs"
+        | "logging_costs_vat"
+        | "logging_costs_non_woods_vat"
+        | "payout"
+        | "sellers_net"
+        | "seller_costs" => EUR_FORMAT,
+        "volume" | "total_volume" | "sold_volume" | "unsold_volume" => VOLUME_FORMAT,
+        "length" => LENGTH_FORMAT,
+        _ => "General",
+    };
+
+    Format::new().set_num_format(num_format)
+}
+
+// Header of a column, the column name if it has no label
+fn get_column_label(column: &str) -> &str {
+    match column {
+        "wood_piece_id" => "Piece ID",
+        "seller_name" => "Seller",
+        "seller_ident" => "Seller ID",
+        "buyer_name" => "Buyer",
+        "buyer_ident" => "Buyer ID",
+        "sequence_no" => "No.",
+        "plate_no" => "Plate no.",
+        "tree_species_name" => "Species",
+        "tree_species_name_slo" => "Species (Slovenian)",
+        "latin_name" => "Latin name",
+        "length" => "Length (m)",
+        "width" => "Width (cm)",
+        "volume" => "Volume",
+        "min_price" => "Minimum price",
+        "offered_price" => "Offered price",
+        "total_price" => "Total price",
+        "num_pieces" => "Pieces",
+        "num_sold_pieces" => "Sold pieces",
+        "num_unsold_pieces" => "Unsold pieces",
+        "total_volume" => "Total volume",
+        "sold_volume" => "Sold volume",
+        "unsold_volume" => "Unsold volume",
+        "max_offered_price" => "Highest price",
+        "avg_offered_price" => "Average price",
+        "costs_below_350" => "Costs up to 350 €/m³",
+        "costs_above_350" => "Costs above 350 €/m³",
+        "seller_income_gross" => "Income",
+        "seller_income_tax_flat" => "Flat rate compensation",
+        "seller_income_tax_vat" => "VAT",
+        "seller_income_gross_after_tax" => "Income with taxes",
+        "transport_costs" => "Transport",
+        "transport_vat" => "Transport VAT",
+        "logging_costs" => "Logging",
+        "logging_costs_vat" => "Logging VAT",
+        "logging_costs_non_woods_vat" => "Logging VAT (outside woods)",
+        "payout" => "Payout",
+        "sellers_net" => "Paid to sellers",
+        "seller_costs" => "Seller costs",
+        "statistic" => "Statistic",
+        "value" => "Value",
+        _ => column,
+    }
+}
+
+// Writes the bold header row and returns the number format of each column
+fn write_header<'a>(
+    worksheet: &mut Worksheet,
+    columns: impl IntoIterator<Item = &'a str>,
+) -> Result<Vec<Format>, Box<dyn Error>> {
+    let header_format = Format::new().set_bold();
+    let mut formats = vec![];
+    for (col, column) in columns.into_iter().enumerate() {
+        worksheet.write_string_with_format(
+            0,
+            col as u16,
+            get_column_label(column),
+            &header_format,
+        )?;
+        formats.push(get_column_format(column));
+    }
+    worksheet.set_freeze_panes(1, 0)?;
+
+    Ok(formats)
+}
+
+fn to_number(value: Decimal) -> f64 {
+    value.to_f64().unwrap_or_default()
+}
+
+// One row per seller with pieces, the same amounts as on the printed statements
+fn write_statements(conn: &Connection, worksheet: &mut Worksheet) -> Result<(), Box<dyn Error>> {
+    let formats = write_header(worksheet, STATEMENT_COLUMNS)?;
+
+    for (i, settlement) in compute_all_settlements(conn, false)?.iter().enumerate() {
+        let row_no = i as u32 + 1;
+        worksheet.write_string(
+            row_no,
+            0,
+            settlement.seller_name.as_deref().unwrap_or_default(),
+        )?;
+        worksheet.write_string(
+            row_no,
+            1,
+            settlement.seller_ident.as_deref().unwrap_or_default(),
+        )?;
+        worksheet.write_number(row_no, 2, settlement.num_sold_pieces() as f64)?;
+        for (col, amount) in (3..).zip(settlement.amounts()) {
+            worksheet.write_number_with_format(
+                row_no,
+                col,
+                to_number(amount),
+                &formats[col as usize],
+            )?;
+        }
+    }
+    worksheet.autofit();
+
+    Ok(())
+}
+
+// One row per total of the auction, each formatted like the column it sums
+fn write_general_statistics(
+    conn: &Connection,
+    worksheet: &mut Worksheet,
+) -> Result<(), Box<dyn Error>> {
+    write_header(worksheet, ["statistic", "value"])?;
+
+    let statistics = compute_statistics(conn)?;
+    let values = [
+        ("num_pieces", statistics.num_wood_pieces as f64),
+        (
+            "num_unsold_pieces",
+            statistics.num_unsold_wood_pieces as f64,
+        ),
+        ("total_volume", to_number(statistics.total_volume)),
+        (
+            "max_offered_price",
+            to_number(statistics.offered_max_price.unwrap_or_default()),
+        ),
+        ("sellers_net", to_number(statistics.sellers_net)),
+        ("costs_below_350", to_number(statistics.costs_below_350)),
+        ("costs_above_350", to_number(statistics.costs_above_350)),
+        (
+            "transport_costs",
+            to_number(statistics.total_transport_costs),
+        ),
+        ("logging_costs", to_number(statistics.total_logging_costs)),
+        ("seller_costs", to_number(statistics.seller_costs)),
+    ];
+    for (row_no, (column, value)) in (1..).zip(values) {
+        worksheet.write_string(row_no, 0, get_column_label(column))?;
+        worksheet.write_number_with_format(row_no, 1, value, &get_column_format(column))?;
+    }
+    worksheet.autofit();
+
+    Ok(())
+}
+
+fn write_sheet(
+    conn: &Connection,
+    worksheet: &mut Worksheet,
+    sheet: XlsxSheet,
+) -> Result<(), Box<dyn Error>> {
+    worksheet.set_name(sheet.name())?;
+
+    let Some(sql) = sheet.sql() else {
+        return match sheet {
+            XlsxSheet::GeneralStatistics => write_general_statistics(conn, worksheet),
+            _ => write_statements(conn, worksheet),
+        };
+    };
+    let mut stmt = conn.prepare(&sql)?;
+    let formats = write_header(worksheet, stmt.column_names())?;
+
+    let mut rows = stmt.query([])?;
+    let mut row_no: u32 = 1;
+    while let Some(row) = rows.next()? {
+        for (col, format) in formats.iter().enumerate() {
+            match row.get_ref(col)? {
+                ValueRef::Null => {}
+                ValueRef::Integer(i) => {
+                    worksheet.write_number_with_format(row_no, col as u16, i as f64, format)?;
+                }
+                ValueRef::Real(r) => {
+                    worksheet.write_number_with_format(row_no, col as u16, r, format)?;
+                }
+                ValueRef::Text(t) => {
+                    worksheet.write_string(row_no, col as u16, String::from_utf8_lossy(t))?;
+                }
+                // No report selects blobs
+                ValueRef::Blob(_) => {}
+            }
+        }
+        row_no += 1;
+    }
+    worksheet.autofit();
+
+    Ok(())
+}
+
+fn export_to_xlsx(
+    conn: &Connection,
+    xlsx_path: &str,
+    sheets: &[XlsxSheet],
+) -> Result<(), Box<dyn Error>> {
+    let mut workbook = Workbook::new();
+    for sheet in sheets {
+        write_sheet(conn, workbook.add_worksheet(), *sheet)?;
+    }
+    workbook.save(xlsx_path)?;
+
+    Ok(())
+}
+
+// Writes a workbook with the given sheets, or all of them
+#[tauri::command]
+pub fn export_xlsx(
+    app_handle: tauri::AppHandle,
+    file_path: String,
+    sheets: Option<Vec<XlsxSheet>>,
+) -> Result<(), String> {
+    let conn: Connection =
+        get_connection(app_handle).map_err(|e| format!("Error opening database: {}", e))?;
+    let sheets = sheets.unwrap_or_else(|| ALL_SHEETS.to_vec());
+    if sheets.is_empty() {
+        return Err("No sheets to export".to_string());
+    }
+
+    export_to_xlsx(&conn, &file_path, &sheets).map_err(|e| format!("Error exporting XLSX: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::open_test_database;
+
+    #[test]
+    fn every_column_has_a_label() {
+        let conn = open_test_database();
+        let mut columns: Vec<String> = STATEMENT_COLUMNS.iter().map(|c| c.to_string()).collect();
+        for sheet in ALL_SHEETS {
+            if let Some(sql) = sheet.sql() {
+                let stmt = conn.prepare(&sql).unwrap();
+                columns.extend(stmt.column_names().into_iter().map(String::from));
+            }
+        }
+
+        for column in &columns {
+            assert_ne!(get_column_label(column), column);
+        }
+    }
+
+    // The text of a file in the saved workbook
+    fn read_part(path: &std::path::Path, name: &str) -> String {
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(path).unwrap()).unwrap();
+        let mut contents = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name(name).unwrap(), &mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn workbook_has_numbers_formats_and_frozen_headers() {
+        let conn = open_test_database();
+        conn.execute_batch(
+            "INSERT INTO sellers (id, seller_name) VALUES (1, 'Seller');
+            INSERT INTO buyers (id, buyer_name) VALUES (1, 'Buyer');
+            INSERT INTO wood_pieces (id, sequence_no, length, width, seller_id, tree_species_id, min_price)
+                VALUES (1, 1, 4, 50, 1, 1, 100), (2, 2, 4, 50, 1, 1, 100);
+            INSERT INTO wood_piece_offers (offered_price, wood_piece_id, buyer_id)
+                VALUES (200, 1, 1);",
+        )
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("auction.xlsx");
+        export_to_xlsx(&conn, path.to_str().unwrap(), &ALL_SHEETS).unwrap();
+
+        let workbook = read_part(&path, "xl/workbook.xml");
+        let styles = read_part(&path, "xl/styles.xml");
+        assert!(workbook.contains("name=\"General statistics\""));
+        assert!(styles.contains("formatCode=\"#,##0.00 &quot;€&quot;\""));
+        assert!(styles.contains("formatCode=\"0.00 &quot;m³&quot;\""));
+
+        for i in 1..=ALL_SHEETS.len() {
+            let worksheet = read_part(&path, &format!("xl/worksheets/sheet{}.xml", i));
+            assert!(worksheet.contains("<pane ySplit=\"1\""), "sheet {}", i);
+        }
+
+        // Catalogue: the volume and minimum price of the first piece are formatted numbers
+        let catalogue = read_part(&path, "xl/worksheets/sheet1.xml");
+        // Styles are numbered in order of first use, the volume format is the 4th, EUR the 5th
+        assert!(catalogue.contains("<c r=\"G2\" s=\"4\"><v>0.79</v></c>"));
+        assert!(catalogue.contains("<c r=\"H2\" s=\"5\"><v>100</v></c>"));
+        // General statistics: 2 pieces, 1.58 m3, 158 € paid to the seller
+        let statistics = read_part(&path, "xl/worksheets/sheet5.xml");
+        for value in ["<v>2</v>", "<v>1.58</v>", "<v>158</v>"] {
+            assert!(statistics.contains(value), "{}", value);
+        }
+    }
+}
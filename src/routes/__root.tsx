@@ -21,15 +21,22 @@ import { useTranslation } from "react-i18next";
 import {
   FaAngleDown,
   FaArrowRotateLeft,
+  FaArrowRotateRight,
   FaRegFloppyDisk,
 } from "react-icons/fa6";
 import { queryClient } from "../main";
+import {
+  cancelBackup,
+  getBackupPercent,
+  runBackup,
+  type BackupOperation,
+} from "../utils/backupService";
 import { buyersQueryOptions } from "../utils/buyerService";
 import { confirm } from "../utils/confirm";
 import { unsetDatabase } from "../utils/database";
 import { sellersQueryOptions } from "../utils/sellerService";
 import { treeSpeciesQueryOptions } from "../utils/treeSpeciesService";
-import { useUndo } from "../utils/undo";
+import { useRedo, useUndo } from "../utils/undo";
 import { woodPiecesCountQueryOptions } from "../utils/woodPieceService";
 
 const LANGUAGE_STORAGE_KEY = "language_v1";
@@ -60,6 +67,9 @@ function RootComponent() {
   const { mutate: undo } = useUndo(() => {
     queryClient.invalidateQueries();
   });
+  const { mutate: redo } = useRedo(() => {
+    queryClient.invalidateQueries();
+  });
 
   const woodPiecesQuery = useSuspenseQuery(woodPiecesCountQueryOptions());
   const woodPiecesCount = woodPiecesQuery.data;
@@ -168,6 +178,80 @@ function RootComponent() {
     }
   };
 
+  // Copies the database to or from a backup file with a progress toast that can cancel it
+  const backupWithProgress = async (
+    operation: BackupOperation,
+    path: string
+  ) => {
+    const toastId = toast.loading(t("loading"), {
+      position: "top-center",
+    });
+    const showProgress = (percent: number) =>
+      toast.loading(
+        <div className="flex flex-row items-center space-x-2">
+          <span>
+            {t(operation === "dump" ? "backingUp" : "restoringBackup")}{" "}
+            {percent}%
+          </span>
+          <button
+            className="bg-blue-400 rounded px-2 py-1 text-white text-sm"
+            onClick={() => cancelBackup(operation)}
+          >
+            {t("cancel")}
+          </button>
+        </div>,
+        { id: toastId, position: "top-center" }
+      );
+    showProgress(0);
+
+    let cancelled = false;
+    try {
+      await runBackup(operation, path, (progress) => {
+        if (progress.phase === "started" || progress.phase === "copying") {
+          showProgress(getBackupPercent(progress));
+        } else if (progress.phase === "cancelled") {
+          cancelled = true;
+        }
+      });
+    } catch (e) {
+      info(JSON.stringify(e));
+      toast.dismiss(toastId);
+      if (cancelled) {
+        toast(t("backupCancelled"));
+      } else {
+        toast.error(`${t("error")} ${JSON.stringify(e)}`);
+      }
+      return;
+    }
+    toast.dismiss(toastId);
+    toast.success(t("success"));
+  };
+
+  const backupDatabase = async () => {
+    const path = await save({
+      filters: [{ name: "SQLite", extensions: ["db"] }],
+      defaultPath: "backup.db",
+    });
+
+    if (path) {
+      await backupWithProgress("dump", path);
+    }
+  };
+
+  const restoreBackup = async () => {
+    const path = await open({
+      multiple: false,
+      directory: false,
+      filters: [{ name: "SQLite", extensions: ["db"] }],
+    });
+
+    if (path && (await confirm({ confirmation: t("areYouSure") }))) {
+      await backupWithProgress("load", path);
+      unsetDatabase();
+      await queryClient.invalidateQueries();
+    }
+  };
+
   const checkForUpdates = async () => {
     if (changes) {
       const confirmed = await confirm({
@@ -361,6 +445,14 @@ function RootComponent() {
             >
               <FaArrowRotateLeft />
             </button>
+            <button
+              className="bg-blue-400 rounded p-2 uppercase text-white font-black disabled:opacity-50 h-10 text-2xl"
+              onClick={() => redo()}
+              title={t("redo")}
+              aria-label={t("redo")}
+            >
+              <FaArrowRotateRight />
+            </button>
             <div className="relative inline-block text-left">
               <div className="flex flex-row space-x-2">
                 <button
@@ -439,6 +531,20 @@ function RootComponent() {
                     >
                       {t("open")}
                     </button>
+                    <button
+                      className="w-full block p-2 disabled:opacity-50 h-10 text-sm text-gray-700 px-4 text-left bg-white "
+                      onClick={() => backupDatabase()}
+                      title={t("backupDatabase")}
+                    >
+                      {t("backupDatabase")}
+                    </button>
+                    <button
+                      className="w-full block p-2 disabled:opacity-50 h-10 text-sm text-gray-700 px-4 text-left bg-white "
+                      onClick={() => restoreBackup()}
+                      title={t("restoreBackup")}
+                    >
+                      {t("restoreBackup")}
+                    </button>
                     <button
                       className="w-full block p-2 disabled:opacity-50 h-10 text-sm text-gray-700 px-4 text-left bg-white "
                       onClick={() => resetApplicationData()}
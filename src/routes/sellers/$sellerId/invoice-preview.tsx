@@ -23,6 +23,7 @@ import { PdfTypeEnum, saveToPDF } from '../../../utils/pdf'
 import { slugifyFilenamePart } from '../../../utils/filename'
 import { sellerQueryOptions } from '../../../utils/sellerService'
 import { settingsQueryOptions } from '../../../utils/settingsService'
+import { sellerSettlementQueryOptions } from '../../../utils/settlementService'
 import {
   WoodPiece,
   woodPiecesQueryOptions,
@@ -156,133 +157,48 @@ function SoldPiecesList() {
     meta: {},
   })
 
-  const rows = table.getFilteredRowModel().rows
-
-  const { totalVolume, totalPrice, costsBelow350, costsAbove350 } =
-    useMemo(() => {
-      return {
-        totalVolume: rows
-          .reduce(
-            (sum: Big, row) => sum.plus(row.getValue('volume') as number),
-            new Big(0),
-          )
-          .round(2),
-        totalPrice: rows
-          .reduce(
-            (sum, row) =>
-              sum.plus((row.getValue('offered_total_price') as number) || 0),
-            new Big(0),
-          )
-          .round(2),
-        costsBelow350: rows
-          .reduce(
-            (sum, row) =>
-              sum.plus(
-                new Big(settingsData.licitator_fixed_cost).mul(
-                  row.getValue('volume') as number,
-                ),
-              ),
-            new Big(0),
-          )
-          .round(2),
-        costsAbove350: rows
-          .reduce((sum, row) => {
-            if ((row.getValue('offered_price') as number) <= 350) {
-              return sum
-            }
-            const totalAbove350 = new Big(
-              row.getValue('offered_total_price') as number,
-            ).minus(new Big(350).mul(row.getValue('volume')))
-            return sum.plus(
-              new Big(settingsData.licitator_percentage).mul(totalAbove350),
-            )
-          }, new Big(0))
-          .round(2),
-      }
-    }, [rows])
-
-  const sellerIncomeGross = useMemo(
-    () => totalPrice.minus(costsAbove350).minus(costsBelow350).round(2),
-    [totalPrice, costsBelow350, costsAbove350],
-  )
-
-  const transportCosts = useMemo(
-    () =>
-      (seller.used_transport
-        ? totalVolume.mul(seller.transport_costs || 0)
-        : new Big(0)
-      ).round(2),
-    [totalVolume, seller],
-  )
-
-  const transportVAT = useMemo(
-    () =>
-      (seller.used_transport ? transportCosts.mul(0.22) : new Big(0)).round(2),
-    [transportCosts, seller],
+  const settlementQuery = useSuspenseQuery(
+    sellerSettlementQueryOptions(params.sellerId, true),
   )
+  const settlement = settlementQuery.data
 
-  const loggingCosts = useMemo(
-    () =>
-      (seller.used_logging || seller.used_logging_non_woods
-        ? totalVolume.mul(seller.logging_costs || 0)
-        : new Big(0)
-      ).round(2),
-    [totalVolume, seller],
-  )
-
-  const loggingCostsVAT = useMemo(
-    () => (seller.used_logging ? loggingCosts.mul(0.095) : new Big(0)).round(2),
-    [loggingCosts, seller],
-  )
-
-  const loggingCostsNonWoodsVAT = useMemo(
-    () =>
-      (seller.used_logging_non_woods
-        ? loggingCosts.mul(0.22)
-        : new Big(0)
-      ).round(2),
-    [loggingCosts, seller],
-  )
-
-  const { sellerIncomeTaxFlat, sellerIncomeTaxVat } = useMemo(
-    () => ({
-      sellerIncomeTaxFlat: (seller.is_flat_rate &&
-      sellerIncomeGross > new Big(0)
-        ? sellerIncomeGross.mul(0.08)
-        : new Big(0)
-      ).round(2),
-      sellerIncomeTaxVat: (seller.is_vat_liable &&
-      sellerIncomeGross > new Big(0)
-        ? sellerIncomeGross.mul(0.22)
-        : new Big(0)
-      ).round(2),
-    }),
-    [sellerIncomeGross, seller],
-  )
-
-  const sellerIncomeGrossAfterTax = useMemo(() => {
-    return sellerIncomeGross
-      .plus(sellerIncomeTaxFlat)
-      .plus(sellerIncomeTaxVat)
-      .round(2)
-  }, [sellerIncomeGross, sellerIncomeTaxVat, sellerIncomeTaxFlat])
-
-  const payout = useMemo(() => {
-    return sellerIncomeGrossAfterTax
-      .minus(transportCosts)
-      .minus(transportVAT)
-      .minus(loggingCosts)
-      .minus(loggingCostsVAT)
-      .minus(loggingCostsNonWoodsVAT)
-      .round(2)
-  }, [
+  // Every amount comes from the compute_seller_settlement command, so the statement
+  // matches the statistics and exports
+  const {
+    totalVolume,
+    totalPrice,
+    costsBelow350,
+    costsAbove350,
+    sellerIncomeGross,
+    sellerIncomeTaxFlat,
+    sellerIncomeTaxVat,
     sellerIncomeGrossAfterTax,
     transportCosts,
     transportVAT,
     loggingCosts,
     loggingCostsVAT,
     loggingCostsNonWoodsVAT,
-  ])
+    payout,
+  } = useMemo(
+    () => ({
+      totalVolume: new Big(settlement.total_volume),
+      totalPrice: new Big(settlement.total_price),
+      costsBelow350: new Big(settlement.costs_below_350),
+      costsAbove350: new Big(settlement.costs_above_350),
+      sellerIncomeGross: new Big(settlement.seller_income_gross),
+      sellerIncomeTaxFlat: new Big(settlement.seller_income_tax_flat),
+      sellerIncomeTaxVat: new Big(settlement.seller_income_tax_vat),
+      sellerIncomeGrossAfterTax: new Big(
+        settlement.seller_income_gross_after_tax,
+      ),
+      transportCosts: new Big(settlement.transport_costs),
+      transportVAT: new Big(settlement.transport_vat),
+      loggingCosts: new Big(settlement.logging_costs),
+      loggingCostsVAT: new Big(settlement.logging_costs_vat),
+      loggingCostsNonWoodsVAT: new Big(settlement.logging_costs_non_woods_vat),
+    }),
+    [settlement],
+  )
 
   const columns_summary = useMemo<PdfTableCol[]>(
     () => [
@@ -0,0 +1,44 @@
+import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
+
+export type BackupOperation = "dump" | "load";
+
+export type BackupProgress = {
+  operation: BackupOperation;
+  phase: "started" | "copying" | "finished" | "cancelled" | "failed";
+  pages_done: number;
+  pages_total: number;
+};
+
+// Copies the working database to `filePath` ("dump") or replaces it with the backup at
+// `filePath` ("load"), reporting the progress of the copy
+export async function runBackup(
+  operation: BackupOperation,
+  filePath: string,
+  onProgress: (progress: BackupProgress) => void
+): Promise<void> {
+  const unlisten = await listen<BackupProgress>("backup-progress", (event) => {
+    if (event.payload.operation === operation) {
+      onProgress(event.payload);
+    }
+  });
+  try {
+    await invoke(operation === "dump" ? "dump_sqlite_db" : "load_sqlite_db", {
+      filePath,
+    });
+  } finally {
+    unlisten();
+  }
+}
+
+// The running copy stops after its current step and leaves nothing half written
+export async function cancelBackup(operation: BackupOperation): Promise<void> {
+  await invoke("cancel_backup", { operation });
+}
+
+export function getBackupPercent(progress: BackupProgress): number {
+  if (progress.pages_total === 0) {
+    return 0;
+  }
+  return Math.round((progress.pages_done / progress.pages_total) * 100);
+}
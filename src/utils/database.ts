@@ -1,11 +1,19 @@
 // src/lib/database.ts
+import { invoke } from "@tauri-apps/api/core";
 import Database from "@tauri-apps/plugin-sql";
 
 let dbInstance: Database | null = null;
 
+async function loadDatabase(): Promise<Database> {
+  const db = await Database.load("sqlite:main_database_v12.db");
+  // Loading runs the migrations, the audit log records changes of this session from now on
+  await invoke("start_audit_session", {});
+  return db;
+}
+
 export async function getDatabase(): Promise<Database> {
   if (!dbInstance) {
-    dbInstance = await Database.load("sqlite:main_database_v12.db");
+    dbInstance = await loadDatabase();
   }
   return dbInstance;
 }
@@ -14,7 +22,7 @@ export async function getDatabaseForModify(): Promise<Database> {
   localStorage.setItem("unsaved_changes_v12", "true");
   window.dispatchEvent(new Event("storage"));
   if (!dbInstance) {
-    dbInstance = await Database.load("sqlite:main_database_v12.db");
+    dbInstance = await loadDatabase();
   }
   return dbInstance;
 }
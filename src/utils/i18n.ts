@@ -51,6 +51,7 @@ const resources = {
       saveAs: "Save as",
       open: "Open",
       undo: "Undo",
+      redo: "Redo",
       addressLine1: "Address line 1",
       addressLine2: "Address line 2",
       isDefined: "Is defined",
@@ -203,6 +204,11 @@ const resources = {
       sellersCosts: "Seller costs",
       buyersCosts: "Buyer costs",
       loading: "Loading...",
+      backupDatabase: "Back up database",
+      restoreBackup: "Restore database backup",
+      backingUp: "Backing up",
+      restoringBackup: "Restoring",
+      backupCancelled: "Cancelled, nothing was changed",
       error: "Error",
       statsPerSpecies: "Statistics per species",
       averageOfferedPrice: "Average offered price",
@@ -264,6 +270,7 @@ const resources = {
       saveAs: "Shrani kot",
       open: "Odpri",
       undo: "Nazaj",
+      redo: "Naprej",
       addressLine1: "Naslov vrstica 1",
       addressLine2: "Naslov vrstica 2",
       isDefined: "Je podana",
@@ -418,6 +425,11 @@ const resources = {
       sellersCosts: "Stroški prodajalca",
       buyersCosts: "Stroški kupca",
       loading: "Nalagam...",
+      backupDatabase: "Varnostna kopija baze",
+      restoreBackup: "Obnovi varnostno kopijo baze",
+      backingUp: "Kopiram",
+      restoringBackup: "Obnavljam",
+      backupCancelled: "Preklicano, nič ni bilo spremenjeno",
       error: "Napaka",
       statsPerSpecies: "Statistika po drevesnih vrstah",
       averageOfferedPrice: "Povprečna ponujena cena",
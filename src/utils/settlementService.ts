@@ -0,0 +1,56 @@
+import { queryOptions } from "@tanstack/react-query";
+import { invoke } from "@tauri-apps/api/core";
+
+// Amounts come as decimal strings, wrap them in Big to keep them exact
+export type SettlementLine = {
+  wood_piece_id: number;
+  sequence_no: number | null;
+  plate_no: string | null;
+  volume: string;
+  buyer_id: number | null;
+  offered_price: string | null;
+  offered_total_price: string | null;
+  costs_below_350: string;
+  costs_above_350: string;
+};
+
+export type SellerSettlement = {
+  seller_id: number | null;
+  lines: SettlementLine[];
+  total_volume: string;
+  total_price: string;
+  costs_below_350: string;
+  costs_above_350: string;
+  seller_income_gross: string;
+  seller_income_tax_flat: string;
+  seller_income_tax_vat: string;
+  seller_income_gross_after_tax: string;
+  transport_costs: string;
+  transport_vat: string;
+  logging_costs: string;
+  logging_costs_vat: string;
+  logging_costs_non_woods_vat: string;
+  payout: string;
+};
+
+// With `preview` the highest offer counts even when it is below the minimum price
+export const fetchSellerSettlement = async (
+  sellerId: string | number,
+  preview: boolean
+) => {
+  return await invoke<SellerSettlement>("compute_seller_settlement", {
+    sellerId: Number(sellerId),
+    preview,
+  });
+};
+
+// Not cached, the offers change all the time during an auction
+export const sellerSettlementQueryOptions = (
+  sellerId: string | number,
+  preview: boolean
+) =>
+  queryOptions({
+    queryKey: ["sellerSettlement", sellerId, preview],
+    queryFn: () => fetchSellerSettlement(sellerId, preview),
+    staleTime: 0,
+  });
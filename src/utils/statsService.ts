@@ -1,4 +1,5 @@
 import { queryOptions } from "@tanstack/react-query";
+import { invoke } from "@tauri-apps/api/core";
 import { info } from "@tauri-apps/plugin-log";
 import { groupBy, keyBy } from "lodash";
 import { getDatabase } from "./database";
@@ -35,6 +36,23 @@ export interface Statistics {
   buyer_costs: number;
 }
 
+// Totals of the sellers' settlements, amounts are decimal strings
+interface AuctionStatistics {
+  num_wood_pieces: number;
+  num_unsold_wood_pieces: number;
+  total_volume: string;
+  offered_max_price: string | null;
+  sellers_net: string;
+  costs_below_350: string;
+  costs_above_350: string;
+  total_transport_costs: string;
+  total_logging_costs: string;
+  seller_costs: string;
+}
+
+// Sums of amounts that are already in cents, without float noise
+const round = (value: number) => Math.round(value * 100) / 100;
+
 interface ListOptions {
   language?: "en" | "sl";
 }
@@ -73,38 +91,17 @@ const ensureStats = async (opts: ListOptions): Promise<Statistics> => {
   const settingsArray = result as Settings[];
   let settings = settingsArray[0];
 
-  const sellersSql = `
-    SELECT --- just selects some additional stuff on top of one row per seller
-      *,
-      CASE WHEN "sellers"."used_transport" = 1
-        THEN ROUND("sellers"."transport_costs" * "sellers"."total_volume", 2)
-        ELSE 0
-      END AS "total_transport_costs",
-      CASE WHEN ("sellers"."used_logging" = 1 OR "sellers"."used_logging_non_woods" = 1)
-        THEN ROUND("sellers"."logging_costs" * "sellers"."total_volume", 2)
-        ELSE 0
-      END AS "total_logging_costs",
-      ROUND("sellers"."total_volume" * ${settings.licitator_fixed_cost}, 2) AS "costs_below_350",
-      ROUND("costs_above_350_per_seller" * ${settings.licitator_percentage}, 2) AS "costs_above_350"
-    FROM (
-      SELECT  -- this one selects one row per seller, so already summed values
-        *,
-        MAX("offered_price") AS "offered_price",
-        SUM("volume") AS "total_volume",
-        SUM(CASE WHEN "wp_id" IS NOT NULL then 1 else 0 end) as "num_pieces",
-        SUM(CASE WHEN "wpo_id" IS NULL AND "wp_id" IS NOT NULL then 1 else 0 end) as "num_unsold_pieces",
-        SUM("total_price") AS "total_price1",
-        SUM(CASE WHEN "offered_price" > 350 THEN ("total_price" - (350 * "volume")) ELSE 0 END) as "costs_above_350_per_seller"
-      FROM (
-        ${woodPiecesSql}
-      )
-      GROUP BY "seller_id"
-    ) AS "sellers"
-  `;
+  // Fees, transport and logging of the sellers, the same amounts as on their statements
+  let sellerStats: AuctionStatistics;
+  try {
+    sellerStats = await invoke<AuctionStatistics>("compute_auction_statistics");
+  } catch (e) {
+    info(JSON.stringify(e));
+    throw e;
+  }
 
   const buyersSql = `
-    SELECT --- just selects some additional stuff on top of one row per buyer
-      *,
+    SELECT --- one row per buyer
       CASE WHEN "buyers"."used_loading" = 1
         THEN ROUND("buyers"."total_volume" * "buyers"."loading_costs", 2)
         ELSE 0
@@ -112,7 +109,8 @@ const ensureStats = async (opts: ListOptions): Promise<Statistics> => {
       CASE WHEN ("buyers"."used_bundle" = 1)
         THEN ROUND("buyers"."total_volume" * ${settings.bundle_cost}, 2)
         ELSE 0
-      END AS "total_bundle_costs"
+      END AS "total_bundle_costs",
+      "total_price1"
     FROM (
       SELECT  -- this one selects one row per buyer, so already summed values
         *,
@@ -121,88 +119,36 @@ const ensureStats = async (opts: ListOptions): Promise<Statistics> => {
       FROM (
         ${woodPiecesSql}
       )
+      WHERE "buyer_id" IS NOT NULL
       GROUP BY "buyer_id"
     ) AS "buyers"
   `;
 
-  const priceSql = `
-    SELECT --- sum up the total income
-      *,
-      SUM(
-          ROUND("total_transport_costs", 2) 
-          + ROUND("total_logging_costs", 2) 
-          + ROUND("costs_below_350", 2) 
-          + ROUND("costs_above_350", 2)
-          + ROUND("total_loading_costs", 2)
-          + ROUND("total_bundle_costs", 2)
-        ) AS "total_income",
-      SUM(
-          ROUND("total_transport_costs", 2) 
-          + ROUND("total_logging_costs", 2) 
-          + ROUND("costs_below_350", 2) 
-          + ROUND("costs_above_350", 2)
-        ) AS "seller_costs",
-      SUM(
-          + ROUND("total_loading_costs", 2)
-          + ROUND("total_bundle_costs", 2)
-        ) AS "buyer_costs"
-    FROM (
-      SELECT --- this one also does the grouping of all rows
-        ROUND(SUM("total_transport_costs"), 2) AS total_transport_costs,
-        ROUND(SUM("total_logging_costs"), 2) AS "total_logging_costs",
-        ROUND(SUM("costs_below_350"), 2) AS "costs_below_350",
-        ROUND(SUM("costs_above_350"), 2) AS "costs_above_350",
-        ROUND(SUM("total_volume"), 2) AS "total_volume",
-        MAX("offered_price") AS "offered_max_price",
-        SUM("num_pieces") AS "num_wood_pieces",
-        SUM("num_unsold_pieces") AS "num_unsold_wood_pieces",
-        ROUND(SUM("total_loading_costs"), 2) AS "total_loading_costs",
-        ROUND(SUM("total_bundle_costs"), 2) AS "total_bundle_costs",
-        ROUND(SUM("total_bundle_costs"), 2) AS "total_bundle_costs",
-        ROUND(SUM("buyer_net"), 2) AS "buyers_net",
-        ROUND(SUM("seller_net"), 2) AS "sellers_net"
-      FROM ( --- some data from sellers, some data from buyers
-        SELECT 
-          "total_transport_costs", 
-          "total_logging_costs",
-          "costs_below_350",
-          "costs_above_350",
-          "total_volume",
-          "offered_price",
-          "num_pieces",
-          "num_unsold_pieces",
-          NULL AS "total_loading_costs",
-          NULL AS "total_bundle_costs",
-          "total_price1" AS "seller_net",
-          0 AS "buyer_net"
-        FROM (${sellersSql})
-        UNION ALL 
-        SELECT 
-          NULL AS "total_transport_costs", 
-          NULL AS "total_logging_costs",
-          NULL AS "costs_below_350",
-          NULL AS "costs_above_350",
-          NULL AS "total_volume",
-          "offered_price",
-          NULL AS "num_pieces",
-          NULL AS "num_unsold_pieces",
-          "total_loading_costs",
-          "total_bundle_costs",
-          0 AS "seller_net",
-          "total_price1" AS "buyer_net"
-        FROM (${buyersSql})
-      ) 
-    )
+  const buyerStatsSql = `
+    SELECT
+      ROUND(SUM("total_loading_costs"), 2) AS "total_loading_costs",
+      ROUND(SUM("total_bundle_costs"), 2) AS "total_bundle_costs",
+      ROUND(SUM("total_price1"), 2) AS "buyers_net"
+    FROM (${buyersSql})
   `;
 
-  let priceResult: Statistics[] = [];
+  let buyerStats: {
+    total_loading_costs: number | null;
+    total_bundle_costs: number | null;
+    buyers_net: number | null;
+  }[] = [];
   try {
-    priceResult = (await db.select(priceSql, [])) as Statistics[];
+    buyerStats = (await db.select(buyerStatsSql, [])) as typeof buyerStats;
   } catch (e) {
     info(JSON.stringify(e));
     throw e;
   }
 
+  const totalLoadingCosts = buyerStats[0]?.total_loading_costs ?? 0;
+  const totalBundleCosts = buyerStats[0]?.total_bundle_costs ?? 0;
+  const sellerCosts = Number(sellerStats.seller_costs);
+  const buyerCosts = round(totalLoadingCosts + totalBundleCosts);
+
   const topLogStatsSql = `
     SELECT 
       *,
@@ -339,21 +285,21 @@ const ensureStats = async (opts: ListOptions): Promise<Statistics> => {
   const averagePerSpeciesKeyed = keyBy(averagePerSpeciesResult, "id");
 
   const statistics: Statistics = {
-    num_wood_pieces: priceResult[0].num_wood_pieces,
-    num_unsold_wood_pieces: priceResult[0].num_unsold_wood_pieces,
-    offered_max_price: priceResult[0].offered_max_price,
-    total_volume: priceResult[0].total_volume,
-    total_income: priceResult[0].total_income,
-    costs_below_350: priceResult[0].costs_below_350,
-    costs_above_350: priceResult[0].costs_above_350,
-    total_logging_costs: priceResult[0].total_logging_costs,
-    total_transport_costs: priceResult[0].total_transport_costs,
-    total_bundle_costs: priceResult[0].total_bundle_costs,
-    total_loading_costs: priceResult[0].total_loading_costs,
-    sellers_net: priceResult[0].sellers_net,
-    buyers_net: priceResult[0].buyers_net,
-    seller_costs: priceResult[0].seller_costs,
-    buyer_costs: priceResult[0].buyer_costs,
+    num_wood_pieces: sellerStats.num_wood_pieces,
+    num_unsold_wood_pieces: sellerStats.num_unsold_wood_pieces,
+    offered_max_price: Number(sellerStats.offered_max_price ?? 0),
+    total_volume: Number(sellerStats.total_volume),
+    total_income: round(sellerCosts + buyerCosts),
+    costs_below_350: Number(sellerStats.costs_below_350),
+    costs_above_350: Number(sellerStats.costs_above_350),
+    total_logging_costs: Number(sellerStats.total_logging_costs),
+    total_transport_costs: Number(sellerStats.total_transport_costs),
+    total_bundle_costs: totalBundleCosts,
+    total_loading_costs: totalLoadingCosts,
+    sellers_net: Number(sellerStats.sellers_net),
+    buyers_net: buyerStats[0]?.buyers_net ?? 0,
+    seller_costs: sellerCosts,
+    buyer_costs: buyerCosts,
 
     top_logs_by_species: treeSpecies,
     top_logs: topLogs,
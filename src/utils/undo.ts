@@ -1,59 +1,41 @@
 import { useMutation } from "@tanstack/react-query";
-import { getDatabaseForModify } from "./database";
+import { invoke } from "@tauri-apps/api/core";
+
+type UndoStep = {
+  group_id: number | null;
+  label: string | null;
+  statements: string[];
+};
 
 export const useUndo = (onSuccess: () => void) => {
   return useMutation({
     onSuccess: onSuccess,
     mutationFn: async () => {
-      const db = await getDatabaseForModify();
-
-      // Fetch the most recent undo log entry
-      const lastUndo: { seq: number; sql: string }[] = await db.select(
-        "SELECT seq, sql FROM undolog ORDER BY seq DESC LIMIT 1"
-      );
-
-      if (lastUndo.length === 0) {
-        throw new Error("No actions to undo");
-      }
-
-      const { seq, sql } = lastUndo[0];
-
-      // Execute the undo SQL
-      await db.execute(sql);
-
-      // Remove the undo log entry
-      await db.execute("DELETE FROM undolog WHERE seq = $1", [seq]);
-      await db.execute(
-        "DELETE FROM undolog WHERE seq = (SELECT MAX(seq) FROM undolog)"
-      );
-      return { seq, sql };
+      // Undoes the most recent entry of the undo log, or all entries of its group
+      return await invoke<UndoStep>("undo");
     },
   });
 };
 
-export const useRedo = () => {
+export const useRedo = (onSuccess?: () => void) => {
   return useMutation({
+    onSuccess: onSuccess,
     mutationFn: async () => {
-      const db = await getDatabaseForModify();
-
-      // Fetch the most recent undo log entry
-      const lastRedo: { seq: number; sql: string }[] = await db.select(
-        "SELECT seq, sql FROM redolog ORDER BY seq DESC LIMIT 1"
-      );
-
-      if (lastRedo.length === 0) {
-        throw new Error("No actions to undo");
-      }
-
-      const { seq, sql } = lastRedo[0];
-
-      // Execute the undo SQL
-      await db.execute(sql);
-
-      // Remove the undo log entry
-      await db.execute("DELETE FROM redolog WHERE seq = $1", [seq]);
-
-      return { seq, sql };
+      // Redoes the most recent entry of the redo log, or all entries of its group
+      return await invoke<UndoStep>("redo");
     },
   });
 };
+
+// Groups everything `action` changes into a single undo step
+export const withUndoGroup = async <T>(
+  label: string,
+  action: () => Promise<T>
+): Promise<T> => {
+  await invoke("begin_undo_group", { label });
+  try {
+    return await action();
+  } finally {
+    await invoke("end_undo_group");
+  }
+};